@@ -202,3 +202,116 @@ fn bench_fnvhashmap_get(b: &mut Bencher) {
         }
     });
 }
+
+// 32-byte keys sharing a long common prefix, as with paths or namespaced identifiers, so that
+// lookups have to descend past the prefix before the keys can be told apart.
+fn key32(x: u32) -> [u8; 32] {
+    let mut key = *b"/srv/data/shards/objects/0000000";
+    key[28..].copy_from_slice(&x.to_le_bytes());
+    key
+}
+
+#[bench]
+fn bench_trie_insert_32(b: &mut Bencher) {
+    let mut trie = Trie::new();
+
+    let a = 1_234u32;
+    let mut x = 0u32;
+
+    b.iter(move || {
+        for _ in 0..499_980 {
+            x = (x + a) % 499_979;
+            trie.insert(key32(x), ());
+        }
+    });
+}
+
+#[bench]
+fn bench_trie_get_32(b: &mut Bencher) {
+    let mut trie = Trie::new();
+
+    let a = 1_234u32;
+    let mut x = 0u32;
+
+    for _ in 0..499_980 {
+        x = (x + a) % 499_979;
+        trie.insert(key32(x), ());
+    }
+
+    b.iter(move || {
+        for _ in 0..499_979 {
+            x = (x + a) % 499_979;
+            trie.get(&key32(x)[..]).unwrap();
+        }
+    });
+}
+
+#[bench]
+fn bench_btreemap_insert_32(b: &mut Bencher) {
+    let mut trie = BTreeMap::new();
+
+    let a = 1_234u32;
+    let mut x = 0u32;
+
+    b.iter(move || {
+        for _ in 0..499_980 {
+            x = (x + a) % 499_979;
+            trie.insert(key32(x), ());
+        }
+    });
+}
+
+#[bench]
+fn bench_btreemap_get_32(b: &mut Bencher) {
+    let mut trie = BTreeMap::new();
+
+    let a = 1_234u32;
+    let mut x = 0u32;
+
+    for _ in 0..499_980 {
+        x = (x + a) % 499_979;
+        trie.insert(key32(x), ());
+    }
+
+    b.iter(move || {
+        for _ in 0..499_979 {
+            x = (x + a) % 499_979;
+            trie.get(&key32(x)).unwrap();
+        }
+    });
+}
+
+#[bench]
+fn bench_hashmap_insert_32(b: &mut Bencher) {
+    let mut trie = HashMap::new();
+
+    let a = 1_234u32;
+    let mut x = 0u32;
+
+    b.iter(move || {
+        for _ in 0..499_980 {
+            x = (x + a) % 499_979;
+            trie.insert(key32(x), ());
+        }
+    });
+}
+
+#[bench]
+fn bench_hashmap_get_32(b: &mut Bencher) {
+    let mut trie = HashMap::new();
+
+    let a = 1_234u32;
+    let mut x = 0u32;
+
+    for _ in 0..499_980 {
+        x = (x + a) % 499_979;
+        trie.insert(key32(x), ());
+    }
+
+    b.iter(move || {
+        for _ in 0..499_979 {
+            x = (x + a) % 499_979;
+            trie.get(&key32(x)).unwrap();
+        }
+    });
+}
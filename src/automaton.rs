@@ -0,0 +1,249 @@
+//! Searching a trie for the keys accepted by an automaton.
+//!
+//! `Trie::search_automaton` walks the trie and the automaton together, feeding the automaton the
+//! bytes of the keys and abandoning every subtrie in which the automaton can no longer match. Any
+//! byte-at-a-time automaton will do; `Levenshtein` is provided for fuzzy matching, and with the
+//! `regex-automata` feature enabled the DFAs of the `regex-automata` crate can be used directly.
+//!
+//! ```rust
+//! # use qp_trie::Trie;
+//! use qp_trie::automaton::Levenshtein;
+//!
+//! let mut trie = Trie::new();
+//! trie.extend(vec![(&b"kitten"[..], 1), (&b"sitten"[..], 2), (&b"sitting"[..], 3)]);
+//!
+//! let mut close = trie
+//!     .search_automaton(&Levenshtein::new(b"kitten", 1))
+//!     .map(|(_, &v)| v)
+//!     .collect::<Vec<_>>();
+//! close.sort();
+//!
+//! assert_eq!(close, [1, 2]);
+//! ```
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::cmp;
+
+use node::Node;
+use trie::Trie;
+
+/// A deterministic automaton over bytes, with which a trie can be searched.
+pub trait Automaton {
+    /// The state of the automaton after having read some input.
+    type State: Clone;
+
+    /// The state of the automaton before it has read any input.
+    fn start(&self) -> Self::State;
+
+    /// The state of the automaton after reading `byte` in `state`.
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State;
+
+    /// Whether or not the input read so far is accepted.
+    fn is_match(&self, state: &Self::State) -> bool;
+
+    /// Whether or not some continuation of the input read so far could be accepted. Subtries are
+    /// pruned as soon as this returns false, so it should do so as early as possible.
+    fn can_match(&self, state: &Self::State) -> bool;
+}
+
+/// An automaton accepting every key within a given edit distance of a query: insertions,
+/// deletions and substitutions of single bytes each count as one edit.
+#[derive(Clone, Debug)]
+pub struct Levenshtein {
+    query: Vec<u8>,
+    max_distance: usize,
+}
+
+impl Levenshtein {
+    /// Create an automaton accepting keys within `max_distance` edits of `query`.
+    pub fn new(query: &[u8], max_distance: usize) -> Levenshtein {
+        Levenshtein {
+            query: query.to_vec(),
+            max_distance,
+        }
+    }
+}
+
+impl Automaton for Levenshtein {
+    // A row of the edit distance table: the distance from the input read so far to each prefix of
+    // the query.
+    type State = Vec<usize>;
+
+    fn start(&self) -> Vec<usize> {
+        (0..self.query.len() + 1).collect()
+    }
+
+    fn accept(&self, state: &Vec<usize>, byte: u8) -> Vec<usize> {
+        let mut next = Vec::with_capacity(state.len());
+        next.push(state[0] + 1);
+
+        for (i, &q) in self.query.iter().enumerate() {
+            let substitution = state[i] + (q != byte) as usize;
+            let distance = cmp::min(substitution, cmp::min(state[i + 1], next[i]) + 1);
+            next.push(distance);
+        }
+
+        next
+    }
+
+    fn is_match(&self, state: &Vec<usize>) -> bool {
+        state[self.query.len()] <= self.max_distance
+    }
+
+    fn can_match(&self, state: &Vec<usize>) -> bool {
+        state.iter().any(|&distance| distance <= self.max_distance)
+    }
+}
+
+/// An iterator over the entries of a trie whose keys are accepted by an automaton, produced by
+/// `Trie::search_automaton`.
+pub struct AutomatonIter<'a, K: 'a, V: 'a, A: 'a + Automaton> {
+    automaton: &'a A,
+    // Nodes yet to be visited, along with how many leading bytes of their keys have been fed to
+    // the automaton and the state it was left in.
+    stack: Vec<(&'a Node<K, V>, usize, A::State)>,
+}
+
+impl<'a, K: Borrow<[u8]>, V, A: Automaton> AutomatonIter<'a, K, V, A> {
+    // Feed bytes `from..to` of `key` to the automaton, giving up as soon as it cannot match.
+    fn feed(&self, key: &[u8], from: usize, to: usize, state: A::State) -> Option<A::State> {
+        key[from..to].iter().try_fold(state, |state, &byte| {
+            let state = self.automaton.accept(&state, byte);
+
+            if self.automaton.can_match(&state) {
+                Some(state)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl<'a, K: Borrow<[u8]>, V, A: Automaton> Iterator for AutomatonIter<'a, K, V, A> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, fed, state)) = self.stack.pop() {
+            match *node {
+                Node::Leaf(ref leaf) => {
+                    let key = leaf.key_slice();
+
+                    match self.feed(key, fed, key.len(), state) {
+                        Some(ref state) if self.automaton.is_match(state) => {
+                            return Some((&leaf.key, &leaf.val))
+                        }
+                        _ => {}
+                    }
+                }
+                Node::Branch(ref branch) => {
+                    // Every key beneath the branch shares its whole bytes before the choice
+                    // point, so they can be fed to the automaton once through any of them.
+                    let choice = branch.choice();
+                    let byte = choice / 2;
+                    let exemplar = node.get_exemplar(&[]).key_slice();
+                    let state = match self.feed(exemplar, fed, byte, state) {
+                        Some(state) => state,
+                        None => continue,
+                    };
+
+                    let start = self.stack.len();
+                    for child in branch.iter() {
+                        let key = child.get_exemplar(&[]).key_slice();
+
+                        // The automaton reads whole bytes. A branch on the high nybble of a byte
+                        // completes it, since the low nybble came first; one on the low nybble
+                        // leaves the byte to be fed further down.
+                        if choice % 2 == 1 && byte < key.len() {
+                            match self.feed(key, byte, byte + 1, state.clone()) {
+                                Some(state) => self.stack.push((child, byte + 1, state)),
+                                None => continue,
+                            }
+                        } else {
+                            self.stack.push((child, byte, state.clone()));
+                        }
+                    }
+                    self.stack[start..].reverse();
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl<K: Borrow<[u8]>, V> Trie<K, V> {
+    /// Iterate over all entries whose keys are accepted by the given automaton. The trie and the
+    /// automaton are walked together a byte at a time, and subtries are pruned as soon as the
+    /// automaton can no longer match.
+    pub fn search_automaton<'a, A: Automaton>(
+        &'a self,
+        automaton: &'a A,
+    ) -> AutomatonIter<'a, K, V, A> {
+        let stack = match self.root() {
+            Some(root) if automaton.can_match(&automaton.start()) => {
+                vec![(root, 0, automaton.start())]
+            }
+            _ => Vec::new(),
+        };
+
+        AutomatonIter { automaton, stack }
+    }
+}
+
+#[cfg(feature = "regex-automata")]
+mod dfa {
+    use regex_automata::dfa::{dense, sparse, Automaton as Dfa};
+    use regex_automata::util::primitives::StateID;
+    use regex_automata::util::start::Config;
+    use regex_automata::Anchored;
+
+    use super::Automaton;
+
+    // Searches are anchored at the start of the key, and a key is accepted only if the DFA
+    // matches once the end of the key is reached. A DFA which cannot supply an anchored start
+    // state accepts nothing.
+    fn start<D: Dfa>(dfa: &D) -> Option<StateID> {
+        dfa.start_state(&Config::new().anchored(Anchored::Yes)).ok()
+    }
+
+    fn accept<D: Dfa>(dfa: &D, state: &Option<StateID>, byte: u8) -> Option<StateID> {
+        state.map(|id| dfa.next_state(id, byte))
+    }
+
+    fn is_match<D: Dfa>(dfa: &D, state: &Option<StateID>) -> bool {
+        matches!(*state, Some(id) if dfa.is_match_state(dfa.next_eoi_state(id)))
+    }
+
+    fn can_match<D: Dfa>(dfa: &D, state: &Option<StateID>) -> bool {
+        matches!(*state, Some(id) if !dfa.is_dead_state(id) && !dfa.is_quit_state(id))
+    }
+
+    macro_rules! impl_automaton {
+        ($dfa:ty, $repr:ident) => {
+            impl<T: AsRef<[$repr]>> Automaton for $dfa {
+                type State = Option<StateID>;
+
+                fn start(&self) -> Self::State {
+                    start(self)
+                }
+
+                fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+                    accept(self, state, byte)
+                }
+
+                fn is_match(&self, state: &Self::State) -> bool {
+                    is_match(self, state)
+                }
+
+                fn can_match(&self, state: &Self::State) -> bool {
+                    can_match(self, state)
+                }
+            }
+        };
+    }
+
+    impl_automaton!(dense::DFA<T>, u32);
+    impl_automaton!(sparse::DFA<T>, u8);
+}
@@ -0,0 +1,202 @@
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+
+use node::Node;
+use trie::Trie;
+use util::nybble_index;
+
+// The `n`th nybble of `key`, if it is long enough to have one.
+#[inline]
+fn nybble(n: usize, key: &[u8]) -> Option<u8> {
+    match nybble_index(n, key) {
+        0 => None,
+        index => Some(index - 1),
+    }
+}
+
+// The nybble indices of the occupied slots of a branch with the given bitmap, in the same order
+// as its children.
+#[inline]
+fn children(bitmap: u32) -> impl Iterator<Item = u8> {
+    (0..17u8).filter(move |&index| bitmap & (1 << index) != 0)
+}
+
+/// An iterator over the entries of a trie whose keys are within a given Hamming distance of a
+/// target, produced by `Trie::search_hamming`.
+pub struct HammingIter<'a, K: 'a, V: 'a> {
+    target: Vec<u8>,
+    max_bits: u32,
+    // Nodes yet to be visited, along with how many leading nybbles of their keys have been
+    // compared with the target and the number of differing bits found in them.
+    stack: Vec<(&'a Node<K, V>, usize, u32)>,
+}
+
+impl<'a, K: Borrow<[u8]>, V> HammingIter<'a, K, V> {
+    // Add the bits differing between `key` and the target in nybbles `from..to` to `bits`,
+    // returning `None` as soon as the total exceeds the maximum.
+    fn distance(&self, key: &[u8], from: usize, to: usize, mut bits: u32) -> Option<u32> {
+        for n in from..to {
+            let (k, t) = (nybble(n, key)?, nybble(n, &self.target)?);
+            bits += (k ^ t).count_ones();
+
+            if bits > self.max_bits {
+                return None;
+            }
+        }
+
+        Some(bits)
+    }
+}
+
+impl<'a, K: Borrow<[u8]>, V> Iterator for HammingIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = self.target.len() * 2;
+
+        while let Some((node, checked, bits)) = self.stack.pop() {
+            match *node {
+                Node::Leaf(ref leaf) => {
+                    let key = leaf.key_slice();
+
+                    if key.len() * 2 == len && self.distance(key, checked, len, bits).is_some() {
+                        return Some((&leaf.key, &leaf.val));
+                    }
+                }
+                Node::Branch(ref branch) => {
+                    // Every key beneath the branch shares the nybbles up to its choice point, so
+                    // they can be compared once through any of them.
+                    let choice = branch.choice();
+                    let exemplar = node.get_exemplar(&[]).key_slice();
+                    let bits = match self.distance(exemplar, checked, choice.min(len), bits) {
+                        Some(bits) => bits,
+                        None => continue,
+                    };
+                    let target = nybble(choice, &self.target);
+
+                    let start = self.stack.len();
+                    for (index, child) in children(branch.bitmap()).zip(branch.iter()) {
+                        let bits = match (index.checked_sub(1), target) {
+                            (Some(n), Some(t)) => bits + (n ^ t).count_ones(),
+                            (None, None) => bits,
+                            _ => continue,
+                        };
+
+                        if bits <= self.max_bits {
+                            self.stack.push((child, choice + 1, bits));
+                        }
+                    }
+                    self.stack[start..].reverse();
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// An iterator over the entries of a trie whose keys agree with a value on the bits set in a
+/// mask, produced by `Trie::search_mask`.
+pub struct MaskIter<'a, K: 'a, V: 'a> {
+    value: Vec<u8>,
+    mask: Vec<u8>,
+    // Nodes yet to be visited, along with how many leading nybbles of their keys have been
+    // checked against the mask.
+    stack: Vec<(&'a Node<K, V>, usize)>,
+}
+
+impl<'a, K: Borrow<[u8]>, V> MaskIter<'a, K, V> {
+    // Whether or not nybbles `from..to` of `key` agree with the value under the mask. Nybbles past
+    // the end of the mask are unconstrained.
+    fn matches(&self, key: &[u8], from: usize, to: usize) -> bool {
+        (from..to.min(self.mask.len() * 2)).all(|n| {
+            let (value, mask) = (nybble(n, &self.value), nybble(n, &self.mask));
+            match (nybble(n, key), value, mask) {
+                (Some(k), Some(v), Some(m)) => k & m == v & m,
+                _ => false,
+            }
+        })
+    }
+}
+
+impl<'a, K: Borrow<[u8]>, V> Iterator for MaskIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = self.mask.len() * 2;
+
+        while let Some((node, checked)) = self.stack.pop() {
+            match *node {
+                Node::Leaf(ref leaf) => {
+                    if self.matches(leaf.key_slice(), checked, len) {
+                        return Some((&leaf.key, &leaf.val));
+                    }
+                }
+                Node::Branch(ref branch) => {
+                    let choice = branch.choice();
+
+                    if !self.matches(node.get_exemplar(&[]).key_slice(), checked, choice) {
+                        continue;
+                    }
+
+                    let (value, mask) = (nybble(choice, &self.value), nybble(choice, &self.mask));
+                    let start = self.stack.len();
+                    for (index, child) in children(branch.bitmap()).zip(branch.iter()) {
+                        let matches = match (index.checked_sub(1), value, mask) {
+                            (Some(k), Some(v), Some(m)) => k & m == v & m,
+                            (_, None, None) => true,
+                            _ => false,
+                        };
+
+                        if matches {
+                            self.stack.push((child, choice + 1));
+                        }
+                    }
+                    self.stack[start..].reverse();
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl<K: Borrow<[u8]>, V> Trie<K, V> {
+    /// Iterate over all entries whose keys are the same length as `key` and differ from it in at
+    /// most `max_bits` bits. Subtries are pruned as soon as the bits differing in the nybbles
+    /// they share exceed the maximum.
+    pub fn search_hamming<Q>(&self, key: &Q, max_bits: u32) -> HammingIter<'_, K, V>
+    where
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        HammingIter {
+            target: key.borrow().to_vec(),
+            max_bits,
+            stack: self.root().map(|root| (root, 0, 0)).into_iter().collect(),
+        }
+    }
+
+    /// Iterate over all entries whose keys agree with `value` on every bit set in `mask`, that
+    /// is, where `key & mask == value & mask`. Keys shorter than the mask never match, and bytes
+    /// past the end of the mask are unconstrained. Subtries are pruned as soon as a nybble they
+    /// share disagrees with the value under the mask.
+    ///
+    /// Panics if `value` and `mask` are of different lengths.
+    pub fn search_mask<Q>(&self, value: &Q, mask: &Q) -> MaskIter<'_, K, V>
+    where
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        let (value, mask) = (value.borrow(), mask.borrow());
+        assert_eq!(
+            value.len(),
+            mask.len(),
+            "search_mask: value and mask must be the same length"
+        );
+
+        MaskIter {
+            value: value.to_vec(),
+            mask: mask.to_vec(),
+            stack: self.root().map(|root| (root, 0)).into_iter().collect(),
+        }
+    }
+}
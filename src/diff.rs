@@ -0,0 +1,226 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::ptr;
+
+use node::{Branch, Node};
+use trie::Trie;
+use util::{nybble_index, nybble_mismatch};
+
+/// A difference between two tries, as produced by `Trie::diff`. When the diff borrows the tries,
+/// `K` and `V` are references.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffItem<K, V> {
+    /// The key is only in the other trie, with the given value.
+    Added(K, V),
+    /// The key is only in this trie, with the given value.
+    Removed(K, V),
+    /// The key is in both tries, with the given old and new values, which differ.
+    Changed(K, V, V),
+}
+
+impl<'a, K: Clone, V: Clone> DiffItem<&'a K, &'a V> {
+    /// Clone the key and values of a borrowed diff item, so that it can outlive the tries.
+    pub fn cloned(self) -> DiffItem<K, V> {
+        match self {
+            DiffItem::Added(key, val) => DiffItem::Added(key.clone(), val.clone()),
+            DiffItem::Removed(key, val) => DiffItem::Removed(key.clone(), val.clone()),
+            DiffItem::Changed(key, old, new) => {
+                DiffItem::Changed(key.clone(), old.clone(), new.clone())
+            }
+        }
+    }
+}
+
+// A pair of corresponding subtries, from this trie and from the other trie respectively.
+type NodePair<'a, K, V> = (Option<&'a Node<K, V>>, Option<&'a Node<K, V>>);
+
+/// An iterator over the differences between two tries, produced by `Trie::diff` and
+/// `Trie::diff_by`.
+pub struct Diff<'a, K: 'a, V: 'a, F> {
+    stack: Vec<NodePair<'a, K, V>>,
+    eq: F,
+}
+
+impl<'a, K: Borrow<[u8]>, V, F> Diff<'a, K, V, F> {
+    pub(crate) fn new(
+        left: Option<&'a Node<K, V>>,
+        right: Option<&'a Node<K, V>>,
+        eq: F,
+    ) -> Diff<'a, K, V, F> {
+        Diff {
+            stack: vec![(left, right)],
+            eq,
+        }
+    }
+
+    // Queue up the children of `branch` paired with nothing, except for the child at `index`,
+    // which is paired with `other`. `flip` is set when `branch` comes from the right-hand trie.
+    fn push_children(
+        &mut self,
+        branch: &'a Branch<K, V>,
+        other: Option<(u8, &'a Node<K, V>)>,
+        flip: bool,
+    ) {
+        let bitmap = branch.bitmap();
+        let mut children = branch.iter().rev();
+
+        for index in (0..17u8).rev() {
+            let ours = if bitmap & (1 << index) != 0 {
+                children.next()
+            } else {
+                None
+            };
+            let theirs = match other {
+                Some((other_index, node)) if other_index == index => Some(node),
+                _ => None,
+            };
+
+            if ours.is_some() || theirs.is_some() {
+                if flip {
+                    self.stack.push((theirs, ours));
+                } else {
+                    self.stack.push((ours, theirs));
+                }
+            }
+        }
+    }
+
+    // Queue up the children of two branches with the same choice point and prefix, pairwise.
+    fn push_zipped(&mut self, left: &'a Branch<K, V>, right: &'a Branch<K, V>) {
+        let (left_bitmap, right_bitmap) = (left.bitmap(), right.bitmap());
+        let (mut lefts, mut rights) = (left.iter().rev(), right.iter().rev());
+
+        for index in (0..17u8).rev() {
+            let l = if left_bitmap & (1 << index) != 0 {
+                lefts.next()
+            } else {
+                None
+            };
+            let r = if right_bitmap & (1 << index) != 0 {
+                rights.next()
+            } else {
+                None
+            };
+
+            if l.is_some() || r.is_some() {
+                self.stack.push((l, r));
+            }
+        }
+    }
+}
+
+impl<'a, K: Borrow<[u8]>, V, F> Iterator for Diff<'a, K, V, F>
+where
+    F: FnMut(&V, &V) -> bool,
+{
+    type Item = DiffItem<&'a K, &'a V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(pair) = self.stack.pop() {
+            match pair {
+                (None, None) => {}
+                // The same subtrie on both sides, as when diffing a trie against itself, has no
+                // differences, so there is no need to walk it.
+                (Some(l), Some(r)) if ptr::eq(l, r) => {}
+
+                (Some(Node::Leaf(leaf)), None) => {
+                    return Some(DiffItem::Removed(&leaf.key, &leaf.val))
+                }
+                (None, Some(Node::Leaf(leaf))) => {
+                    return Some(DiffItem::Added(&leaf.key, &leaf.val))
+                }
+                (Some(Node::Branch(branch)), None) => self.push_children(branch, None, false),
+                (None, Some(Node::Branch(branch))) => self.push_children(branch, None, true),
+
+                (Some(Node::Leaf(l)), Some(Node::Leaf(r))) => {
+                    if l.key_slice() == r.key_slice() {
+                        if !(self.eq)(&l.val, &r.val) {
+                            return Some(DiffItem::Changed(&l.key, &l.val, &r.val));
+                        }
+                    } else {
+                        self.stack.push((None, Some(pair.1.unwrap())));
+                        self.stack.push((Some(pair.0.unwrap()), None));
+                    }
+                }
+
+                (Some(left), Some(right)) => {
+                    // At least one of the two is a branch. The two subtries overlap only if the
+                    // keys in them agree up to the lower of their choice points, a leaf's choice
+                    // point being past the end of its key.
+                    let choice = |node: &Node<K, V>| match *node {
+                        Node::Leaf(..) => usize::MAX,
+                        Node::Branch(ref branch) => branch.choice(),
+                    };
+                    let (lc, rc) = (choice(left), choice(right));
+                    let (le, re) = (
+                        left.get_exemplar(&[]).key_slice(),
+                        right.get_exemplar(&[]).key_slice(),
+                    );
+                    let agree = !matches!(nybble_mismatch(le, re), Some(m) if m < lc.min(rc));
+
+                    match (left, right) {
+                        _ if !agree => {
+                            self.stack.push((None, Some(right)));
+                            self.stack.push((Some(left), None));
+                        }
+
+                        (Node::Branch(l), Node::Branch(r)) if lc == rc => self.push_zipped(l, r),
+                        (Node::Branch(l), _) if lc < rc => {
+                            self.push_children(l, Some((nybble_index(lc, re), right)), false)
+                        }
+                        (_, Node::Branch(r)) => {
+                            self.push_children(r, Some((nybble_index(rc, le), left)), true)
+                        }
+
+                        // unsafe: the pair of leaves was handled above, and the remaining
+                        // cases are covered by the arms above.
+                        _ => unsafe { debug_unreachable!() },
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl<K: Borrow<[u8]>, V> Trie<K, V> {
+    /// Iterate over the differences between this trie and another: the keys which were added,
+    /// removed, or changed in value going from this trie to the other. Both tries are walked
+    /// together, so subtries which have the same shape in both are visited only once, and a
+    /// subtrie which is shared by both is not visited at all.
+    pub fn diff<'a>(&'a self, other: &'a Trie<K, V>) -> Diff<'a, K, V, fn(&V, &V) -> bool>
+    where
+        V: PartialEq,
+    {
+        self.diff_by(other, PartialEq::eq)
+    }
+
+    /// As `Trie::diff`, but deciding whether a key's value has changed with the given function,
+    /// which should return true if the two values are to be considered equal.
+    pub fn diff_by<'a, F>(&'a self, other: &'a Trie<K, V>, eq: F) -> Diff<'a, K, V, F>
+    where
+        F: FnMut(&V, &V) -> bool,
+    {
+        Diff::new(self.root(), other.root(), eq)
+    }
+
+    /// Replay a diff against this trie, inserting added and changed entries and removing removed
+    /// ones. The old values in the diff are not checked against the trie's contents.
+    pub fn apply_diff<I>(&mut self, diff: I)
+    where
+        I: IntoIterator<Item = DiffItem<K, V>>,
+    {
+        for item in diff {
+            match item {
+                DiffItem::Added(key, val) | DiffItem::Changed(key, _, val) => {
+                    self.insert(key, val);
+                }
+                DiffItem::Removed(key, _) => {
+                    self.remove(key.borrow());
+                }
+            }
+        }
+    }
+}
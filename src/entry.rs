@@ -1,85 +1,153 @@
+use alloc::borrow::ToOwned;
 use core::borrow::Borrow;
+use core::fmt;
 use core::marker::PhantomData;
 use core::mem;
+use core::ptr;
 
 use unreachable::UncheckedOptionExt;
 
+use measure::Measure;
 use node::{Leaf, Node};
 use util::nybble_get_mismatch;
 
-pub fn make_entry<'a, K: 'a + Borrow<[u8]>, V: 'a>(
+pub fn make_entry<'a, K: 'a + Borrow<[u8]>, V: 'a, M: Measure<K, V>>(
     key: K,
-    root: &'a mut Option<Node<K, V>>,
+    root: &'a mut Option<Node<K, V, M>>,
     count: &'a mut usize,
-) -> Entry<'a, K, V> {
-    match *root {
-        Some(..) => Entry::nonempty(key, root, count),
-        None => Entry::empty(key, root, count),
+) -> Entry<'a, K, V, M> {
+    match locate(key.borrow(), root, count) {
+        Ok(occupied) => Entry::Occupied(occupied),
+        Err(graft) => Entry::Vacant(VacantEntry { key, graft }),
     }
 }
 
-/// An entry - occupied or vacant - in the trie, corresponding to some given key.
-#[derive(Debug)]
-pub enum Entry<'a, K: 'a, V: 'a> {
-    Vacant(VacantEntry<'a, K, V>),
-    Occupied(OccupiedEntry<'a, K, V>),
+pub fn make_entry_ref<'a, 'b, K: 'a + Borrow<[u8]>, Q, V: 'a, M: Measure<K, V>>(
+    key: &'b Q,
+    root: &'a mut Option<Node<K, V, M>>,
+    count: &'a mut usize,
+) -> EntryRef<'a, 'b, K, Q, V, M>
+where
+    Q: 'b + ?Sized + Borrow<[u8]>,
+{
+    match locate(key.borrow(), root, count) {
+        Ok(occupied) => EntryRef::Occupied(occupied),
+        Err(graft) => EntryRef::Vacant(VacantEntryRef { key, graft }),
+    }
 }
 
-impl<'a, K: 'a + Borrow<[u8]>, V: 'a> Entry<'a, K, V> {
-    fn nonempty(key: K, root: &'a mut Option<Node<K, V>>, count: &'a mut usize) -> Entry<'a, K, V> {
-        let (exemplar_ptr, mismatch) = {
-            let node = unsafe { root.as_mut().unchecked_unwrap() };
-            let exemplar = node.get_exemplar_mut(key.borrow());
-            let mismatch = nybble_get_mismatch(exemplar.key_slice(), key.borrow());
+// Find the leaf holding the given key, or, if there is none, the place where a leaf for the key
+// would be grafted into the trie.
+fn locate<'a, K: 'a + Borrow<[u8]>, V: 'a, M: Measure<K, V>>(
+    key: &[u8],
+    root: &'a mut Option<Node<K, V, M>>,
+    count: &'a mut usize,
+) -> Result<OccupiedEntry<'a, K, V, M>, Graft<'a, K, V, M>> {
+    let (exemplar_ptr, mismatch) = match *root {
+        Some(ref mut node) => {
+            let exemplar = node.get_exemplar_mut(key);
+            let mismatch = nybble_get_mismatch(exemplar.key_slice(), key);
             (exemplar as *mut Leaf<K, V>, mismatch)
-        };
+        }
+        None => {
+            return Err(Graft {
+                root,
+                point: None,
+                count,
+            })
+        }
+    };
 
-        match mismatch {
-            None => Entry::occupied(exemplar_ptr, root as *mut Option<Node<K, V>>, count),
+    match mismatch {
+        None => Ok(OccupiedEntry {
+            _dummy: PhantomData,
+            leaf: exemplar_ptr,
+            root: root as *mut Option<Node<K, V, M>>,
+            count,
+        }),
 
-            Some((b, i)) => {
-                let node = unsafe { root.as_mut().unchecked_unwrap() };
+        Some((b, i)) => Err(Graft {
+            root,
+            point: Some((i, b)),
+            count,
+        }),
+    }
+}
 
-                Entry::vacant_nonempty(key, i, b, node, count)
+// Where a vacant entry's key is to be inserted: either as the only node of an empty trie, or at
+// the given graft point and nybble beneath the root of a nonempty one.
+#[derive(Debug)]
+pub(crate) struct Graft<'a, K: 'a, V: 'a, M: 'a + Measure<K, V>> {
+    pub(crate) root: &'a mut Option<Node<K, V, M>>,
+    pub(crate) point: Option<(usize, u8)>,
+    pub(crate) count: &'a mut usize,
+}
+
+// A newly grafted leaf node: the trie's root, the branch node holding the leaf and the leaf's
+// index in it (if the leaf is not the root), and the leaf node itself.
+pub(crate) struct Grafted<'a, K: 'a, V: 'a, M: 'a + Measure<K, V>> {
+    pub(crate) root: *mut Option<Node<K, V, M>>,
+    pub(crate) parent: Option<(*mut Node<K, V, M>, u8)>,
+    pub(crate) node: *mut Node<K, V, M>,
+    pub(crate) count: &'a mut usize,
+}
+
+impl<'a, K: 'a + Borrow<[u8]>, V: 'a, M: Measure<K, V>> Graft<'a, K, V, M> {
+    pub(crate) fn insert_node(self, key: K, val: V) -> Grafted<'a, K, V, M> {
+        *self.count += 1;
+
+        let root = self.root as *mut Option<Node<K, V, M>>;
+        let (parent, node) = match self.point {
+            None => {
+                // unsafe: `root` came from a live mutable borrow, which we have given up.
+                let root = unsafe { &mut *root };
+                debug_assert!(root.is_none());
+
+                *root = Some(Node::Leaf(Leaf::new(key, val)));
+                let node = unsafe { root.as_mut().unchecked_unwrap() };
+                (None, node as *mut Node<K, V, M>)
+            }
+            Some((graft, graft_nybble)) => {
+                // unsafe: same rationale; the trie is nonempty if a graft point was found.
+                let node = unsafe { (*root).as_mut().unchecked_unwrap() };
+                let (parent, index) = node.insert_with_graft_point(graft, graft_nybble, key, val);
+                let parent = parent as *mut Node<K, V, M>;
+
+                // unsafe: the leaf has just been inserted into `parent` at `index`.
+                let node = unsafe { (*parent).unwrap_branch_mut() }.entry_mut(index);
+                (Some((parent, index)), node as *mut Node<K, V, M>)
             }
+        };
+
+        Grafted {
+            root,
+            parent,
+            node,
+            count: self.count,
         }
     }
 
-    fn occupied(
-        leaf: *mut Leaf<K, V>,
-        root: *mut Option<Node<K, V>>,
-        count: &'a mut usize,
-    ) -> Entry<'a, K, V> {
-        Entry::Occupied(OccupiedEntry {
+    fn insert(self, key: K, val: V) -> OccupiedEntry<'a, K, V, M> {
+        let grafted = self.insert_node(key, val);
+
+        OccupiedEntry {
             _dummy: PhantomData,
-            leaf,
-            root,
-            count,
-        })
-    }
-
-    fn vacant_nonempty(
-        key: K,
-        graft: usize,
-        graft_nybble: u8,
-        node: &'a mut Node<K, V>,
-        count: &'a mut usize,
-    ) -> Entry<'a, K, V> {
-        Entry::Vacant(VacantEntry {
-            key,
-            inner: VacantEntryInner::Internal(graft, graft_nybble, node),
-            count,
-        })
+            // unsafe: the grafted node is a leaf.
+            leaf: unsafe { (*grafted.node).unwrap_leaf_mut() },
+            root: grafted.root,
+            count: grafted.count,
+        }
     }
+}
 
-    fn empty(key: K, root: &'a mut Option<Node<K, V>>, count: &'a mut usize) -> Entry<'a, K, V> {
-        Entry::Vacant(VacantEntry {
-            key,
-            inner: VacantEntryInner::Root(root),
-            count,
-        })
-    }
+/// An entry - occupied or vacant - in the trie, corresponding to some given key.
+#[derive(Debug)]
+pub enum Entry<'a, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
+    Vacant(VacantEntry<'a, K, V, M>),
+    Occupied(OccupiedEntry<'a, K, V, M>),
+}
 
+impl<'a, K: 'a + Borrow<[u8]>, V: 'a, M: Measure<K, V>> Entry<'a, K, V, M> {
     /// Get a mutable reference to a value already in the trie, if it exists - otherwise, insert a
     /// given default value, and return a mutable reference to its new location in the trie.
     pub fn or_insert(self, default: V) -> &'a mut V {
@@ -99,6 +167,40 @@ impl<'a, K: 'a + Borrow<[u8]>, V: 'a> Entry<'a, K, V> {
         }
     }
 
+    /// Get a mutable reference to a value already in the trie, if it exists - otherwise, call the
+    /// provided closure with the entry's key to construct a new value, insert it into the trie, and
+    /// then return a mutable reference to it.
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Vacant(vacant) => {
+                let val = default(vacant.key());
+                vacant.insert(val)
+            }
+            Entry::Occupied(occupied) => occupied.into_mut(),
+        }
+    }
+
+    /// Get a mutable reference to a value already in the trie, if it exists - otherwise, insert
+    /// the default value, and return a mutable reference to its new location in the trie.
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Modify the value already in the trie, if it exists, with the provided closure, and return
+    /// the entry for further use.
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Vacant(vacant) => Entry::Vacant(vacant),
+            Entry::Occupied(mut occupied) => {
+                f(occupied.get_mut());
+                Entry::Occupied(occupied)
+            }
+        }
+    }
+
     /// Get a reference to the key associated with this entry.
     pub fn key(&self) -> &K {
         match self {
@@ -110,19 +212,12 @@ impl<'a, K: 'a + Borrow<[u8]>, V: 'a> Entry<'a, K, V> {
 
 /// A vacant entry in the trie.
 #[derive(Debug)]
-pub struct VacantEntry<'a, K: 'a, V: 'a> {
+pub struct VacantEntry<'a, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
     key: K,
-    inner: VacantEntryInner<'a, K, V>,
-    count: &'a mut usize,
-}
-
-#[derive(Debug)]
-enum VacantEntryInner<'a, K: 'a, V: 'a> {
-    Root(&'a mut Option<Node<K, V>>),
-    Internal(usize, u8, &'a mut Node<K, V>),
+    graft: Graft<'a, K, V, M>,
 }
 
-impl<'a, K: 'a + Borrow<[u8]>, V: 'a> VacantEntry<'a, K, V> {
+impl<'a, K: 'a + Borrow<[u8]>, V: 'a, M: Measure<K, V>> VacantEntry<'a, K, V, M> {
     /// Get a reference to the key associated with this vacant entry.
     pub fn key(&self) -> &K {
         &self.key
@@ -136,53 +231,174 @@ impl<'a, K: 'a + Borrow<[u8]>, V: 'a> VacantEntry<'a, K, V> {
     /// Insert a value into the vacant entry, returning a mutable reference to the newly inserted
     /// value.
     pub fn insert(self, val: V) -> &'a mut V {
-        *self.count += 1;
-        match self.inner {
-            VacantEntryInner::Root(root) => {
-                debug_assert!(root.is_none());
+        self.insert_entry(val).into_mut()
+    }
 
-                *root = Some(Node::Leaf(Leaf::new(self.key, val)));
-                let root_mut_opt = root.as_mut();
-                let leaf_mut = unsafe { root_mut_opt.unchecked_unwrap().unwrap_leaf_mut() };
-                &mut leaf_mut.val
+    /// Insert a value into the vacant entry, returning the now-occupied entry.
+    pub fn insert_entry(self, val: V) -> OccupiedEntry<'a, K, V, M> {
+        self.graft.insert(self.key, val)
+    }
+}
+
+/// An entry - occupied or vacant - in the trie, corresponding to some given borrowed key. The key
+/// is only converted to an owned key if a value is inserted into a vacant entry.
+#[derive(Debug)]
+pub enum EntryRef<'a, 'b, K: 'a, Q: 'b + ?Sized, V: 'a, M: 'a + Measure<K, V> = ()> {
+    Vacant(VacantEntryRef<'a, 'b, K, Q, V, M>),
+    Occupied(OccupiedEntry<'a, K, V, M>),
+}
+
+impl<'a, 'b, K, Q, V, M> EntryRef<'a, 'b, K, Q, V, M>
+where
+    K: 'a + Borrow<[u8]>,
+    Q: 'b + ?Sized + Borrow<[u8]> + ToOwned<Owned = K>,
+    V: 'a,
+    M: Measure<K, V>,
+{
+    /// Get a mutable reference to a value already in the trie, if it exists - otherwise, insert a
+    /// given default value, and return a mutable reference to its new location in the trie.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            EntryRef::Vacant(vacant) => vacant.insert(default),
+            EntryRef::Occupied(occupied) => occupied.into_mut(),
+        }
+    }
+
+    /// Get a mutable reference to a value already in the trie, if it exists - otherwise, call the
+    /// provided closure to construct a new value, insert it into the trie, and then return a
+    /// mutable reference to it.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            EntryRef::Vacant(vacant) => vacant.insert(default()),
+            EntryRef::Occupied(occupied) => occupied.into_mut(),
+        }
+    }
+
+    /// Get a mutable reference to a value already in the trie, if it exists - otherwise, call the
+    /// provided closure with the borrowed key to construct a new value, insert it into the trie,
+    /// and then return a mutable reference to it.
+    pub fn or_insert_with_key<F: FnOnce(&Q) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            EntryRef::Vacant(vacant) => {
+                let val = default(vacant.key());
+                vacant.insert(val)
             }
-            VacantEntryInner::Internal(graft, graft_nybble, node) => {
-                node.insert_with_graft_point(graft, graft_nybble, self.key, val)
+            EntryRef::Occupied(occupied) => occupied.into_mut(),
+        }
+    }
+
+    /// Get a mutable reference to a value already in the trie, if it exists - otherwise, insert
+    /// the default value, and return a mutable reference to its new location in the trie.
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Modify the value already in the trie, if it exists, with the provided closure, and return
+    /// the entry for further use.
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            EntryRef::Vacant(vacant) => EntryRef::Vacant(vacant),
+            EntryRef::Occupied(mut occupied) => {
+                f(occupied.get_mut());
+                EntryRef::Occupied(occupied)
             }
         }
     }
 }
 
+/// A vacant entry in the trie, corresponding to some borrowed key.
+#[derive(Debug)]
+pub struct VacantEntryRef<'a, 'b, K: 'a, Q: 'b + ?Sized, V: 'a, M: 'a + Measure<K, V> = ()> {
+    key: &'b Q,
+    graft: Graft<'a, K, V, M>,
+}
+
+impl<'a, 'b, K, Q, V, M> VacantEntryRef<'a, 'b, K, Q, V, M>
+where
+    K: 'a + Borrow<[u8]>,
+    Q: 'b + ?Sized + Borrow<[u8]> + ToOwned<Owned = K>,
+    V: 'a,
+    M: Measure<K, V>,
+{
+    /// Get a reference to the borrowed key associated with this vacant entry.
+    pub fn key(&self) -> &'b Q {
+        self.key
+    }
+
+    /// Insert a value into the vacant entry, converting the key into an owned key, and return a
+    /// mutable reference to the newly inserted value.
+    pub fn insert(self, val: V) -> &'a mut V {
+        self.insert_entry(val).into_mut()
+    }
+
+    /// Insert a value into the vacant entry, converting the key into an owned key, and return the
+    /// now-occupied entry.
+    pub fn insert_entry(self, val: V) -> OccupiedEntry<'a, K, V, M> {
+        self.graft.insert(self.key.to_owned(), val)
+    }
+}
+
 /// An occupied entry in the trie.
 #[derive(Debug)]
-pub struct OccupiedEntry<'a, K: 'a, V: 'a> {
+pub struct OccupiedEntry<'a, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
     _dummy: PhantomData<&'a mut ()>,
 
     leaf: *mut Leaf<K, V>,
-    root: *mut Option<Node<K, V>>,
+    root: *mut Option<Node<K, V, M>>,
     count: &'a mut usize,
 }
 
-impl<'a, K: 'a + Borrow<[u8]>, V: 'a> OccupiedEntry<'a, K, V> {
+impl<'a, K: 'a + Borrow<[u8]>, V: 'a, M: Measure<K, V>> OccupiedEntry<'a, K, V, M> {
     /// Get a reference to the key of the entry.
     pub fn key(&self) -> &K {
         let leaf = unsafe { &*self.leaf };
         &leaf.key
     }
 
+    /// Replace the key of the entry with another which has the same bytes, returning the old key.
+    /// This is useful when keys carry more than their bytes, such as an interned or shared
+    /// allocation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the new key's bytes differ from the old key's.
+    pub fn replace_key(&mut self, key: K) -> K {
+        let leaf = unsafe { &mut *self.leaf };
+        assert!(
+            leaf.key_slice() == key.borrow(),
+            "replacement key differs from the entry's key"
+        );
+        mem::replace(&mut leaf.key, key)
+    }
+
+    /// Replace both the key and the value of the entry, returning the old key and value. The new
+    /// key must have the same bytes as the old one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the new key's bytes differ from the old key's.
+    pub fn replace_entry(&mut self, key: K, val: V) -> (K, V) {
+        let key = self.replace_key(key);
+        (key, self.insert(val))
+    }
+
     /// Remove the entry from the trie, returning the stored key and value.
     pub fn remove_entry(self) -> (K, V) {
         let root = unsafe { &mut *self.root };
         *self.count -= 1;
         match *root {
-            Some(Node::Leaf(_)) => {
+            Some(Node::Leaf(ref leaf)) if ptr::eq(leaf, self.leaf) => {
                 let leaf_opt = root.take();
                 let leaf = unsafe { leaf_opt.unchecked_unwrap().unwrap_leaf() };
 
-                debug_assert!(leaf.key_slice() == self.key().borrow());
                 (leaf.key, leaf.val)
             }
 
+            Some(Node::Leaf(_)) => unsafe { debug_unreachable!() },
+
             Some(Node::Branch(_)) => {
                 let branch_opt = root.as_mut();
                 let branch = unsafe { branch_opt.unchecked_unwrap() };
@@ -228,3 +444,27 @@ impl<'a, K: 'a + Borrow<[u8]>, V: 'a> OccupiedEntry<'a, K, V> {
         self.remove_entry().1
     }
 }
+
+/// The error returned by `Trie::try_insert` when the key is already present. It holds the occupied
+/// entry for the key along with the value which was not inserted.
+#[derive(Debug)]
+pub struct OccupiedError<'a, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
+    /// The entry for the key which was already present.
+    pub entry: OccupiedEntry<'a, K, V, M>,
+    /// The value which was not inserted.
+    pub value: V,
+}
+
+impl<'a, K: 'a + Borrow<[u8]> + fmt::Debug, V: 'a + fmt::Debug, M: Measure<K, V>> fmt::Display
+    for OccupiedError<'a, K, V, M>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "failed to insert {:?}, key {:?} already exists with value {:?}",
+            self.value,
+            self.entry.key(),
+            self.entry.get(),
+        )
+    }
+}
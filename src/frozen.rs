@@ -0,0 +1,574 @@
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::convert::TryInto;
+use core::fmt;
+
+use measure::Measure;
+use node::Node;
+use util::{nybble_index, nybble_mismatch};
+
+// The layout of a frozen image is as follows. All integers are little-endian `u32`s, and all
+// offsets are relative to the start of the image, so the image may be placed anywhere in memory.
+//
+// - Header: magic, version, entry count, root reference, offset of the leaf table, offset of the
+//   value section.
+// - Branch records, in preorder: choice, bitmap, first leaf index, end leaf index, then one
+//   reference per occupied child slot, in nybble order.
+// - Leaf table: one offset per leaf, pointing at its leaf record. Leaves are numbered in iteration
+//   order, so every branch covers a contiguous range of the leaf table.
+// - Leaf records: key length, value offset, value length, key bytes. The value offset is relative
+//   to the start of the value section.
+// - Value section: the bytes of each value, starting on a multiple of `VALUE_ALIGN`, so that
+//   values of fixed-size types may be read in place from an aligned image.
+//
+// A node reference with `LEAF_FLAG` set is the index of a leaf in the leaf table; otherwise, it is
+// the offset of a branch record.
+
+const MAGIC: &[u8; 4] = b"qptf";
+const VERSION: u32 = 2;
+
+const HEADER_LEN: usize = 24;
+const BRANCH_LEN: usize = 16;
+const LEAF_LEN: usize = 12;
+
+const VALUE_ALIGN: usize = 8;
+
+const LEAF_FLAG: u32 = 1 << 31;
+const EMPTY: u32 = u32::MAX;
+
+/// An error encountered while opening a frozen trie image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrozenError {
+    /// The image is too short to contain the data its header describes.
+    Truncated,
+    /// The image does not begin with the frozen trie magic number.
+    BadMagic,
+    /// The image was written by an incompatible version of this crate.
+    UnsupportedVersion(u32),
+    /// The nodes or leaves of the image are out of bounds or do not form a trie.
+    Corrupt,
+}
+
+impl fmt::Display for FrozenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FrozenError::Truncated => f.write_str("truncated frozen trie image"),
+            FrozenError::BadMagic => f.write_str("not a frozen trie image"),
+            FrozenError::UnsupportedVersion(v) => {
+                write!(f, "unsupported frozen trie image version {}", v)
+            }
+            FrozenError::Corrupt => f.write_str("corrupt frozen trie image"),
+        }
+    }
+}
+
+#[inline]
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+fn to_u32(n: usize) -> u32 {
+    assert!(n < LEAF_FLAG as usize, "trie is too large to freeze");
+    n as u32
+}
+
+#[inline]
+fn push_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.extend_from_slice(&n.to_le_bytes());
+}
+
+#[inline]
+fn align(n: usize) -> usize {
+    n.next_multiple_of(VALUE_ALIGN)
+}
+
+// Whether `len` bytes starting at `start` lie before `end`, without overflowing.
+#[inline]
+fn in_bounds(start: usize, len: usize, end: usize) -> bool {
+    start <= end && len <= end - start
+}
+
+struct Freezer<F> {
+    branches: Vec<u8>,
+    leaf_table: Vec<u32>,
+    leaves: Vec<u8>,
+    values: Vec<u8>,
+    value: F,
+}
+
+impl<F> Freezer<F> {
+    fn node<K, V, M: Measure<K, V>>(&mut self, node: &Node<K, V, M>) -> u32
+    where
+        K: Borrow<[u8]>,
+        F: FnMut(&V, &mut Vec<u8>),
+    {
+        match *node {
+            Node::Leaf(ref leaf) => {
+                let index = to_u32(self.leaf_table.len());
+                let key = leaf.key_slice();
+
+                let val = align(self.values.len());
+                self.values.resize(val, 0);
+                (self.value)(&leaf.val, &mut self.values);
+                let val_len = self.values.len() - val;
+
+                self.leaf_table.push(to_u32(self.leaves.len()));
+                push_u32(&mut self.leaves, to_u32(key.len()));
+                push_u32(&mut self.leaves, to_u32(val));
+                push_u32(&mut self.leaves, to_u32(val_len));
+                self.leaves.extend_from_slice(key);
+
+                LEAF_FLAG | index
+            }
+            Node::Branch(ref branch) => {
+                let offset = HEADER_LEN + self.branches.len();
+                let first = to_u32(self.leaf_table.len());
+
+                push_u32(&mut self.branches, to_u32(branch.choice()));
+                push_u32(&mut self.branches, branch.bitmap());
+                push_u32(&mut self.branches, first);
+                push_u32(&mut self.branches, 0);
+                let children = self.branches.len();
+                for _ in branch.iter() {
+                    push_u32(&mut self.branches, 0);
+                }
+
+                for (i, child) in branch.iter().enumerate() {
+                    let child_ref = self.node(child).to_le_bytes();
+                    let at = children + i * 4;
+                    self.branches[at..at + 4].copy_from_slice(&child_ref);
+                }
+
+                let end = to_u32(self.leaf_table.len()).to_le_bytes();
+                let at = children - 4;
+                self.branches[at..at + 4].copy_from_slice(&end);
+
+                to_u32(offset)
+            }
+        }
+    }
+}
+
+// Write a frozen image of the trie rooted at `root`, using `value` to append the bytes stored for
+// each value to the image.
+pub fn freeze<K, V, M, F>(root: Option<&Node<K, V, M>>, count: usize, value: F) -> Vec<u8>
+where
+    K: Borrow<[u8]>,
+    M: Measure<K, V>,
+    F: FnMut(&V, &mut Vec<u8>),
+{
+    let mut freezer = Freezer {
+        branches: Vec::new(),
+        leaf_table: Vec::with_capacity(count),
+        leaves: Vec::new(),
+        values: Vec::new(),
+        value,
+    };
+
+    let root = root.map(|node| freezer.node(node)).unwrap_or(EMPTY);
+    let table = HEADER_LEN + freezer.branches.len();
+    let leaves = table + freezer.leaf_table.len() * 4;
+    let values = align(leaves + freezer.leaves.len());
+    to_u32(values + freezer.values.len());
+
+    let mut image = Vec::with_capacity(values + freezer.values.len());
+    image.extend_from_slice(MAGIC);
+    push_u32(&mut image, VERSION);
+    push_u32(&mut image, to_u32(count));
+    push_u32(&mut image, root);
+    push_u32(&mut image, to_u32(table));
+    push_u32(&mut image, to_u32(values));
+    image.extend_from_slice(&freezer.branches);
+    for offset in freezer.leaf_table {
+        push_u32(&mut image, to_u32(leaves) + offset);
+    }
+    image.extend_from_slice(&freezer.leaves);
+    image.resize(values, 0);
+    image.extend_from_slice(&freezer.values);
+
+    image
+}
+
+// A step in checking the nodes of an image: either a node to check, or the end of a branch whose
+// leaf range is checked once its children have been.
+enum Check {
+    Node(u32),
+    End(u32),
+}
+
+/// A read-only view of a trie image produced by `Trie::freeze`.
+///
+/// A `FrozenTrie` reads its nodes directly out of the underlying byte slice, which may be a
+/// memory-mapped file; opening one does not deserialize or copy anything. Values are stored as
+/// opaque byte strings, as written by the closure given to `Trie::freeze_with`, in a section of
+/// their own. Each value begins on a multiple of `FrozenTrie::VALUE_ALIGN` bytes from the start of the image,
+/// so if the image is itself aligned, fixed-size values can be read in place.
+///
+/// `FrozenTrie::new` checks the structure of the whole image in a single pass over its nodes and
+/// leaves, so that lookups never read out of bounds, panic or loop. A corrupted image which passes
+/// these checks may still give wrong answers.
+///
+/// # Example
+///
+/// ```rust
+/// # use qp_trie::{FrozenTrie, Trie};
+///
+/// let mut trie = Trie::new();
+///
+/// trie.insert_str("abc", 1u32);
+/// trie.insert_str("abd", 2u32);
+/// trie.insert_str("bcd", 3u32);
+///
+/// let image = trie.freeze_with(|val, buf| buf.extend_from_slice(&val.to_le_bytes()));
+/// let frozen = FrozenTrie::new(&image).unwrap();
+///
+/// assert_eq!(frozen.get(b"abd"), Some(&2u32.to_le_bytes()[..]));
+/// assert_eq!(frozen.iter_prefix(b"ab").count(), 2);
+/// assert_eq!(frozen.longest_common_prefix(b"abz"), b"ab");
+/// ```
+#[derive(Clone, Copy)]
+pub struct FrozenTrie<'a> {
+    bytes: &'a [u8],
+    root: u32,
+}
+
+impl<'a> fmt::Debug for FrozenTrie<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a> FrozenTrie<'a> {
+    /// The alignment of every value in a frozen image, relative to the start of the image.
+    pub const VALUE_ALIGN: usize = VALUE_ALIGN;
+
+    /// Open a frozen trie image, checking that it is well-formed.
+    pub fn new(bytes: &'a [u8]) -> Result<FrozenTrie<'a>, FrozenError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(FrozenError::Truncated);
+        }
+
+        if &bytes[..4] != MAGIC {
+            return Err(FrozenError::BadMagic);
+        }
+
+        let version = read_u32(bytes, 4);
+        if version != VERSION {
+            return Err(FrozenError::UnsupportedVersion(version));
+        }
+
+        let count = read_u32(bytes, 8) as usize;
+        let root = read_u32(bytes, 12);
+        let table = read_u32(bytes, 16) as usize;
+        let values = read_u32(bytes, 20) as usize;
+        if table < HEADER_LEN
+            || table > values
+            || values > bytes.len()
+            || (values - table) / 4 < count
+        {
+            return Err(FrozenError::Truncated);
+        }
+
+        let frozen = FrozenTrie { bytes, root };
+        if frozen.check_leaves(count, table, values) && frozen.check_nodes(count, table) {
+            Ok(frozen)
+        } else {
+            Err(FrozenError::Corrupt)
+        }
+    }
+
+    // Check that every leaf record, key and value lies in its own section of the image.
+    fn check_leaves(&self, count: usize, table: usize, values: usize) -> bool {
+        let bytes = self.bytes;
+        let leaves = table + count * 4;
+
+        (0..count).all(|index| {
+            let record = read_u32(bytes, table + index * 4) as usize;
+            if record < leaves || !in_bounds(record, LEAF_LEN, values) {
+                return false;
+            }
+
+            let key_len = read_u32(bytes, record) as usize;
+            let val = read_u32(bytes, record + 4) as usize;
+            let val_len = read_u32(bytes, record + 8) as usize;
+
+            in_bounds(record + LEAF_LEN, key_len, values)
+                && val % VALUE_ALIGN == 0
+                && in_bounds(val, val_len, bytes.len() - values)
+        })
+    }
+
+    // Check that the branch records are laid out in preorder, each directly after the last, and
+    // that the leaves are referenced in order, each exactly once. Every child of a branch then
+    // lies strictly after it and within the branch records, so no descent can loop or stray.
+    fn check_nodes(&self, count: usize, table: usize) -> bool {
+        let bytes = self.bytes;
+        let (mut next_branch, mut next_leaf) = (HEADER_LEN, 0);
+        let mut stack = Vec::new();
+
+        if self.root != EMPTY {
+            stack.push(Check::Node(self.root));
+        }
+
+        while let Some(check) = stack.pop() {
+            match check {
+                Check::Node(node) if node & LEAF_FLAG != 0 => {
+                    if (node & !LEAF_FLAG) as usize != next_leaf {
+                        return false;
+                    }
+                    next_leaf += 1;
+                }
+                Check::Node(node) => {
+                    let branch = node as usize;
+                    if branch != next_branch || !in_bounds(branch, BRANCH_LEN, table) {
+                        return false;
+                    }
+
+                    let bitmap = read_u32(bytes, branch + 4);
+                    let children = bitmap.count_ones() as usize;
+                    if bitmap >> 17 != 0
+                        || children < 2
+                        || !in_bounds(branch, BRANCH_LEN + children * 4, table)
+                        || read_u32(bytes, branch + 8) as usize != next_leaf
+                    {
+                        return false;
+                    }
+
+                    next_branch = branch + BRANCH_LEN + children * 4;
+                    stack.push(Check::End(node));
+                    for i in (0..children).rev() {
+                        stack.push(Check::Node(read_u32(bytes, branch + BRANCH_LEN + i * 4)));
+                    }
+                }
+                Check::End(node) => {
+                    if read_u32(bytes, node as usize + 12) as usize != next_leaf {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        next_branch == table && next_leaf == count
+    }
+
+    /// Returns true if the frozen trie has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.root == EMPTY
+    }
+
+    /// Count the number of entries in the frozen trie.
+    pub fn count(&self) -> usize {
+        let (first, end) = self.leaf_range(self.root);
+        end - first
+    }
+
+    /// Iterate over all keys and values in the frozen trie.
+    pub fn iter(&self) -> FrozenIter<'a> {
+        let (first, end) = self.leaf_range(self.root);
+        FrozenIter {
+            bytes: self.bytes,
+            next: first,
+            end,
+        }
+    }
+
+    /// Iterate over all entries whose keys begin with the given prefix.
+    pub fn iter_prefix(&self, prefix: &[u8]) -> FrozenIter<'a> {
+        self.subtrie(prefix).iter()
+    }
+
+    /// Get a view of the frozen trie containing only the entries whose keys begin with the given
+    /// prefix.
+    pub fn subtrie(&self, prefix: &[u8]) -> FrozenTrie<'a> {
+        let root = match self.exemplar(prefix) {
+            Some(exemplar) if self.leaf_key(exemplar).starts_with(prefix) => {
+                let mut node = self.root;
+
+                while node & LEAF_FLAG == 0 && self.choice(node) < prefix.len() * 2 {
+                    match self.child(node, nybble_index(self.choice(node), prefix)) {
+                        Some(child) => node = child,
+                        None => node = EMPTY,
+                    }
+                }
+
+                node
+            }
+            _ => EMPTY,
+        };
+
+        FrozenTrie {
+            bytes: self.bytes,
+            root,
+        }
+    }
+
+    /// Get the value associated with a given key, if it is in the frozen trie.
+    pub fn get(&self, key: &[u8]) -> Option<&'a [u8]> {
+        self.get_key_value(key).map(|(_, val)| val)
+    }
+
+    /// Get the stored key and the value associated with a given key, if it is in the frozen trie.
+    pub fn get_key_value(&self, key: &[u8]) -> Option<(&'a [u8], &'a [u8])> {
+        let mut node = self.root;
+
+        if node == EMPTY {
+            return None;
+        }
+
+        while node & LEAF_FLAG == 0 {
+            node = self.child(node, nybble_index(self.choice(node), key))?;
+        }
+
+        let leaf = self.leaf(node & !LEAF_FLAG);
+        if leaf.0 == key {
+            Some(leaf)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if there is an entry for the given key.
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Get the longest common prefix of all the keys in the frozen trie and the given key.
+    pub fn longest_common_prefix(&self, key: &[u8]) -> &'a [u8] {
+        match self.exemplar(key) {
+            Some(exemplar) => {
+                let exemplar = self.leaf_key(exemplar);
+
+                match nybble_mismatch(exemplar, key) {
+                    Some(i) => &exemplar[..i / 2],
+                    None => exemplar,
+                }
+            }
+            None => &[],
+        }
+    }
+
+    // Find the leaf index of the exemplar for the given key; see `Node::get_exemplar`.
+    fn exemplar(&self, key: &[u8]) -> Option<u32> {
+        let mut node = self.root;
+
+        if node == EMPTY {
+            return None;
+        }
+
+        while node & LEAF_FLAG == 0 {
+            let index = nybble_index(self.choice(node), key);
+            node = self
+                .child(node, index)
+                .unwrap_or_else(|| read_u32(self.bytes, node as usize + BRANCH_LEN));
+        }
+
+        Some(node & !LEAF_FLAG)
+    }
+
+    #[inline]
+    fn choice(&self, branch: u32) -> usize {
+        read_u32(self.bytes, branch as usize) as usize
+    }
+
+    // Look up the child of a branch for a given nybble index, if there is one.
+    #[inline]
+    fn child(&self, branch: u32, index: u8) -> Option<u32> {
+        let branch = branch as usize;
+        let bitmap = read_u32(self.bytes, branch + 4);
+
+        if bitmap & (1 << index) == 0 {
+            return None;
+        }
+
+        let actual = (bitmap & ((1 << index) - 1)).count_ones() as usize;
+        Some(read_u32(self.bytes, branch + BRANCH_LEN + actual * 4))
+    }
+
+    // The range of leaf indices covered by a node.
+    fn leaf_range(&self, node: u32) -> (usize, usize) {
+        if node == EMPTY {
+            (0, 0)
+        } else if node & LEAF_FLAG != 0 {
+            let index = (node & !LEAF_FLAG) as usize;
+            (index, index + 1)
+        } else {
+            let branch = node as usize;
+            (
+                read_u32(self.bytes, branch + 8) as usize,
+                read_u32(self.bytes, branch + 12) as usize,
+            )
+        }
+    }
+
+    #[inline]
+    fn leaf_key(&self, index: u32) -> &'a [u8] {
+        self.leaf(index).0
+    }
+
+    #[inline]
+    fn leaf(&self, index: u32) -> (&'a [u8], &'a [u8]) {
+        read_leaf(self.bytes, index as usize)
+    }
+}
+
+fn read_leaf(bytes: &[u8], index: usize) -> (&[u8], &[u8]) {
+    let table = read_u32(bytes, 16) as usize;
+    let values = read_u32(bytes, 20) as usize;
+    let record = read_u32(bytes, table + index * 4) as usize;
+    let key_len = read_u32(bytes, record) as usize;
+    let val = values + read_u32(bytes, record + 4) as usize;
+    let val_len = read_u32(bytes, record + 8) as usize;
+    let key = record + LEAF_LEN;
+
+    (&bytes[key..key + key_len], &bytes[val..val + val_len])
+}
+
+impl<'a> IntoIterator for FrozenTrie<'a> {
+    type IntoIter = FrozenIter<'a>;
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// An iterator over the keys and values of a frozen trie, in the same order as `Trie::iter`.
+#[derive(Clone, Debug)]
+pub struct FrozenIter<'a> {
+    bytes: &'a [u8],
+    next: usize,
+    end: usize,
+}
+
+impl<'a> Iterator for FrozenIter<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next < self.end {
+            let leaf = read_leaf(self.bytes, self.next);
+            self.next += 1;
+            Some(leaf)
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.next;
+        (len, Some(len))
+    }
+}
+
+impl<'a> DoubleEndedIterator for FrozenIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.next < self.end {
+            self.end -= 1;
+            Some(read_leaf(self.bytes, self.end))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> ExactSizeIterator for FrozenIter<'a> {}
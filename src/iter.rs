@@ -1,26 +1,46 @@
 use alloc::{vec, vec::Vec};
+use core::fmt;
 
+use measure::Measure;
 use node::Node;
 
 /// An iterator over the keys and values in a QP-trie.
-#[derive(Clone, Debug)]
-pub struct IntoIter<K, V> {
-    stack: Vec<Node<K, V>>,
+pub struct IntoIter<K, V, M: Measure<K, V> = ()> {
+    stack: Vec<Node<K, V, M>>,
 }
 
-impl<K, V> IntoIter<K, V> {
-    pub(crate) fn new(node: Node<K, V>) -> IntoIter<K, V> {
+impl<K: Clone, V: Clone, M: Measure<K, V>> Clone for IntoIter<K, V, M>
+where
+    M::Summary: Clone,
+{
+    fn clone(&self) -> Self {
+        IntoIter {
+            stack: self.stack.clone(),
+        }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, M: Measure<K, V>> fmt::Debug for IntoIter<K, V, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IntoIter")
+            .field("stack", &self.stack)
+            .finish()
+    }
+}
+
+impl<K, V, M: Measure<K, V>> IntoIter<K, V, M> {
+    pub(crate) fn new(node: Node<K, V, M>) -> IntoIter<K, V, M> {
         IntoIter { stack: vec![node] }
     }
 }
 
-impl<K, V> Default for IntoIter<K, V> {
+impl<K, V, M: Measure<K, V>> Default for IntoIter<K, V, M> {
     fn default() -> Self {
         IntoIter { stack: vec![] }
     }
 }
 
-impl<K, V> Iterator for IntoIter<K, V> {
+impl<K, V, M: Measure<K, V>> Iterator for IntoIter<K, V, M> {
     type Item = (K, V);
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -36,24 +56,37 @@ impl<K, V> Iterator for IntoIter<K, V> {
 }
 
 /// An iterator over immutable references to keys and values in a QP-trie.
-#[derive(Clone, Debug)]
-pub struct Iter<'a, K: 'a, V: 'a> {
-    stack: Vec<&'a Node<K, V>>,
+pub struct Iter<'a, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
+    stack: Vec<&'a Node<K, V, M>>,
 }
 
-impl<'a, K, V> Iter<'a, K, V> {
-    pub fn new(node: &'a Node<K, V>) -> Iter<'a, K, V> {
+impl<'a, K, V, M: Measure<K, V>> Clone for Iter<'a, K, V, M> {
+    fn clone(&self) -> Self {
+        Iter {
+            stack: self.stack.clone(),
+        }
+    }
+}
+
+impl<'a, K: fmt::Debug, V: fmt::Debug, M: Measure<K, V>> fmt::Debug for Iter<'a, K, V, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Iter").field("stack", &self.stack).finish()
+    }
+}
+
+impl<'a, K, V, M: Measure<K, V>> Iter<'a, K, V, M> {
+    pub fn new(node: &'a Node<K, V, M>) -> Iter<'a, K, V, M> {
         Iter { stack: vec![node] }
     }
 }
 
-impl<'a, K, V> Default for Iter<'a, K, V> {
+impl<'a, K, V, M: Measure<K, V>> Default for Iter<'a, K, V, M> {
     fn default() -> Self {
         Iter { stack: vec![] }
     }
 }
 
-impl<'a, K: 'a, V: 'a> Iterator for Iter<'a, K, V> {
+impl<'a, K: 'a, V: 'a, M: Measure<K, V>> Iterator for Iter<'a, K, V, M> {
     type Item = (&'a K, &'a V);
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -69,24 +102,31 @@ impl<'a, K: 'a, V: 'a> Iterator for Iter<'a, K, V> {
 }
 
 /// An iterator over immutable references to keys and mutable references to values in a QP-trie.
-#[derive(Debug)]
-pub struct IterMut<'a, K: 'a, V: 'a> {
-    stack: Vec<&'a mut Node<K, V>>,
+pub struct IterMut<'a, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
+    stack: Vec<&'a mut Node<K, V, M>>,
+}
+
+impl<'a, K: fmt::Debug, V: fmt::Debug, M: Measure<K, V>> fmt::Debug for IterMut<'a, K, V, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IterMut")
+            .field("stack", &self.stack)
+            .finish()
+    }
 }
 
-impl<'a, K, V> IterMut<'a, K, V> {
-    pub fn new(node: &'a mut Node<K, V>) -> IterMut<'a, K, V> {
+impl<'a, K, V, M: Measure<K, V>> IterMut<'a, K, V, M> {
+    pub fn new(node: &'a mut Node<K, V, M>) -> IterMut<'a, K, V, M> {
         IterMut { stack: vec![node] }
     }
 }
 
-impl<'a, K, V> Default for IterMut<'a, K, V> {
+impl<'a, K, V, M: Measure<K, V>> Default for IterMut<'a, K, V, M> {
     fn default() -> Self {
         IterMut { stack: vec![] }
     }
 }
 
-impl<'a, K: 'a, V: 'a> Iterator for IterMut<'a, K, V> {
+impl<'a, K: 'a, V: 'a, M: Measure<K, V>> Iterator for IterMut<'a, K, V, M> {
     type Item = (&'a K, &'a mut V);
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -102,24 +142,37 @@ impl<'a, K: 'a, V: 'a> Iterator for IterMut<'a, K, V> {
 }
 
 /// An iterator over immutable references to the keys in the QP-trie.
-#[derive(Clone, Debug)]
-pub struct Keys<'a, K: 'a, V: 'a> {
-    stack: Vec<&'a Node<K, V>>,
+pub struct Keys<'a, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
+    stack: Vec<&'a Node<K, V, M>>,
 }
 
-impl<'a, K, V> Keys<'a, K, V> {
-    pub fn new(node: &'a Node<K, V>) -> Keys<'a, K, V> {
+impl<'a, K, V, M: Measure<K, V>> Clone for Keys<'a, K, V, M> {
+    fn clone(&self) -> Self {
+        Keys {
+            stack: self.stack.clone(),
+        }
+    }
+}
+
+impl<'a, K: fmt::Debug, V: fmt::Debug, M: Measure<K, V>> fmt::Debug for Keys<'a, K, V, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Keys").field("stack", &self.stack).finish()
+    }
+}
+
+impl<'a, K, V, M: Measure<K, V>> Keys<'a, K, V, M> {
+    pub fn new(node: &'a Node<K, V, M>) -> Keys<'a, K, V, M> {
         Keys { stack: vec![node] }
     }
 }
 
-impl<'a, K, V> Default for Keys<'a, K, V> {
+impl<'a, K, V, M: Measure<K, V>> Default for Keys<'a, K, V, M> {
     fn default() -> Self {
         Keys { stack: vec![] }
     }
 }
 
-impl<'a, K: 'a, V: 'a> Iterator for Keys<'a, K, V> {
+impl<'a, K: 'a, V: 'a, M: Measure<K, V>> Iterator for Keys<'a, K, V, M> {
     type Item = &'a K;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -135,24 +188,39 @@ impl<'a, K: 'a, V: 'a> Iterator for Keys<'a, K, V> {
 }
 
 /// An iterator over immutable references to the values in the QP-trie.
-#[derive(Clone, Debug)]
-pub struct Values<'a, K: 'a, V: 'a> {
-    stack: Vec<&'a Node<K, V>>,
+pub struct Values<'a, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
+    stack: Vec<&'a Node<K, V, M>>,
 }
 
-impl<'a, K, V> Values<'a, K, V> {
-    pub fn new(node: &'a Node<K, V>) -> Values<'a, K, V> {
+impl<'a, K, V, M: Measure<K, V>> Clone for Values<'a, K, V, M> {
+    fn clone(&self) -> Self {
+        Values {
+            stack: self.stack.clone(),
+        }
+    }
+}
+
+impl<'a, K: fmt::Debug, V: fmt::Debug, M: Measure<K, V>> fmt::Debug for Values<'a, K, V, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Values")
+            .field("stack", &self.stack)
+            .finish()
+    }
+}
+
+impl<'a, K, V, M: Measure<K, V>> Values<'a, K, V, M> {
+    pub fn new(node: &'a Node<K, V, M>) -> Values<'a, K, V, M> {
         Values { stack: vec![node] }
     }
 }
 
-impl<'a, K, V> Default for Values<'a, K, V> {
+impl<'a, K, V, M: Measure<K, V>> Default for Values<'a, K, V, M> {
     fn default() -> Self {
         Values { stack: vec![] }
     }
 }
 
-impl<'a, K: 'a, V: 'a> Iterator for Values<'a, K, V> {
+impl<'a, K: 'a, V: 'a, M: Measure<K, V>> Iterator for Values<'a, K, V, M> {
     type Item = &'a V;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -168,24 +236,31 @@ impl<'a, K: 'a, V: 'a> Iterator for Values<'a, K, V> {
 }
 
 /// An iterator over mutable references to the values in the QP-trie.
-#[derive(Debug)]
-pub struct ValuesMut<'a, K: 'a, V: 'a> {
-    stack: Vec<&'a mut Node<K, V>>,
+pub struct ValuesMut<'a, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
+    stack: Vec<&'a mut Node<K, V, M>>,
+}
+
+impl<'a, K: fmt::Debug, V: fmt::Debug, M: Measure<K, V>> fmt::Debug for ValuesMut<'a, K, V, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ValuesMut")
+            .field("stack", &self.stack)
+            .finish()
+    }
 }
 
-impl<'a, K, V> ValuesMut<'a, K, V> {
-    pub fn new(node: &'a mut Node<K, V>) -> ValuesMut<'a, K, V> {
+impl<'a, K, V, M: Measure<K, V>> ValuesMut<'a, K, V, M> {
+    pub fn new(node: &'a mut Node<K, V, M>) -> ValuesMut<'a, K, V, M> {
         ValuesMut { stack: vec![node] }
     }
 }
 
-impl<'a, K, V> Default for ValuesMut<'a, K, V> {
+impl<'a, K, V, M: Measure<K, V>> Default for ValuesMut<'a, K, V, M> {
     fn default() -> Self {
         ValuesMut { stack: vec![] }
     }
 }
 
-impl<'a, K: 'a, V: 'a> Iterator for ValuesMut<'a, K, V> {
+impl<'a, K: 'a, V: 'a, M: Measure<K, V>> Iterator for ValuesMut<'a, K, V, M> {
     type Item = &'a mut V;
 
     fn next(&mut self) -> Option<Self::Item> {
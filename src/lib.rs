@@ -1,6 +1,8 @@
 #![no_std]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
 #[macro_use]
 extern crate debug_unreachable;
@@ -14,20 +16,53 @@ extern crate serde;
 #[cfg(test)]
 extern crate quickcheck;
 
+#[cfg(feature = "digest")]
+extern crate digest;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "regex-automata")]
+extern crate regex_automata;
+#[cfg(feature = "unicode-normalization")]
+extern crate unicode_normalization;
+
+#[cfg(feature = "rayon")]
+pub mod par;
+#[cfg(feature = "std")]
+pub mod path;
 #[cfg(feature = "serde")]
-mod serialization;
+pub mod serialization;
+
+pub mod automaton;
+pub mod reversed;
+pub mod tuple;
 
+mod bitwise;
+mod diff;
 mod entry;
+mod frozen;
 mod iter;
+mod measure;
+#[cfg(feature = "digest")]
+mod merkle;
 mod node;
+mod prefix_entry;
 mod sparse;
 mod subtrie;
 mod trie;
 mod util;
+mod xor;
 
 pub mod wrapper;
 
-pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use bitwise::{HammingIter, MaskIter};
+pub use diff::{Diff, DiffItem};
+pub use entry::{Entry, EntryRef, OccupiedEntry, OccupiedError, VacantEntry, VacantEntryRef};
+pub use frozen::{FrozenError, FrozenIter, FrozenTrie};
 pub use iter::{IntoIter, Iter, IterMut};
-pub use subtrie::SubTrie;
+pub use measure::{Max, Measure, Min, Monoid, Sum};
+#[cfg(feature = "digest")]
+pub use merkle::{DiffByHash, KeyRange, Merkle, MerkleDigest};
+pub use prefix_entry::{OccupiedPrefixEntry, PrefixEntry, VacantPrefixEntry};
+pub use subtrie::{SubTrie, SubTrieMut};
 pub use trie::{Break, Trie};
+pub use xor::XorIter;
@@ -0,0 +1,305 @@
+use alloc::collections::BinaryHeap;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::ops::Add;
+
+use node::Node;
+use trie::Trie;
+use util::Ranked;
+use wrapper::{BStr, BString};
+
+/// A summary of a set of entries: an associative `combine` operation with an identity, `empty`.
+pub trait Monoid {
+    /// The identity of `combine`, which is the summary of no values at all.
+    fn empty() -> Self;
+
+    /// Combine two summaries. This must be associative, but need not be commutative; summaries
+    /// are combined in the trie's iteration order.
+    fn combine(&self, other: &Self) -> Self;
+}
+
+/// A monoid summing its values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Sum<T>(pub T);
+
+impl<T: Clone + Default + Add<Output = T>> Monoid for Sum<T> {
+    fn empty() -> Self {
+        Sum(T::default())
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        Sum(self.0.clone() + other.0.clone())
+    }
+}
+
+impl<T> From<T> for Sum<T> {
+    fn from(val: T) -> Self {
+        Sum(val)
+    }
+}
+
+/// A monoid keeping the least of its values, or `None` if there are none.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Min<T>(pub Option<T>);
+
+impl<T: Clone + Ord> Monoid for Min<T> {
+    fn empty() -> Self {
+        Min(None)
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        match (&self.0, &other.0) {
+            (Some(l), Some(r)) if r < l => Min(Some(r.clone())),
+            (Some(l), _) => Min(Some(l.clone())),
+            (None, r) => Min(r.clone()),
+        }
+    }
+}
+
+impl<T> From<T> for Min<T> {
+    fn from(val: T) -> Self {
+        Min(Some(val))
+    }
+}
+
+/// A monoid keeping the greatest of its values, or `None` if there are none.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Max<T>(pub Option<T>);
+
+impl<T: Clone + Ord> Monoid for Max<T> {
+    fn empty() -> Self {
+        Max(None)
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        match (&self.0, &other.0) {
+            (Some(l), Some(r)) if r > l => Max(Some(r.clone())),
+            (Some(l), _) => Max(Some(l.clone())),
+            (None, r) => Max(r.clone()),
+        }
+    }
+}
+
+impl<T> From<T> for Max<T> {
+    fn from(val: T) -> Self {
+        Max(Some(val))
+    }
+}
+
+// The summary of a trie which is not measured.
+impl Monoid for () {
+    #[inline]
+    fn empty() {}
+
+    #[inline]
+    fn combine(&self, _: &()) {}
+}
+
+/// A way of summarizing the entries of a trie. A `Trie<K, V, M>` measures each of its entries
+/// with `M`, and every branch of the trie caches the combination of the summaries beneath it,
+/// recomputing it along the path of every insertion and removal. Aggregating over all entries with
+/// a given prefix then takes time proportional to the depth of the trie rather than to the number
+/// of entries.
+///
+/// Values may still be borrowed mutably in place, through `get_mut`, `entry`, `iter_mut` and the
+/// like. Doing so marks the branches above the borrowed values as stale, and aggregates over a
+/// stale branch are recomputed from its children rather than read from its cache, until the next
+/// insertion or removal beneath it brings the cache back up to date. The default measure, `()`,
+/// keeps no summary at all, and costs nothing.
+///
+/// # Example
+///
+/// ```rust
+/// # use qp_trie::{Measure, Sum, Trie};
+/// struct File {
+///     size: u64,
+///     owner: &'static str,
+/// }
+///
+/// struct TotalSize;
+///
+/// impl<K> Measure<K, File> for TotalSize {
+///     type Summary = Sum<u64>;
+///
+///     fn measure(_: &K, file: &File) -> Sum<u64> {
+///         Sum(file.size)
+///     }
+/// }
+///
+/// let mut files = Trie::<_, _, TotalSize>::with_measure();
+///
+/// files.insert(&b"/home/alice/notes.txt"[..], File { size: 120, owner: "alice" });
+/// files.insert(&b"/home/alice/photo.jpg"[..], File { size: 4000, owner: "alice" });
+/// files.insert(&b"/home/bob/run.sh"[..], File { size: 30, owner: "bob" });
+///
+/// assert_eq!(files.aggregate_prefix(&b"/home/alice/"[..]), Sum(4120));
+/// assert_eq!(files.aggregate_prefix(&b"/home/"[..]), Sum(4150));
+/// assert_eq!(files.aggregate_prefix(&b"/srv/"[..]), Sum(0));
+/// assert_eq!(files.get(&b"/home/bob/run.sh"[..]).map(|f| f.owner), Some("bob"));
+/// ```
+pub trait Measure<K, V> {
+    /// The summary of a set of entries.
+    type Summary: Monoid;
+
+    /// The summary of a single entry.
+    fn measure(key: &K, val: &V) -> Self::Summary;
+
+    /// The summary of a branch of the trie, given the nybble index it splits its keys at and the
+    /// summaries of its children, in iteration order, each with its index in the branch. By
+    /// default this combines the children's summaries, leaving out the shape of the trie; a
+    /// measure such as `Merkle`, whose summaries must commit to that shape, can override it.
+    #[inline]
+    fn summarize_branch<I>(choice: usize, children: I) -> Self::Summary
+    where
+        I: Iterator<Item = (u8, Self::Summary)>,
+    {
+        let _ = choice;
+        children.fold(Self::Summary::empty(), |acc, (_, summary)| {
+            acc.combine(&summary)
+        })
+    }
+}
+
+impl<K, V> Measure<K, V> for () {
+    type Summary = ();
+
+    #[inline]
+    fn measure(_: &K, _: &V) {}
+}
+
+impl<K: Borrow<[u8]>, V, M: Measure<K, V>> Trie<K, V, M> {
+    /// The summary of every entry in the trie.
+    pub fn aggregate(&self) -> M::Summary {
+        match self.root() {
+            Some(root) => root.summarize(&M::Summary::empty()),
+            None => M::Summary::empty(),
+        }
+    }
+
+    /// The summary of every entry whose key begins with the given prefix. This only visits the
+    /// branches on the path to the prefix, rather than every entry beneath it.
+    pub fn aggregate_prefix<Q>(&self, prefix: &Q) -> M::Summary
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        match self
+            .root()
+            .and_then(|root| root.get_prefix(prefix.borrow()))
+        {
+            Some(node) => node.summarize(&M::Summary::empty()),
+            None => M::Summary::empty(),
+        }
+    }
+
+    /// Find the `k` entries beginning with `prefix` which score highest, best first. Scores are
+    /// given by `score_fn` from summaries under the measure `M`: that of each entry, and the one
+    /// cached for each branch. A branch must score at least as high as every entry beneath it,
+    /// as it does when `M` keeps the highest score in a `Max`, or sums scores which are never
+    /// negative in a `Sum`. Among entries which score the same, those found first by the search
+    /// win.
+    ///
+    /// This is a best-first search guided by the scores of the branches: a branch is only visited
+    /// once its score is among the best `k`, so most of the entries with a common prefix need
+    /// never be looked at.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use qp_trie::{Max, Measure, Trie};
+    /// # use qp_trie::wrapper::BString;
+    /// struct Popularity;
+    ///
+    /// impl<K> Measure<K, u32> for Popularity {
+    ///     type Summary = Max<u32>;
+    ///
+    ///     fn measure(_: &K, &searches: &u32) -> Max<u32> {
+    ///         Max::from(searches)
+    ///     }
+    /// }
+    ///
+    /// let mut words = Trie::<BString, u32, Popularity>::with_measure();
+    /// words.insert_str("apple", 40);
+    /// words.insert_str("apply", 25);
+    /// words.insert_str("ape", 90);
+    /// words.insert_str("banana", 70);
+    ///
+    /// let best = words.top_k_prefix_str("ap", 2, |searches| searches.0);
+    /// assert_eq!(best.iter().map(|(w, _)| w.as_str()).collect::<Vec<_>>(), ["ape", "apple"]);
+    /// ```
+    pub fn top_k_prefix<'a, Q, S, F>(
+        &'a self,
+        prefix: &Q,
+        k: usize,
+        mut score_fn: F,
+    ) -> Vec<(&'a K, &'a V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+        S: Ord,
+        F: FnMut(&M::Summary) -> S,
+    {
+        let mut found = Vec::new();
+        let mut frontier = BinaryHeap::new();
+        let mut seq = 0;
+
+        if let Some(node) = self
+            .root()
+            .and_then(|root| root.get_prefix(prefix.borrow()))
+        {
+            frontier.push(Ranked {
+                score: score_fn(&node.summarize(&M::Summary::empty())),
+                seq,
+                item: node,
+            });
+        }
+
+        while found.len() < k {
+            let node = match frontier.pop() {
+                Some(ranked) => ranked.item,
+                None => break,
+            };
+
+            match *node {
+                Node::Leaf(ref leaf) => found.push((&leaf.key, &leaf.val)),
+                Node::Branch(ref branch) => {
+                    for child in branch.iter() {
+                        seq += 1;
+                        frontier.push(Ranked {
+                            score: score_fn(&child.summarize(&M::Summary::empty())),
+                            seq,
+                            item: child,
+                        });
+                    }
+                }
+            }
+        }
+
+        found
+    }
+}
+
+impl<V, M: Measure<BString, V>> Trie<BString, V, M> {
+    /// Convenience function for aggregating over a string prefix.
+    pub fn aggregate_prefix_str<Q>(&self, prefix: &Q) -> M::Summary
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.aggregate_prefix(AsRef::<BStr>::as_ref(prefix.borrow()))
+    }
+
+    /// Convenience function for finding the best completions of a string prefix.
+    pub fn top_k_prefix_str<'a, Q, S, F>(
+        &'a self,
+        prefix: &Q,
+        k: usize,
+        score_fn: F,
+    ) -> Vec<(&'a BString, &'a V)>
+    where
+        Q: ?Sized + Borrow<str>,
+        S: Ord,
+        F: FnMut(&M::Summary) -> S,
+    {
+        self.top_k_prefix(AsRef::<BStr>::as_ref(prefix.borrow()), k, score_fn)
+    }
+}
@@ -0,0 +1,416 @@
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
+
+use digest::{Digest, Output, OutputSizeUser};
+
+use measure::{Measure, Monoid};
+use node::Node;
+use trie::Trie;
+
+// Tags keeping the digests of leaves and branches apart.
+const LEAF: u8 = 0;
+const BRANCH: u8 = 1;
+
+/// A measure keeping a Merkle digest of the entries beneath every branch of a trie, hashed with
+/// the cryptographic digest `D` (any implementation of `digest::Digest`, such as `sha2::Sha256`).
+/// Digests are cached alongside the trie and updated along the path of every insertion and
+/// removal, so the digest of any prefix is always at hand. Two replicas of the same trie - say, on
+/// different machines - can then find where they differ with `Trie::diff_by_hash`, by exchanging
+/// digests of ever narrower prefixes.
+///
+/// The digest of a leaf covers its key and value, and the digest of a branch covers the nybble
+/// index it splits its keys at and the digests of its children in order, each with its index in
+/// the branch. As the shape of a trie depends only on its keys, two tries with the same entries
+/// have the same digests, and finding two sets of entries with the same digest is as hard as
+/// finding a collision in `D`.
+///
+/// Values are fed to the digest through their `Hash` implementations. Those of the integer types
+/// depend on the byte order of the machine, so replicas should agree on it, or encode their values
+/// as bytes or strings.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate qp_trie;
+/// # extern crate sha2;
+/// # use qp_trie::{Merkle, Trie};
+/// # use qp_trie::wrapper::BString;
+/// # use sha2::Sha256;
+/// # fn main() {
+/// let mut ours = Trie::<BString, _, Merkle<Sha256>>::with_measure();
+/// ours.insert_str("config/a", 1);
+/// ours.insert_str("config/b", 2);
+/// ours.insert_str("config/c", 3);
+///
+/// let mut theirs = ours.clone();
+/// *theirs.get_mut_str("config/b").unwrap() = 20;
+///
+/// // In practice, each digest asked for would be a round trip to the other replica.
+/// let ranges = ours
+///     .diff_by_hash(|range| theirs.merkle_digest(range))
+///     .collect::<Vec<_>>();
+/// assert_eq!(ranges.len(), 1);
+/// assert!(ranges[0].contains(b"config/b"));
+/// assert!(!ranges[0].contains(b"config/a"));
+/// # }
+/// ```
+pub struct Merkle<D>(PhantomData<D>);
+
+/// The Merkle digest of the entries beneath a node of a trie measured by `Merkle<D>`, or `None`
+/// for no entries at all.
+///
+/// The trie only ever combines a digest with the empty digest, but digests combine by XOR so as to
+/// form a monoid.
+pub struct MerkleDigest<D: OutputSizeUser>(pub Option<Output<D>>);
+
+impl<D: OutputSizeUser> Clone for MerkleDigest<D> {
+    fn clone(&self) -> Self {
+        MerkleDigest(self.0.clone())
+    }
+}
+
+impl<D: OutputSizeUser> PartialEq for MerkleDigest<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<D: OutputSizeUser> Eq for MerkleDigest<D> {}
+
+impl<D: OutputSizeUser> fmt::Debug for MerkleDigest<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(ref digest) => {
+                for byte in digest.iter() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            None => f.write_str("empty"),
+        }
+    }
+}
+
+impl<D: OutputSizeUser> Monoid for MerkleDigest<D> {
+    fn empty() -> Self {
+        MerkleDigest(None)
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        match (&self.0, &other.0) {
+            (Some(l), Some(r)) => {
+                let mut digest = l.clone();
+                for (byte, r) in digest.iter_mut().zip(r.iter()) {
+                    *byte ^= *r;
+                }
+                MerkleDigest(Some(digest))
+            }
+            (Some(l), None) => MerkleDigest(Some(l.clone())),
+            (None, r) => MerkleDigest(r.clone()),
+        }
+    }
+}
+
+// Feeds everything hashed into it to a digest.
+struct DigestWriter<'a, D: 'a>(&'a mut D);
+
+impl<'a, D: Digest> Hasher for DigestWriter<'a, D> {
+    fn finish(&self) -> u64 {
+        unreachable!("values are only ever written to a digest")
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+}
+
+impl<K: Borrow<[u8]>, V: Hash, D: Digest> Measure<K, V> for Merkle<D> {
+    type Summary = MerkleDigest<D>;
+
+    fn measure(key: &K, val: &V) -> MerkleDigest<D> {
+        MerkleDigest(Some(leaf_digest::<D, V>(key.borrow(), val)))
+    }
+
+    fn summarize_branch<I>(choice: usize, children: I) -> MerkleDigest<D>
+    where
+        I: Iterator<Item = (u8, MerkleDigest<D>)>,
+    {
+        MerkleDigest(Some(branch_digest::<D, _>(choice, children)))
+    }
+}
+
+fn leaf_digest<D: Digest, V: Hash>(key: &[u8], val: &V) -> Output<D> {
+    let mut digest = D::new();
+    digest.update([LEAF]);
+    digest.update((key.len() as u64).to_le_bytes());
+    digest.update(key);
+    val.hash(&mut DigestWriter(&mut digest));
+    digest.finalize()
+}
+
+// Every child of a branch has entries, so its digest is always present; empty digests are skipped
+// all the same, as `diff_by_hash` uses this to check the digests reported for children.
+fn branch_digest<D: Digest, I>(choice: usize, children: I) -> Output<D>
+where
+    I: Iterator<Item = (u8, MerkleDigest<D>)>,
+{
+    let mut digest = D::new();
+    digest.update([BRANCH]);
+    digest.update((choice as u64).to_le_bytes());
+    for (index, child) in children {
+        if let Some(ref child) = child.0 {
+            digest.update([index]);
+            digest.update(child);
+        }
+    }
+    digest.finalize()
+}
+
+fn node_digest<K: Borrow<[u8]>, V: Hash, D: Digest>(
+    node: &Node<K, V, Merkle<D>>,
+) -> Option<Output<D>> {
+    node.summarize(&MerkleDigest::empty()).0
+}
+
+// The node holding exactly the keys of the trie in a prefix range, if there are any.
+fn range_node<'a, K, V, D>(
+    root: &'a Node<K, V, Merkle<D>>,
+    range: &KeyRange,
+) -> Option<&'a Node<K, V, Merkle<D>>>
+where
+    K: Borrow<[u8]>,
+    V: Hash,
+    D: Digest,
+{
+    let mut node = root;
+    while let Node::Branch(ref branch) = *node {
+        if branch.choice() >= range.nybbles {
+            break;
+        }
+        node = branch.child(&range.bytes)?;
+    }
+
+    // Every key beneath the node agrees past the end of the range, so any one of them tells
+    // whether they are all in it.
+    if range.contains(node.get_exemplar(&[]).key_slice()) {
+        Some(node)
+    } else {
+        None
+    }
+}
+
+impl<K: Borrow<[u8]>, V: Hash, D: Digest> Trie<K, V, Merkle<D>> {
+    /// The Merkle digest of the whole trie, or `None` if it is empty. Two tries with the same
+    /// entries always have the same digest.
+    pub fn merkle_root(&self) -> Option<Output<D>> {
+        self.merkle_digest(&KeyRange::all())
+    }
+
+    /// The Merkle digest of the entries in the given range, or `None` if there are none. This only
+    /// visits the branches on the path to the range.
+    pub fn merkle_digest(&self, range: &KeyRange) -> Option<Output<D>> {
+        let root = self.root()?;
+
+        if range.exact {
+            return root
+                .get(range.byte_prefix())
+                .map(|leaf| leaf_digest::<D, V>(leaf.key_slice(), &leaf.val));
+        }
+
+        range_node(root, range).and_then(node_digest)
+    }
+
+    /// Iterate over ranges of keys in which this trie differs from another, given a way to ask for
+    /// the other trie's digest of a range - typically `Trie::merkle_digest` on the far side of a
+    /// connection. Every key whose presence or value differs between the two tries is in one of
+    /// the ranges, and the ranges are disjoint.
+    ///
+    /// Ranges are narrowed by following the branches of this trie. At each branch whose digest
+    /// differs, the other trie is asked for the digests of the branch's children; if those make up
+    /// the other trie's digest of the whole branch, the two tries branch the same way there, and
+    /// only the children whose digests differ are descended into. Otherwise the other trie has
+    /// keys which this one has no branch for, and the range is given whole. The number of digests
+    /// asked for is thus proportional to the number of differences times the depth of the trie.
+    pub fn diff_by_hash<'a, F>(&'a self, mut remote: F) -> DiffByHash<'a, K, V, D, F>
+    where
+        F: FnMut(&KeyRange) -> Option<Output<D>>,
+    {
+        let range = KeyRange::all();
+        let theirs = remote(&range);
+        let mut stack = Vec::new();
+        if theirs != self.merkle_root() {
+            stack.push((self.root(), range, theirs));
+        }
+        DiffByHash { stack, remote }
+    }
+}
+
+// Returns true if the first `nybbles` nybbles of the two byte strings agree. Both must be at
+// least long enough to hold that many nybbles.
+fn nybbles_eq(left: &[u8], right: &[u8], nybbles: usize) -> bool {
+    let bytes = nybbles / 2;
+    left[..bytes] == right[..bytes]
+        && (nybbles & 1 == 0 || (left[bytes] ^ right[bytes]) & 0x0F == 0)
+}
+
+/// A range of keys in a Merkle trie. It is either a single key, or every key which begins with a
+/// given prefix. As the trie branches on nybbles, a prefix may end halfway through a byte, in
+/// which case only the low nybble of its last byte is fixed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KeyRange {
+    bytes: Vec<u8>,
+    nybbles: usize,
+    exact: bool,
+}
+
+impl KeyRange {
+    /// The range of every key.
+    pub fn all() -> KeyRange {
+        KeyRange::prefix(&[])
+    }
+
+    /// The range of every key beginning with the given bytes.
+    pub fn prefix(prefix: &[u8]) -> KeyRange {
+        KeyRange {
+            bytes: prefix.to_vec(),
+            nybbles: prefix.len() * 2,
+            exact: false,
+        }
+    }
+
+    /// The range holding only the given key.
+    pub fn key(key: &[u8]) -> KeyRange {
+        KeyRange {
+            bytes: key.to_vec(),
+            nybbles: key.len() * 2,
+            exact: true,
+        }
+    }
+
+    // The range of the child at `index` of a branch with the given choice point, whose keys begin
+    // with `prefix`.
+    fn child(choice: usize, prefix: &[u8], index: u8) -> KeyRange {
+        if index == 0 {
+            return KeyRange::key(&prefix[..choice / 2]);
+        }
+
+        let mut bytes = prefix[..choice / 2].to_vec();
+        let nybble = index - 1;
+        if choice & 1 == 0 {
+            bytes.push(nybble);
+        } else {
+            bytes.push((prefix[choice / 2] & 0x0F) | (nybble << 4));
+        }
+
+        KeyRange {
+            bytes,
+            nybbles: choice + 1,
+            exact: false,
+        }
+    }
+
+    /// Returns true if the range is a single key rather than a prefix.
+    pub fn is_key(&self) -> bool {
+        self.exact
+    }
+
+    /// The longest whole-byte prefix shared by every key in the range, suitable for
+    /// `Trie::iter_prefix`. If the range is a single key, this is the key.
+    pub fn byte_prefix(&self) -> &[u8] {
+        &self.bytes[..self.nybbles / 2]
+    }
+
+    /// Returns true if the given key is in the range.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        if self.exact {
+            return key == self.byte_prefix();
+        }
+
+        key.len() * 2 >= self.nybbles && nybbles_eq(key, &self.bytes, self.nybbles)
+    }
+}
+
+// A range known to differ, with this trie's node for the range and the other trie's digest of it.
+type Pending<'a, K, V, D> = (
+    Option<&'a Node<K, V, Merkle<D>>>,
+    KeyRange,
+    Option<Output<D>>,
+);
+
+/// An iterator over the ranges of keys in which a Merkle trie differs from another, produced by
+/// `Trie::diff_by_hash`.
+pub struct DiffByHash<'a, K: 'a, V: 'a, D: 'a, F>
+where
+    K: Borrow<[u8]>,
+    V: Hash,
+    D: Digest,
+{
+    stack: Vec<Pending<'a, K, V, D>>,
+    remote: F,
+}
+
+impl<'a, K, V, D, F> Iterator for DiffByHash<'a, K, V, D, F>
+where
+    K: Borrow<[u8]>,
+    V: Hash,
+    D: Digest,
+    F: FnMut(&KeyRange) -> Option<Output<D>>,
+{
+    type Item = KeyRange;
+
+    fn next(&mut self) -> Option<KeyRange> {
+        while let Some((node, range, theirs)) = self.stack.pop() {
+            let (node, theirs) = match (node, theirs) {
+                (Some(node), Some(theirs)) => (node, theirs),
+                _ => return Some(range),
+            };
+
+            let branch = match *node {
+                Node::Branch(ref branch) => branch,
+
+                // A lone key of ours: if the other trie has nothing else in the range, the
+                // difference is in that key alone.
+                Node::Leaf(ref leaf) if !range.exact => {
+                    let key = KeyRange::key(leaf.key_slice());
+                    if Some(theirs) == (self.remote)(&key) {
+                        return Some(key);
+                    }
+                    return Some(range);
+                }
+
+                Node::Leaf(..) => return Some(range),
+            };
+
+            let prefix = branch.get_exemplar(&[]).key_slice();
+            let children = branch
+                .indexed_iter()
+                .map(|(index, child)| {
+                    let range = KeyRange::child(branch.choice(), prefix, index);
+                    let digest = (self.remote)(&range);
+                    (index, child, range, digest)
+                })
+                .collect::<Vec<_>>();
+
+            // Unless the other trie's children for the range make up its digest of the range,
+            // it has keys in the range which fall under none of our children.
+            let reported = children
+                .iter()
+                .map(|&(index, _, _, ref digest)| (index, MerkleDigest::<D>(digest.clone())));
+            if branch_digest::<D, _>(branch.choice(), reported) != theirs {
+                return Some(range);
+            }
+
+            for (_, child, range, digest) in children.into_iter().rev() {
+                if node_digest(child) != digest {
+                    self.stack.push((Some(child), range, digest));
+                }
+            }
+        }
+
+        None
+    }
+}
@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use core::borrow::Borrow;
 use core::fmt;
 use core::mem;
@@ -5,8 +6,9 @@ use core::mem;
 use unreachable::UncheckedOptionExt;
 
 use iter::{IntoIter, Iter, IterMut};
+use measure::{Measure, Monoid};
 use sparse::Sparse;
-use util::{nybble_index, nybble_mismatch};
+use util::{nybble_index, nybble_mismatch, prefetch};
 
 // A leaf in the trie.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -33,15 +35,42 @@ impl<K: Borrow<[u8]>, V> Leaf<K, V> {
 // other branches - the 0th entry, if it exists in the sparse array, is the "head" of the branch,
 // containing a key/value pair corresponding to the leaf which would otherwise occupy the location
 // of the branch in the trie.
-#[derive(Clone, PartialEq, Eq)]
-pub struct Branch<K, V> {
+//
+// A branch also caches the summary under `M` of every entry beneath it. Whenever a child is
+// borrowed mutably, the branch is marked stale, and its summary is recomputed from its children
+// until the next insertion or removal through it brings the cache up to date again. With the
+// default measure `()` the summary takes no space and is never recomputed.
+pub struct Branch<K, V, M: Measure<K, V> = ()> {
     // The nybble that this `Branch` cares about. Entries in the `entries` sparse array correspond
     // to different values of the nybble at the choice point for given keys.
     choice: usize,
-    entries: Sparse<Node<K, V>>,
+    entries: Sparse<Node<K, V, M>>,
+    summary: M::Summary,
 }
 
-impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Branch<K, V> {
+impl<K: Clone, V: Clone, M: Measure<K, V>> Clone for Branch<K, V, M>
+where
+    M::Summary: Clone,
+{
+    fn clone(&self) -> Self {
+        Branch {
+            choice: self.choice,
+            entries: self.entries.clone(),
+            summary: self.summary.clone(),
+        }
+    }
+}
+
+// The summary is determined by the entries, so it takes no part in comparisons.
+impl<K: PartialEq, V: PartialEq, M: Measure<K, V>> PartialEq for Branch<K, V, M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.choice == other.choice && self.entries == other.entries
+    }
+}
+
+impl<K: Eq, V: Eq, M: Measure<K, V>> Eq for Branch<K, V, M> {}
+
+impl<K: fmt::Debug, V: fmt::Debug, M: Measure<K, V>> fmt::Debug for Branch<K, V, M> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Branch")
             .field("choice", &self.choice)
@@ -50,16 +79,31 @@ impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Branch<K, V> {
     }
 }
 
-impl<K: Borrow<[u8]>, V> Branch<K, V> {
+impl<K: Borrow<[u8]>, V, M: Measure<K, V>> Branch<K, V, M> {
     // Create an empty `Branch` with the given choice point.
     #[inline]
-    pub fn new(choice: usize) -> Branch<K, V> {
+    pub fn new(choice: usize) -> Branch<K, V, M> {
         Branch {
             choice,
             entries: Sparse::new(),
+            summary: M::Summary::empty(),
         }
     }
 
+    // Reassemble a `Branch` from its choice point, the bitmap of its occupied child slots and its
+    // children in nybble order. The caller is responsible for the children actually belonging in
+    // the slots they are given.
+    #[inline]
+    pub fn from_parts(choice: usize, bitmap: u32, entries: Vec<Node<K, V, M>>) -> Branch<K, V, M> {
+        let mut branch = Branch {
+            choice,
+            entries: Sparse::from_parts(bitmap, entries),
+            summary: M::Summary::empty(),
+        };
+        branch.resummarize();
+        branch
+    }
+
     // Return the nybble index corresponding to the branch's choice point in the given key.
     #[inline]
     pub fn index(&self, key: &[u8]) -> u8 {
@@ -79,7 +123,8 @@ impl<K: Borrow<[u8]>, V> Branch<K, V> {
     }
 
     #[inline]
-    pub fn entry_mut(&mut self, index: u8) -> &mut Node<K, V> {
+    pub fn entry_mut(&mut self, index: u8) -> &mut Node<K, V, M> {
+        self.invalidate();
         let entry = self.entries.get_mut(index);
         debug_assert!(entry.is_some());
         unsafe { entry.unchecked_unwrap() }
@@ -87,67 +132,52 @@ impl<K: Borrow<[u8]>, V> Branch<K, V> {
 
     // Get the child node corresponding to the given key.
     #[inline]
-    pub fn child(&self, key: &[u8]) -> Option<&Node<K, V>> {
+    pub fn child(&self, key: &[u8]) -> Option<&Node<K, V, M>> {
         self.entries.get(nybble_index(self.choice, key.borrow()))
     }
 
     // Mutable version of `Branch::child`.
     #[inline]
-    pub fn child_mut(&mut self, key: &[u8]) -> Option<&mut Node<K, V>> {
+    pub fn child_mut(&mut self, key: &[u8]) -> Option<&mut Node<K, V, M>> {
+        self.invalidate();
         self.entries
             .get_mut(nybble_index(self.choice, key.borrow()))
     }
 
-    // Immutably borrow the leaf for the given key, if it exists, mutually recursing through
-    // `Node::get`.
+    // Raw pointer version of `Branch::child_mut`, for handing out several mutable borrows at once.
     #[inline]
-    pub fn get(&self, key: &[u8]) -> Option<&Leaf<K, V>> {
-        match self.child(key.borrow()) {
-            Some(child) => child.get(key),
-            None => None,
-        }
-    }
-
-    // Mutably borrow the value for the given key, if it exists, mutually recursing through
-    // `Node::get_mut`.
-    #[inline]
-    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut Leaf<K, V>> {
-        self.child_mut(key.borrow())
-            .and_then(|node| node.get_mut(key))
+    pub fn child_ptr(&mut self, key: &[u8]) -> Option<*mut Node<K, V, M>> {
+        self.invalidate();
+        self.entries.get_ptr(nybble_index(self.choice, key))
     }
 
     // Retrieve the node which contains the exemplar. This does not recurse and return the actual
     // exemplar - just the node which might be or contain it.
     #[inline]
-    pub fn exemplar(&self, key: &[u8]) -> &Node<K, V> {
+    pub fn exemplar(&self, key: &[u8]) -> &Node<K, V, M> {
         self.entries
             .get_or_any(nybble_index(self.choice, key.borrow()))
     }
 
     // As `Branch::exemplar` but for mutable borrows.
     #[inline]
-    pub fn exemplar_mut(&mut self, key: &[u8]) -> &mut Node<K, V> {
+    pub fn exemplar_mut(&mut self, key: &[u8]) -> &mut Node<K, V, M> {
+        self.invalidate();
         self.entries
             .get_or_any_mut(nybble_index(self.choice, key.borrow()))
     }
 
-    // Immutably borrow the exemplar for the given key, mutually recursing through
+    // Immutably borrow the exemplar for the given key, continuing the descent with
     // `Node::get_exemplar`.
     #[inline]
     pub fn get_exemplar(&self, key: &[u8]) -> &Leaf<K, V> {
-        self.exemplar(key.borrow()).get_exemplar(key)
-    }
-
-    // Mutably borrow the exemplar for the given key, mutually recursing through
-    // `Node::get_exemplar_mut`.
-    #[inline]
-    pub fn get_exemplar_mut(&mut self, key: &[u8]) -> &mut Leaf<K, V> {
-        self.exemplar_mut(key.borrow()).get_exemplar_mut(key)
+        self.exemplar(key).get_exemplar(key)
     }
 
     // Convenience method for inserting a leaf into the branch's sparse array.
     #[inline]
     pub fn insert_leaf(&mut self, leaf: Leaf<K, V>) -> &mut Leaf<K, V> {
+        self.invalidate();
         let node_mut = self.entries.insert(
             nybble_index(self.choice, leaf.key_slice()),
             Node::Leaf(leaf),
@@ -158,7 +188,8 @@ impl<K: Borrow<[u8]>, V> Branch<K, V> {
 
     // Convenience method for inserting a branch into the branch's sparse array.
     #[inline]
-    pub fn insert_branch(&mut self, index: u8, branch: Branch<K, V>) -> &mut Branch<K, V> {
+    pub fn insert_branch(&mut self, index: u8, branch: Branch<K, V, M>) -> &mut Branch<K, V, M> {
+        self.invalidate();
         let node_mut = self.entries.insert(index, Node::Branch(branch));
 
         unsafe { node_mut.unwrap_branch_mut() }
@@ -167,19 +198,32 @@ impl<K: Borrow<[u8]>, V> Branch<K, V> {
     // Assuming that the provided index is valid, remove the node with that nybble index and
     // return it.
     #[inline]
-    pub fn remove(&mut self, index: u8) -> Node<K, V> {
+    pub fn remove(&mut self, index: u8) -> Node<K, V, M> {
+        self.invalidate();
         self.entries.remove(index)
     }
 
     // Assuming that the branch node has only one element back, remove it and return it in
     // preparation for replacement with a leaf.
     #[inline]
-    pub fn clear_last(&mut self) -> Node<K, V> {
+    pub fn clear_last(&mut self) -> Node<K, V, M> {
         self.entries.clear_last()
     }
 }
 
-impl<K, V> Branch<K, V> {
+impl<K, V, M: Measure<K, V>> Branch<K, V, M> {
+    // The nybble index at which this branch discriminates between its children.
+    #[inline]
+    pub fn choice(&self) -> usize {
+        self.choice
+    }
+
+    // The bitmap of occupied child slots, as stored by the branch's sparse array.
+    #[inline]
+    pub fn bitmap(&self) -> u32 {
+        self.entries.bitmap()
+    }
+
     // Count the number of entries stored in this branch. This traverses all subnodes of the
     // branch, so it is relatively expensive.
     #[inline]
@@ -187,20 +231,72 @@ impl<K, V> Branch<K, V> {
         self.entries.iter().map(Node::count).sum()
     }
 
+    // Returns true if a child of the branch may have changed since its summary was computed. The
+    // ancestors of a stale branch are always stale too.
+    #[inline]
+    pub fn is_stale(&self) -> bool {
+        self.entries.flag()
+    }
+
+    // Mark the cached summary as out of date, ahead of handing out a mutable borrow of a child.
+    #[inline]
+    fn invalidate(&mut self) {
+        if mem::size_of::<M::Summary>() != 0 {
+            self.entries.set_flag(true);
+        }
+    }
+
+    // Bring the cached summary up to date, along with those of any stale branches beneath.
+    #[inline]
+    pub fn resummarize(&mut self) {
+        if mem::size_of::<M::Summary>() != 0 {
+            for child in self.entries.iter_mut() {
+                if let Node::Branch(ref mut branch) = *child {
+                    if branch.is_stale() {
+                        branch.resummarize();
+                    }
+                }
+            }
+
+            self.summary = self.summarize_children();
+            self.entries.set_flag(false);
+        }
+    }
+
+    // Compute the summary of the branch from those of its children, ignoring the cache.
     #[inline]
-    pub fn iter(&self) -> ::core::slice::Iter<Node<K, V>> {
+    fn summarize_children(&self) -> M::Summary {
+        M::summarize_branch(
+            self.choice,
+            self.indexed_iter()
+                .map(|(index, child)| (index, child.summarize(&M::Summary::empty()))),
+        )
+    }
+
+    // Iterate over the children of the branch in order, along with their nybble indices.
+    #[inline]
+    pub fn indexed_iter(&self) -> impl Iterator<Item = (u8, &Node<K, V, M>)> {
+        let bitmap = self.bitmap();
+        (0..17u8)
+            .filter(move |&index| bitmap & (1 << index) != 0)
+            .zip(self.entries.iter())
+    }
+
+    #[inline]
+    pub fn iter(&self) -> ::core::slice::Iter<Node<K, V, M>> {
         self.entries.iter()
     }
 
     #[inline]
-    pub fn iter_mut(&mut self) -> ::core::slice::IterMut<Node<K, V>> {
+    pub fn iter_mut(&mut self) -> ::core::slice::IterMut<Node<K, V, M>> {
+        self.invalidate();
         self.entries.iter_mut()
     }
 }
 
-impl<K, V> IntoIterator for Branch<K, V> {
-    type IntoIter = ::alloc::vec::IntoIter<Node<K, V>>;
-    type Item = Node<K, V>;
+impl<K, V, M: Measure<K, V>> IntoIterator for Branch<K, V, M> {
+    type IntoIter = ::alloc::vec::IntoIter<Node<K, V, M>>;
+    type Item = Node<K, V, M>;
 
     #[inline]
     fn into_iter(self) -> Self::IntoIter {
@@ -209,13 +305,36 @@ impl<K, V> IntoIterator for Branch<K, V> {
 }
 
 // A node in the trie. `K` must be `ToOwned` because the `Owned` version is what we store.
-#[derive(Clone, PartialEq, Eq)]
-pub enum Node<K, V> {
+pub enum Node<K, V, M: Measure<K, V> = ()> {
     Leaf(Leaf<K, V>),
-    Branch(Branch<K, V>),
+    Branch(Branch<K, V, M>),
 }
 
-impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Node<K, V> {
+impl<K: Clone, V: Clone, M: Measure<K, V>> Clone for Node<K, V, M>
+where
+    M::Summary: Clone,
+{
+    fn clone(&self) -> Self {
+        match *self {
+            Node::Leaf(ref leaf) => Node::Leaf(leaf.clone()),
+            Node::Branch(ref branch) => Node::Branch(branch.clone()),
+        }
+    }
+}
+
+impl<K: PartialEq, V: PartialEq, M: Measure<K, V>> PartialEq for Node<K, V, M> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Node::Leaf(l), Node::Leaf(r)) => l == r,
+            (Node::Branch(l), Node::Branch(r)) => l == r,
+            _ => false,
+        }
+    }
+}
+
+impl<K: Eq, V: Eq, M: Measure<K, V>> Eq for Node<K, V, M> {}
+
+impl<K: fmt::Debug, V: fmt::Debug, M: Measure<K, V>> fmt::Debug for Node<K, V, M> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Node::Leaf(ref leaf) => f
@@ -232,7 +351,7 @@ impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Node<K, V> {
     }
 }
 
-impl<K: Borrow<[u8]>, V> Node<K, V> {
+impl<K: Borrow<[u8]>, V, M: Measure<K, V>> Node<K, V, M> {
     // The following `unwrap_` functions are used for (at times) efficiently circumventing the
     // borrowchecker. All of them use `debug_unreachable!` internally, which means that in release,
     // a misuse can cause undefined behavior (because the tried-to-unwrap-wrong-thing code path is
@@ -263,7 +382,7 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
     }
 
     #[inline]
-    pub unsafe fn unwrap_branch_ref(&self) -> &Branch<K, V> {
+    pub unsafe fn unwrap_branch_ref(&self) -> &Branch<K, V, M> {
         match *self {
             Node::Leaf(..) => debug_unreachable!(),
             Node::Branch(ref branch) => branch,
@@ -271,30 +390,69 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
     }
 
     #[inline]
-    pub unsafe fn unwrap_branch_mut(&mut self) -> &mut Branch<K, V> {
+    pub unsafe fn unwrap_branch_mut(&mut self) -> &mut Branch<K, V, M> {
         match *self {
             Node::Leaf(..) => debug_unreachable!(),
             Node::Branch(ref mut branch) => branch,
         }
     }
 
+    // Hint that the children of this node are about to be visited, if it is a branch. This is a
+    // no-op unless the `prefetch` feature is enabled.
+    #[inline(always)]
+    fn prefetch(&self) {
+        if let Node::Branch(ref branch) = *self {
+            prefetch(branch.entries.as_ptr());
+        }
+    }
+
     // Borrow the associated leaf for a given key, if it exists in the trie.
     pub fn get(&self, key: &[u8]) -> Option<&Leaf<K, V>> {
-        match *self {
-            Node::Leaf(ref leaf) if leaf.key_slice() == key => Some(leaf),
-            Node::Leaf(..) => None,
+        let mut node = self;
 
-            Node::Branch(ref branch) => branch.get(key),
+        loop {
+            match *node {
+                Node::Leaf(ref leaf) if leaf.key_slice() == key => return Some(leaf),
+                Node::Leaf(..) => return None,
+
+                Node::Branch(ref branch) => node = branch.child(key)?,
+            }
+
+            node.prefetch();
         }
     }
 
     // Mutably borrow the associated leaf for a given key, if it exists in the trie.
     pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut Leaf<K, V>> {
-        match *self {
-            Node::Leaf(ref mut leaf) if leaf.key_slice() == key => Some(leaf),
-            Node::Leaf(..) => None,
+        let mut node = self;
+
+        loop {
+            match *node {
+                Node::Leaf(ref mut leaf) if leaf.key_slice() == key => return Some(leaf),
+                Node::Leaf(..) => return None,
+
+                Node::Branch(ref mut branch) => node = branch.child_mut(key)?,
+            }
 
-            Node::Branch(ref mut branch) => branch.get_mut(key),
+            node.prefetch();
+        }
+    }
+
+    // Find the associated leaf for a given key, if it exists in the trie, walking down from a raw
+    // pointer to a node. The only mutable references created are to the branches on the way down
+    // and to the leaf itself, never to a branch's entries, so pointers to leaves found in earlier
+    // walks stay valid as long as their keys differ from this one.
+    //
+    // PRECONDITION:
+    // - `node` is valid for reads and writes.
+    pub unsafe fn get_ptr(mut node: *mut Node<K, V, M>, key: &[u8]) -> Option<*mut Leaf<K, V>> {
+        loop {
+            match *node {
+                Node::Leaf(ref mut leaf) if leaf.key_slice() == key => return Some(leaf),
+                Node::Leaf(..) => return None,
+
+                Node::Branch(ref mut branch) => node = branch.child_ptr(key)?,
+            }
         }
     }
 
@@ -307,17 +465,29 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
     // If the key already exists in the trie, then the leaf containing it is returned as the
     // exemplar.
     pub fn get_exemplar(&self, key: &[u8]) -> &Leaf<K, V> {
-        match *self {
-            Node::Leaf(ref leaf) => leaf,
-            Node::Branch(ref branch) => branch.get_exemplar(key),
+        let mut node = self;
+
+        loop {
+            match *node {
+                Node::Leaf(ref leaf) => return leaf,
+                Node::Branch(ref branch) => node = branch.exemplar(key),
+            }
+
+            node.prefetch();
         }
     }
 
     // Mutably borrow the exemplar for a given key.
     pub fn get_exemplar_mut(&mut self, key: &[u8]) -> &mut Leaf<K, V> {
-        match *self {
-            Node::Leaf(ref mut leaf) => leaf,
-            Node::Branch(ref mut branch) => branch.get_exemplar_mut(key),
+        let mut node = self;
+
+        loop {
+            match *node {
+                Node::Leaf(ref mut leaf) => return leaf,
+                Node::Branch(ref mut branch) => node = branch.exemplar_mut(key),
+            }
+
+            node.prefetch();
         }
     }
 
@@ -326,27 +496,24 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
     //
     // PRECONDITION:
     // - There exists at least one node in the trie with the given prefix.
-    pub fn get_prefix_validated<'a>(&'a self, prefix: &[u8]) -> &'a Node<K, V> {
-        match *self {
-            Node::Leaf(..) => self,
-            Node::Branch(ref branch) => {
-                if branch.choice >= prefix.len() * 2 {
-                    self
-                } else {
-                    let child_opt = branch.child(prefix);
+    pub fn get_prefix_validated<'a>(&'a self, prefix: &[u8]) -> &'a Node<K, V, M> {
+        let mut node = self;
 
+        loop {
+            match *node {
+                Node::Branch(ref branch) if branch.choice < prefix.len() * 2 => {
                     // unsafe: child must exist in the trie - prefix'd nodes must exist.
-                    let child = unsafe { child_opt.unchecked_unwrap() };
-
-                    child.get_prefix_validated(prefix)
+                    node = unsafe { branch.child(prefix).unchecked_unwrap() };
                 }
+
+                _ => return node,
             }
         }
     }
 
     // Borrow the node which contains all and only entries with keys beginning with
     // `prefix`.
-    pub fn get_prefix<'a>(&'a self, prefix: &[u8]) -> Option<&'a Node<K, V>> {
+    pub fn get_prefix<'a>(&'a self, prefix: &[u8]) -> Option<&'a Node<K, V, M>> {
         match *self {
             Node::Leaf(ref leaf) if leaf.key_slice().starts_with(prefix) => Some(self),
             Node::Branch(ref branch)
@@ -364,32 +531,28 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
     //
     // PRECONDITION:
     // - There exists at least one node in the trie with the given prefix.
-    pub fn get_prefix_validated_mut<'a>(&'a mut self, prefix: &[u8]) -> &'a mut Node<K, V> {
-        match *self {
-            Node::Leaf(..) => self,
-            Node::Branch(..) => {
-                // unsafe: self has been match'd as a branch.
-                if unsafe { self.unwrap_branch_mut() }.choice >= prefix.len() * 2 {
-                    self
-                } else {
-                    // unsafe: self has been match'd as a branch.
-                    let branch_mut = unsafe { self.unwrap_branch_mut() };
-
-                    let child_opt = branch_mut.child_mut(prefix);
+    pub fn get_prefix_validated_mut<'a>(&'a mut self, prefix: &[u8]) -> &'a mut Node<K, V, M> {
+        let mut node = self;
 
-                    // unsafe: child must exist as there must exist nodes with the given prefix in
-                    // the trie.
-                    let child = unsafe { child_opt.unchecked_unwrap() };
-
-                    child.get_prefix_validated_mut(prefix)
-                }
+        loop {
+            match *node {
+                Node::Branch(ref branch) if branch.choice < prefix.len() * 2 => {}
+                _ => return node,
             }
+
+            // unsafe: node has been match'd as a branch, and the child must exist as there must
+            // exist nodes with the given prefix in the trie.
+            node = unsafe {
+                node.unwrap_branch_mut()
+                    .child_mut(prefix)
+                    .unchecked_unwrap()
+            };
         }
     }
 
     // Mutably borrow the node which contains all and only entries with keys beginning with
     // `prefix`.
-    pub fn get_prefix_mut<'a>(&'a mut self, prefix: &[u8]) -> Option<&'a mut Node<K, V>> {
+    pub fn get_prefix_mut<'a>(&'a mut self, prefix: &[u8]) -> Option<&'a mut Node<K, V, M>> {
         match *self {
             Node::Leaf(..) => {
                 // unsafe: self has been match'd as a leaf.
@@ -422,7 +585,8 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
     }
 
     // Insert into the trie with a given "graft point" - the first point of nybble mismatch
-    // between the key and an "exemplar" key.
+    // between the key and an "exemplar" key - and return the branch node which the new leaf was
+    // inserted into, along with the leaf's index in it. The branches on the way are left stale.
     //
     // PRECONDITION:
     // - The key is not already in the trie.
@@ -432,45 +596,74 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
         graft_nybble: u8,
         key: K,
         val: V,
-    ) -> &mut V {
+    ) -> (&mut Node<K, V, M>, u8) {
+        let (node, index) = self.graft(graft, graft_nybble, key, val, false);
+
+        // unsafe: `node` is the branch the leaf was just inserted into, which nothing has touched
+        // since.
+        (unsafe { &mut *node }, index)
+    }
+
+    // Insert at the given graft point as `Node::insert_with_graft_point` does, returning a pointer
+    // to the branch the leaf was inserted into. If `resummarize` is set, the summaries of the
+    // branches on the way are brought up to date as the descent unwinds, and the pointer must not
+    // be used.
+    fn graft(
+        &mut self,
+        graft: usize,
+        graft_nybble: u8,
+        key: K,
+        val: V,
+        resummarize: bool,
+    ) -> (*mut Node<K, V, M>, u8) {
         let node = mem::replace(self, Node::Branch(Branch::new(graft)));
-        let graft_branch = match node {
+        match node {
             Node::Leaf(leaf) => {
                 // unsafe: we've just replaced self with a branch.
-                let graft_branch = unsafe { self.unwrap_branch_mut() };
-                graft_branch.insert_leaf(leaf);
-                graft_branch
+                unsafe { self.unwrap_branch_mut() }.insert_leaf(leaf);
             }
             Node::Branch(branch) => {
                 if branch.choice <= graft {
                     *self = Node::Branch(branch);
-                    if let Node::Branch(ref mut branch) = *self {
-                        let index = branch.index(key.borrow());
-
-                        return if branch.has_entry(index) {
-                            branch.entry_mut(index).insert_with_graft_point(
-                                graft,
-                                graft_nybble,
-                                key,
-                                val,
-                            )
-                        } else {
-                            &mut branch.insert_leaf(Leaf::new(key, val)).val
-                        };
+
+                    // unsafe: self has just been restored to a branch.
+                    let index = unsafe { self.unwrap_branch_ref() }.index(key.borrow());
+                    if unsafe { self.unwrap_branch_ref() }.has_entry(index) {
+                        let grafted = unsafe { self.unwrap_branch_mut() }.entry_mut(index).graft(
+                            graft,
+                            graft_nybble,
+                            key,
+                            val,
+                            resummarize,
+                        );
+
+                        if resummarize {
+                            unsafe { self.unwrap_branch_mut() }.resummarize();
+                        }
+
+                        return grafted;
                     }
-                    unreachable!();
+                } else {
+                    // unsafe: we've just replaced self with a branch.
+                    unsafe { self.unwrap_branch_mut() }.insert_branch(graft_nybble, branch);
                 }
-                // unsafe: we've just replaced self with a branch.
-                let graft_branch = unsafe { self.unwrap_branch_mut() };
-                graft_branch.insert_branch(graft_nybble, branch);
-                graft_branch
             }
-        };
+        }
+
+        // unsafe: in every case above which falls through, self is a branch.
+        let graft_branch = unsafe { self.unwrap_branch_mut() };
+        let index = graft_branch.index(key.borrow());
+        graft_branch.insert_leaf(Leaf::new(key, val));
 
-        &mut graft_branch.insert_leaf(Leaf::new(key, val)).val
+        if resummarize {
+            graft_branch.resummarize();
+        }
+
+        (self, index)
     }
 
-    // Insert a node into a nonempty trie.
+    // Insert a node into a nonempty trie, bringing the summaries of the branches on the way up to
+    // date.
     pub fn insert(&mut self, key: K, val: V) -> Option<V> {
         match *self {
             Node::Leaf(..) => {
@@ -491,6 +684,7 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
 
                         branch.insert_leaf(Leaf::new(key, val));
                         branch.insert_leaf(leaf);
+                        branch.resummarize();
 
                         None
                     }
@@ -498,70 +692,135 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
             }
 
             Node::Branch(..) => {
-                let (mismatch, mismatch_nybble) = {
-                    let exemplar = self.get_exemplar_mut(key.borrow());
-
-                    let mismatch_opt = nybble_mismatch(exemplar.key_slice(), key.borrow());
-
-                    match mismatch_opt {
-                        Some(mismatch) => (mismatch, nybble_index(mismatch, exemplar.key_slice())),
-                        None => return Some(mem::replace(&mut exemplar.val, val)),
-                    }
+                let (mismatch, mismatch_nybble, val) = match self.replace(key.borrow(), val) {
+                    Ok(old) => return Some(old),
+                    Err(graft) => graft,
                 };
 
-                self.insert_with_graft_point(mismatch, mismatch_nybble, key, val);
+                self.graft(mismatch, mismatch_nybble, key, val, true);
 
                 None
             }
         }
     }
 
+    // Replace the value of the leaf with the given key and return the old value. If there is no
+    // such leaf, the value is handed back along with the key's first nybble mismatch with its
+    // exemplar and the exemplar's nybble at that point.
+    //
+    // Measured tries recompute the summaries on the path to a replaced value as the descent
+    // unwinds. Should the key be missing, the branches on the path are left stale, for the graft
+    // which follows to bring up to date.
+    fn replace(&mut self, key: &[u8], val: V) -> Result<V, (usize, u8, V)> {
+        if mem::size_of::<M::Summary>() != 0 {
+            if let Node::Branch(ref mut branch) = *self {
+                let replaced = branch.exemplar_mut(key).replace(key, val);
+                if replaced.is_ok() {
+                    branch.resummarize();
+                }
+                return replaced;
+            }
+        }
+
+        let exemplar = self.get_exemplar_mut(key);
+        match nybble_mismatch(exemplar.key_slice(), key) {
+            Some(mismatch) => Err((mismatch, nybble_index(mismatch, exemplar.key_slice()), val)),
+            None => Ok(mem::replace(&mut exemplar.val, val)),
+        }
+    }
+
     // `remove_validated` assumes that it is being called on a `Node::Branch`.
     //
     // PRECONDITION:
     // - `self` is of the `Node::Branch` variant.
     pub fn remove_validated(&mut self, key: &[u8]) -> Option<Leaf<K, V>> {
-        match *self {
-            Node::Leaf(..) => unsafe { debug_unreachable!() },
-            Node::Branch(..) => {
-                let leaf = {
-                    // unsafe: self has been match'd as branch.
-                    let branch = unsafe { self.unwrap_branch_mut() };
-                    let index = branch.index(key);
+        // The summaries on the path of a measured trie must be recomputed as the descent unwinds,
+        // so it cannot be iterative.
+        if mem::size_of::<M::Summary>() != 0 {
+            return self.remove_resummarized(key);
+        }
 
-                    match branch.child_mut(key) {
-                        // Removing a leaf means waiting for `self` to be available so we can try
-                        // to compress. Also we can't remove in this match arm since `branch` is
-                        // borrowed.
-                        Some(&mut Node::Leaf(ref leaf)) if leaf.key_slice() == key => {}
+        let mut node = self;
 
-                        Some(child @ &mut Node::Branch(..)) => return child.remove_validated(key),
-                        _ => return None,
-                    };
+        loop {
+            let index = {
+                // unsafe: node is always a branch - we only ever descend into branches.
+                let branch = unsafe { node.unwrap_branch_ref() };
+                let index = branch.index(key);
 
-                    let node = branch.remove(index);
-                    unsafe { node.unwrap_leaf() }
-                };
+                match branch.entries.get(index) {
+                    // Removing a leaf means compressing the branch it was removed from, so stop
+                    // the descent here.
+                    Some(Node::Leaf(leaf)) if leaf.key_slice() == key => index,
 
-                // We removed a leaf. The branch's arity has reduced - we may be able to compress.
-                // unsafe: self hass been match'd as a branch.
-                if unsafe { self.unwrap_branch_mut() }.is_singleton() {
-                    let node = {
-                        // unsafe: self has been match'd as a branch.
-                        let branch_mut = unsafe { self.unwrap_branch_mut() };
+                    Some(Node::Branch(..)) => {
+                        // unsafe: same rationale.
+                        node = unsafe { node.unwrap_branch_mut() }.entry_mut(index);
+                        continue;
+                    }
 
-                        branch_mut.clear_last()
-                    };
-                    *self = node;
+                    _ => return None,
+                }
+            };
+
+            // unsafe: node is a branch, and index has been match'd as a leaf in it.
+            let branch = unsafe { node.unwrap_branch_mut() };
+            let leaf = unsafe { branch.remove(index).unwrap_leaf() };
+
+            // We removed a leaf. The branch's arity has reduced - we may be able to compress.
+            if branch.is_singleton() {
+                let last = branch.clear_last();
+                *node = last;
+            }
+
+            return Some(leaf);
+        }
+    }
+
+    // As `Node::remove_validated`, but recursing, and bringing the summaries of the branches on
+    // the path up to date on the way back up.
+    //
+    // PRECONDITION:
+    // - `self` is of the `Node::Branch` variant.
+    fn remove_resummarized(&mut self, key: &[u8]) -> Option<Leaf<K, V>> {
+        let leaf = {
+            // unsafe: only branches are descended into.
+            let branch = unsafe { self.unwrap_branch_mut() };
+            let index = branch.index(key);
+
+            match branch.child_mut(key) {
+                Some(&mut Node::Leaf(ref leaf)) if leaf.key_slice() == key => {}
+
+                Some(child @ &mut Node::Branch(..)) => {
+                    let leaf = child.remove_resummarized(key);
+                    branch.resummarize();
+                    return leaf;
                 }
 
-                Some(leaf)
+                _ => {
+                    branch.resummarize();
+                    return None;
+                }
             }
+
+            // unsafe: index has been match'd as a leaf.
+            unsafe { branch.remove(index).unwrap_leaf() }
+        };
+
+        // unsafe: self is a branch.
+        let branch = unsafe { self.unwrap_branch_mut() };
+        if branch.is_singleton() {
+            let last = branch.clear_last();
+            *self = last;
+        } else {
+            branch.resummarize();
         }
+
+        Some(leaf)
     }
 
     // Remove a node from the trie with the given key and return its value, if it exists.
-    pub fn remove(root: &mut Option<Node<K, V>>, key: &[u8]) -> Option<Leaf<K, V>> {
+    pub fn remove(root: &mut Option<Node<K, V, M>>, key: &[u8]) -> Option<Leaf<K, V>> {
         match *root {
             Some(Node::Leaf(..))
                 // unsafe: root has been match'd as some branch.
@@ -584,7 +843,7 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
     // PRECONDITION:
     // - There exists a node in the trie with the given prefix.
     // - `self` is of the `Branch` variant.
-    pub fn remove_prefix_validated(&mut self, prefix: &[u8]) -> Option<Node<K, V>> {
+    pub fn remove_prefix_validated(&mut self, prefix: &[u8]) -> Option<Node<K, V, M>> {
         match *self {
             Node::Leaf(..) => unsafe { debug_unreachable!() },
             Node::Branch(..) => {
@@ -600,10 +859,15 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
                             if child_branch.choice >= prefix.len() * 2 => {}
 
                         Some(child @ &mut Node::Branch(..)) => {
-                            return child.remove_prefix_validated(prefix)
+                            let removed = child.remove_prefix_validated(prefix);
+                            branch.resummarize();
+                            return removed;
                         }
 
-                        _ => return None,
+                        _ => {
+                            branch.resummarize();
+                            return None;
+                        }
                     }
 
                     branch.remove(index)
@@ -614,6 +878,9 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
                     // unsafe: same rationale.
                     let node = unsafe { self.unwrap_branch_mut() }.clear_last();
                     *self = node;
+                } else {
+                    // unsafe: same rationale.
+                    unsafe { self.unwrap_branch_mut() }.resummarize();
                 }
 
                 Some(prefix_node)
@@ -623,7 +890,7 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
 
     // Remove the node which holds all and only elements starting with the given prefix and return
     // it, if it exists.
-    pub fn remove_prefix(root: &mut Option<Node<K, V>>, prefix: &[u8]) -> Option<Node<K, V>> {
+    pub fn remove_prefix(root: &mut Option<Node<K, V, M>>, prefix: &[u8]) -> Option<Node<K, V, M>> {
         match *root {
             Some(Node::Leaf(..))
                 // unsafe: root has been matched as some leaf.
@@ -651,9 +918,143 @@ impl<K: Borrow<[u8]>, V> Node<K, V> {
             _ => None,
         }
     }
+
+    // Remove every entry beginning with `prefix` for which `f` returns false, returning the number
+    // of entries removed and whether anything remains of the node. If nothing remains, the node
+    // is left in an unspecified state and must be removed by the caller.
+    fn retain_prefix_validated<F>(&mut self, prefix: &[u8], f: &mut F) -> (usize, bool)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let descend = match *self {
+            Node::Leaf(ref leaf) if leaf.key_slice().starts_with(prefix) => false,
+            Node::Branch(ref branch) if branch.choice < prefix.len() * 2 => true,
+            Node::Branch(ref branch)
+                if branch.get_exemplar(prefix).key_slice().starts_with(prefix) =>
+            {
+                false
+            }
+            _ => return (0, true),
+        };
+
+        if !descend {
+            return self.retain(f);
+        }
+
+        let removed = {
+            // unsafe: only branches are descended into.
+            let branch = unsafe { self.unwrap_branch_mut() };
+            let index = branch.index(prefix);
+            let (removed, keep) = match branch.entries.get_mut(index) {
+                Some(child) => child.retain_prefix_validated(prefix, f),
+                None => return (0, true),
+            };
+
+            if keep {
+                branch.resummarize();
+                return (removed, true);
+            }
+
+            branch.remove(index);
+            if !branch.is_singleton() {
+                branch.resummarize();
+                return (removed, true);
+            }
+
+            removed
+        };
+
+        // The branch has been left with a single child, which takes its place.
+        // unsafe: same rationale.
+        let node = unsafe { self.unwrap_branch_mut() }.clear_last();
+        *self = node;
+        (removed, true)
+    }
+
+    // Remove every entry beginning with `prefix` for which `f` returns false, returning the number
+    // of entries removed.
+    pub fn retain_prefix<F>(root: &mut Option<Node<K, V, M>>, prefix: &[u8], f: &mut F) -> usize
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let (removed, keep) = match *root {
+            Some(ref mut node) => node.retain_prefix_validated(prefix, f),
+            None => return 0,
+        };
+
+        if !keep {
+            *root = None;
+        }
+
+        removed
+    }
 }
 
-impl<K, V> Node<K, V> {
+impl<K, V, M: Measure<K, V>> Node<K, V, M> {
+    // Remove every entry beneath this node for which `f` returns false, returning the number of
+    // entries removed and whether anything remains of the node. Branches left with a single child
+    // are replaced by it; if nothing remains, the node must be removed by the caller.
+    pub fn retain<F>(&mut self, f: &mut F) -> (usize, bool)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let (removed, node) = match *self {
+            Node::Leaf(ref mut leaf) => {
+                let keep = f(&leaf.key, &mut leaf.val);
+                return (!keep as usize, keep);
+            }
+            Node::Branch(ref mut branch) => {
+                let mut removed = 0;
+                branch.entries.retain(|child| {
+                    let (n, keep) = child.retain(f);
+                    removed += n;
+                    keep
+                });
+
+                if branch.entries.len() != 1 {
+                    branch.resummarize();
+                    return (removed, !branch.entries.is_empty());
+                }
+
+                (removed, branch.entries.clear_last())
+            }
+        };
+
+        *self = node;
+        (removed, true)
+    }
+
+    // Borrow the leftmost leaf beneath this node. Every key beneath a branch agrees with it up to
+    // the branch's choice point.
+    pub fn first_leaf(&self) -> &Leaf<K, V> {
+        let mut node = self;
+
+        loop {
+            match *node {
+                Node::Leaf(ref leaf) => return leaf,
+                // unsafe: branches always have at least two children.
+                Node::Branch(ref branch) => {
+                    node = unsafe { branch.iter().next().unchecked_unwrap() }
+                }
+            }
+        }
+    }
+
+    // Borrow the rightmost leaf beneath this node.
+    pub fn last_leaf(&self) -> &Leaf<K, V> {
+        let mut node = self;
+
+        loop {
+            match *node {
+                Node::Leaf(ref leaf) => return leaf,
+                // unsafe: branches always have at least two children.
+                Node::Branch(ref branch) => {
+                    node = unsafe { branch.iter().next_back().unchecked_unwrap() }
+                }
+            }
+        }
+    }
+
     pub fn count(&self) -> usize {
         match *self {
             Node::Leaf(..) => 1,
@@ -661,17 +1062,32 @@ impl<K, V> Node<K, V> {
         }
     }
 
-    pub fn iter(&self) -> Iter<K, V> {
+    pub fn iter(&self) -> Iter<K, V, M> {
         Iter::new(self)
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<K, V> {
+    // Combine `acc` with the summary of every entry beneath this node. The summaries of stale
+    // branches are recomputed from their children, without updating the cache.
+    #[inline]
+    pub fn summarize(&self, acc: &M::Summary) -> M::Summary {
+        match *self {
+            Node::Leaf(ref leaf) => acc.combine(&M::measure(&leaf.key, &leaf.val)),
+            Node::Branch(ref branch) if branch.is_stale() => {
+                acc.combine(&branch.summarize_children())
+            }
+            Node::Branch(ref branch) => acc.combine(&branch.summary),
+        }
+    }
+}
+
+impl<K, V, M: Measure<K, V>> Node<K, V, M> {
+    pub fn iter_mut(&mut self) -> IterMut<K, V, M> {
         IterMut::new(self)
     }
 }
 
-impl<K, V> IntoIterator for Node<K, V> {
-    type IntoIter = IntoIter<K, V>;
+impl<K, V, M: Measure<K, V>> IntoIterator for Node<K, V, M> {
+    type IntoIter = IntoIter<K, V, M>;
     type Item = (K, V);
 
     fn into_iter(self) -> Self::IntoIter {
@@ -0,0 +1,574 @@
+//! Parallel iteration over and construction of tries through
+//! [rayon](https://github.com/rayon-rs/rayon), enabled by the `rayon` feature.
+//!
+//! Work is split at branch boundaries: the children of a branch are disjoint subtries, so each
+//! half of them can be handed to a different thread. Items are produced in the same order as the
+//! sequential iterators.
+
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::mem;
+use core::slice;
+
+use rayon::iter::plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer};
+use rayon::prelude::*;
+use unreachable::UncheckedOptionExt;
+
+use iter::{IntoIter, Iter, IterMut};
+use node::{Branch, Leaf, Node};
+use subtrie::{SubTrie, SubTrieMut};
+use trie::Trie;
+use util::{nybble_index, nybble_mismatch};
+
+// Below this many entries, `Trie::par_from_sorted` builds subtries on the current thread.
+const SEQUENTIAL_BUILD_THRESHOLD: usize = 1 << 12;
+
+// Branches deeper than this have their children merged on the current thread by `par_extend`.
+// Each level down divides the work by up to 17, so the first few levels give plenty of tasks.
+const PARALLEL_MERGE_DEPTH: usize = 3;
+
+/// A parallel iterator over immutable references to keys and values in a QP-trie.
+#[derive(Clone, Debug)]
+pub struct ParIter<'a, K: 'a, V: 'a> {
+    root: Option<&'a Node<K, V>>,
+}
+
+/// A parallel iterator over immutable references to keys and mutable references to values in a
+/// QP-trie.
+#[derive(Debug)]
+pub struct ParIterMut<'a, K: 'a, V: 'a> {
+    root: Option<&'a mut Node<K, V>>,
+}
+
+/// A parallel iterator over mutable references to values in a QP-trie.
+#[derive(Debug)]
+pub struct ParValuesMut<'a, K: 'a, V: 'a> {
+    inner: ParIterMut<'a, K, V>,
+}
+
+/// A parallel iterator over the keys and values in a QP-trie.
+#[derive(Debug)]
+pub struct IntoParIter<K, V> {
+    root: Option<Node<K, V>>,
+}
+
+impl<'a, K: Sync, V: Sync> ParallelIterator for ParIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        let nodes = self.root.map(slice::from_ref).unwrap_or(&[]);
+        bridge_unindexed(NodesProducer { nodes }, consumer)
+    }
+}
+
+impl<'a, K: Send + Sync, V: Send> ParallelIterator for ParIterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        let nodes = self.root.map(slice::from_mut).unwrap_or(&mut []);
+        bridge_unindexed(NodesMutProducer { nodes }, consumer)
+    }
+}
+
+impl<'a, K: Send + Sync, V: Send> ParallelIterator for ParValuesMut<'a, K, V> {
+    type Item = &'a mut V;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        self.inner.map(|(_, val)| val).drive_unindexed(consumer)
+    }
+}
+
+impl<K: Send, V: Send> ParallelIterator for IntoParIter<K, V> {
+    type Item = (K, V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        let nodes = self.root.into_iter().collect();
+        bridge_unindexed(NodesIntoProducer { nodes }, consumer)
+    }
+}
+
+// Each producer holds a run of sibling subtries. A run of several is split in half; a lone branch
+// is split by replacing it with its children, of which a branch always has at least two.
+
+struct NodesProducer<'a, K: 'a, V: 'a> {
+    nodes: &'a [Node<K, V>],
+}
+
+impl<'a, K: Sync, V: Sync> UnindexedProducer for NodesProducer<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn split(self) -> (Self, Option<Self>) {
+        let nodes = match self.nodes {
+            [Node::Branch(branch)] => branch.iter().as_slice(),
+            nodes if nodes.len() > 1 => nodes,
+            nodes => return (NodesProducer { nodes }, None),
+        };
+
+        let (left, right) = nodes.split_at(nodes.len() / 2);
+        (
+            NodesProducer { nodes: left },
+            Some(NodesProducer { nodes: right }),
+        )
+    }
+
+    fn fold_with<F>(self, mut folder: F) -> F
+    where
+        F: Folder<Self::Item>,
+    {
+        for node in self.nodes {
+            folder = folder.consume_iter(Iter::new(node));
+            if folder.full() {
+                break;
+            }
+        }
+        folder
+    }
+}
+
+struct NodesMutProducer<'a, K: 'a, V: 'a> {
+    nodes: &'a mut [Node<K, V>],
+}
+
+impl<'a, K: Send + Sync, V: Send> UnindexedProducer for NodesMutProducer<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn split(self) -> (Self, Option<Self>) {
+        let nodes = match self.nodes {
+            [Node::Branch(branch)] => branch.iter_mut().into_slice(),
+            nodes if nodes.len() > 1 => nodes,
+            nodes => return (NodesMutProducer { nodes }, None),
+        };
+
+        let mid = nodes.len() / 2;
+        let (left, right) = nodes.split_at_mut(mid);
+        (
+            NodesMutProducer { nodes: left },
+            Some(NodesMutProducer { nodes: right }),
+        )
+    }
+
+    fn fold_with<F>(self, mut folder: F) -> F
+    where
+        F: Folder<Self::Item>,
+    {
+        for node in self.nodes {
+            folder = folder.consume_iter(IterMut::new(node));
+            if folder.full() {
+                break;
+            }
+        }
+        folder
+    }
+}
+
+struct NodesIntoProducer<K, V> {
+    nodes: Vec<Node<K, V>>,
+}
+
+impl<K: Send, V: Send> UnindexedProducer for NodesIntoProducer<K, V> {
+    type Item = (K, V);
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        if self.nodes.len() == 1 {
+            match self.nodes.pop() {
+                Some(Node::Branch(branch)) => self.nodes.extend(branch),
+                Some(leaf) => {
+                    self.nodes.push(leaf);
+                    return (self, None);
+                }
+                None => unsafe { debug_unreachable!() },
+            }
+        } else if self.nodes.is_empty() {
+            return (self, None);
+        }
+
+        let right = self.nodes.split_off(self.nodes.len() / 2);
+        (self, Some(NodesIntoProducer { nodes: right }))
+    }
+
+    fn fold_with<F>(self, mut folder: F) -> F
+    where
+        F: Folder<Self::Item>,
+    {
+        for node in self.nodes {
+            folder = folder.consume_iter(IntoIter::new(node));
+            if folder.full() {
+                break;
+            }
+        }
+        folder
+    }
+}
+
+impl<K, V> Trie<K, V> {
+    /// Iterate over all elements in the trie in parallel.
+    pub fn par_iter(&self) -> ParIter<'_, K, V> {
+        ParIter { root: self.root() }
+    }
+
+    /// Iterate over all elements in the trie in parallel, given a mutable reference to the
+    /// associated value.
+    pub fn par_iter_mut(&mut self) -> ParIterMut<'_, K, V> {
+        ParIterMut {
+            root: self.root_mut(),
+        }
+    }
+
+    /// Iterate over all values in the trie in parallel, mutably.
+    pub fn par_values_mut(&mut self) -> ParValuesMut<'_, K, V> {
+        ParValuesMut {
+            inner: self.par_iter_mut(),
+        }
+    }
+}
+
+impl<K: Borrow<[u8]> + Send + Sync, V: Send> Trie<K, V> {
+    /// Build a trie in parallel from entries sorted by key, such as a sorted dump of another trie
+    /// or map. The entries are grouped by the nybble at which their keys first differ, and the
+    /// groups are built into subtries on separate threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys are not in strictly increasing byte order.
+    pub fn par_from_sorted(entries: Vec<(K, V)>) -> Trie<K, V> {
+        assert!(
+            entries
+                .windows(2)
+                .all(|pair| pair[0].0.borrow() < pair[1].0.borrow()),
+            "entries must be sorted by key without duplicates"
+        );
+
+        let count = entries.len();
+        if count == 0 {
+            return Trie::new();
+        }
+
+        Trie::from_root(Some(build_sorted(entries)), count)
+    }
+}
+
+// Build the subtrie holding the given entries, which are nonempty, sorted and distinct.
+fn build_sorted<K: Borrow<[u8]> + Send + Sync, V: Send>(mut entries: Vec<(K, V)>) -> Node<K, V> {
+    if entries.len() == 1 {
+        // unsafe: there is exactly one entry.
+        let (key, val) = unsafe { entries.pop().unchecked_unwrap() };
+        return Node::Leaf(Leaf::new(key, val));
+    }
+
+    let choice = {
+        let first = entries[0].0.borrow();
+        let last = entries[entries.len() - 1].0.borrow();
+
+        // The keys are sorted, so they all share the bytes that the first and last share, and the
+        // choice point must lie in the first byte at which those differ. It is the high nybble
+        // only if every key has that byte and agrees with the first on its low nybble.
+        // unsafe: the first and last keys are distinct.
+        let byte = unsafe { nybble_mismatch(first, last).unchecked_unwrap() } / 2;
+        let shared_low = entries.iter().all(|(key, _)| {
+            let key = key.borrow();
+            key.len() > byte && (key[byte] ^ first[byte]) & 0x0F == 0
+        });
+
+        if shared_low {
+            byte * 2 + 1
+        } else {
+            byte * 2
+        }
+    };
+
+    let total = entries.len();
+    let mut groups = (0..17).map(|_| Vec::new()).collect::<Vec<_>>();
+    let mut bitmap = 0u32;
+
+    for entry in entries.drain(..) {
+        let index = nybble_index(choice, entry.0.borrow());
+        bitmap |= 1 << index;
+        groups[index as usize].push(entry);
+    }
+
+    let groups = groups.into_iter().filter(|group| !group.is_empty());
+    let children = if total < SEQUENTIAL_BUILD_THRESHOLD {
+        groups.map(build_sorted).collect()
+    } else {
+        groups
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(build_sorted)
+            .collect()
+    };
+
+    Node::Branch(Branch::from_parts(choice, bitmap, children))
+}
+
+// The children of two branches at the same index, at least one of which is present.
+type ChildPair<K, V> = (Option<Node<K, V>>, Option<Node<K, V>>);
+
+// Merge the entries of `new` into `old`, keeping the values from `new` for keys in both, and
+// return the merged node along with the number of such keys. Branches with the same choice point
+// have their children merged pairwise, on separate threads for the first few levels.
+fn merge<K: Borrow<[u8]> + Send + Sync, V: Send>(
+    old: Node<K, V>,
+    new: Node<K, V>,
+    depth: usize,
+) -> (Node<K, V>, usize) {
+    let (old, new) = match (old, new) {
+        (mut old, Node::Leaf(leaf)) => {
+            let replaced = old.insert(leaf.key, leaf.val).is_some();
+            return (old, replaced as usize);
+        }
+        (Node::Leaf(leaf), mut new) => {
+            if new.get(leaf.key_slice()).is_some() {
+                return (new, 1);
+            }
+            new.insert(leaf.key, leaf.val);
+            return (new, 0);
+        }
+        (Node::Branch(old), Node::Branch(new)) => (old, new),
+    };
+
+    let (old_choice, new_choice) = (old.choice(), new.choice());
+    let mismatch = {
+        let old_key = old.get_exemplar(&[]).key_slice();
+        let new_key = new.get_exemplar(&[]).key_slice();
+        nybble_mismatch(old_key, new_key).unwrap_or(usize::MAX)
+    };
+
+    // The two branches hold keys which part ways above both of them.
+    if mismatch < old_choice.min(new_choice) {
+        let old_index = nybble_index(mismatch, old.get_exemplar(&[]).key_slice());
+        let new_index = nybble_index(mismatch, new.get_exemplar(&[]).key_slice());
+        let (first, second) = if old_index < new_index {
+            (old, new)
+        } else {
+            (new, old)
+        };
+        let children = Vec::from([Node::Branch(first), Node::Branch(second)]);
+
+        let bitmap = 1 << old_index | 1 << new_index;
+        return (
+            Node::Branch(Branch::from_parts(mismatch, bitmap, children)),
+            0,
+        );
+    }
+
+    // One branch lies beneath a single child of the other; merge it into that child.
+    if old_choice != new_choice {
+        let (outer, inner, inner_is_new) = if old_choice < new_choice {
+            (old, new, true)
+        } else {
+            (new, old, false)
+        };
+        let index = nybble_index(outer.choice(), inner.get_exemplar(&[]).key_slice());
+        let choice = outer.choice();
+        let mut bitmap = outer.bitmap();
+        let mut replaced = 0;
+
+        let mut children = Vec::with_capacity(17);
+        let mut inner = Some(Node::Branch(inner));
+        for (child_index, child) in (0..17u8).filter(|&i| bitmap & (1 << i) != 0).zip(outer) {
+            if child_index > index {
+                if let Some(inner) = inner.take() {
+                    children.push(inner);
+                }
+            }
+            if child_index == index {
+                // unsafe: the inner branch is only taken once.
+                let inner = unsafe { inner.take().unchecked_unwrap() };
+                let (merged, count) = if inner_is_new {
+                    merge(child, inner, depth + 1)
+                } else {
+                    merge(inner, child, depth + 1)
+                };
+                replaced = count;
+                children.push(merged);
+            } else {
+                children.push(child);
+            }
+        }
+        if let Some(inner) = inner {
+            children.push(inner);
+        }
+
+        bitmap |= 1 << index;
+        return (
+            Node::Branch(Branch::from_parts(choice, bitmap, children)),
+            replaced,
+        );
+    }
+
+    // Both branches split on the same nybble; pair up their children by index.
+    let choice = old_choice;
+    let (old_bitmap, new_bitmap) = (old.bitmap(), new.bitmap());
+    let bitmap = old_bitmap | new_bitmap;
+    let mut old_children = (0..17u8)
+        .filter(|&i| old_bitmap & (1 << i) != 0)
+        .zip(old)
+        .peekable();
+    let mut new_children = (0..17u8)
+        .filter(|&i| new_bitmap & (1 << i) != 0)
+        .zip(new)
+        .peekable();
+
+    let mut pairs = Vec::with_capacity(17);
+    for index in 0..17u8 {
+        let old_child = match old_children.peek() {
+            Some(&(i, _)) if i == index => old_children.next().map(|(_, child)| child),
+            _ => None,
+        };
+        let new_child = match new_children.peek() {
+            Some(&(i, _)) if i == index => new_children.next().map(|(_, child)| child),
+            _ => None,
+        };
+        if old_child.is_some() || new_child.is_some() {
+            pairs.push((old_child, new_child));
+        }
+    }
+
+    let merge_pair = |(old_child, new_child): ChildPair<K, V>| match (old_child, new_child) {
+        (Some(old_child), Some(new_child)) => merge(old_child, new_child, depth + 1),
+        (Some(child), None) | (None, Some(child)) => (child, 0),
+        // unsafe: only pairs with at least one child were kept.
+        (None, None) => unsafe { debug_unreachable!() },
+    };
+
+    let merged: Vec<(Node<K, V>, usize)> = if depth < PARALLEL_MERGE_DEPTH {
+        pairs.into_par_iter().map(merge_pair).collect()
+    } else {
+        pairs.into_iter().map(merge_pair).collect()
+    };
+
+    let replaced = merged.iter().map(|&(_, count)| count).sum();
+    let children = merged.into_iter().map(|(child, _)| child).collect();
+    (
+        Node::Branch(Branch::from_parts(choice, bitmap, children)),
+        replaced,
+    )
+}
+
+impl<K: Borrow<[u8]> + Send + Sync, V: Send> ParallelExtend<(K, V)> for Trie<K, V> {
+    /// Extend the trie with the items of a parallel iterator. The items are sorted in parallel and
+    /// built into a trie of their own as `Trie::par_from_sorted` does, which is then merged into
+    /// this one, again in parallel. Where a key appears more than once, the last value for it wins,
+    /// as with `Extend`.
+    fn par_extend<I>(&mut self, par_iter: I)
+    where
+        I: IntoParallelIterator<Item = (K, V)>,
+    {
+        let mut entries: Vec<(K, V)> = par_iter.into_par_iter().collect();
+        if entries.is_empty() {
+            return;
+        }
+
+        // The sort is stable, so the last value for each key ends its run; move it to the front of
+        // the run, which is what `dedup_by` keeps.
+        entries.par_sort_by(|a, b| a.0.borrow().cmp(b.0.borrow()));
+        entries.dedup_by(|later, earlier| {
+            if later.0.borrow() == earlier.0.borrow() {
+                mem::swap(later, earlier);
+                true
+            } else {
+                false
+            }
+        });
+
+        let count = self.count() + entries.len();
+        let batch = build_sorted(entries);
+        *self = match mem::take(self).into_root() {
+            Some(root) => {
+                let (root, replaced) = merge(root, batch, 0);
+                Trie::from_root(Some(root), count - replaced)
+            }
+            None => Trie::from_root(Some(batch), count),
+        };
+    }
+}
+
+impl<K: Borrow<[u8]> + Send + Sync, V: Send> FromParallelIterator<(K, V)> for Trie<K, V> {
+    fn from_par_iter<I>(par_iter: I) -> Trie<K, V>
+    where
+        I: IntoParallelIterator<Item = (K, V)>,
+    {
+        let mut trie = Trie::new();
+        trie.par_extend(par_iter);
+        trie
+    }
+}
+
+impl<K: Send, V: Send> IntoParallelIterator for Trie<K, V> {
+    type Iter = IntoParIter<K, V>;
+    type Item = (K, V);
+
+    fn into_par_iter(self) -> Self::Iter {
+        IntoParIter {
+            root: self.into_root(),
+        }
+    }
+}
+
+impl<'a, K: Sync, V: Sync> IntoParallelIterator for &'a Trie<K, V> {
+    type Iter = ParIter<'a, K, V>;
+    type Item = (&'a K, &'a V);
+
+    fn into_par_iter(self) -> Self::Iter {
+        self.par_iter()
+    }
+}
+
+impl<'a, K: Send + Sync, V: Send> IntoParallelIterator for &'a mut Trie<K, V> {
+    type Iter = ParIterMut<'a, K, V>;
+    type Item = (&'a K, &'a mut V);
+
+    fn into_par_iter(self) -> Self::Iter {
+        self.par_iter_mut()
+    }
+}
+
+impl<'a, K, V> SubTrie<'a, K, V> {
+    /// Iterate over all elements in the subtrie in parallel.
+    pub fn par_iter(&self) -> ParIter<'a, K, V> {
+        ParIter { root: self.root }
+    }
+}
+
+impl<'a, K: Sync, V: Sync> IntoParallelIterator for SubTrie<'a, K, V> {
+    type Iter = ParIter<'a, K, V>;
+    type Item = (&'a K, &'a V);
+
+    fn into_par_iter(self) -> Self::Iter {
+        self.par_iter()
+    }
+}
+
+impl<'a, K: Borrow<[u8]>, V> SubTrieMut<'a, K, V> {
+    /// Iterate over all elements in the view in parallel.
+    pub fn par_iter(&self) -> ParIter<'_, K, V> {
+        self.as_subtrie().par_iter()
+    }
+
+    /// Iterate over all elements in the view in parallel, given a mutable reference to the
+    /// associated value.
+    pub fn par_iter_mut(&mut self) -> ParIterMut<'_, K, V> {
+        ParIterMut {
+            root: self.node_mut(),
+        }
+    }
+
+    /// Iterate over all values in the view in parallel, mutably.
+    pub fn par_values_mut(&mut self) -> ParValuesMut<'_, K, V> {
+        ParValuesMut {
+            inner: self.par_iter_mut(),
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! Filesystem paths as keys, with prefixes made up of whole components.
+//!
+//! A `PathKey` is stored in the trie as its components, each followed by a separator, so that
+//! `/usr/lib` is a prefix of `/usr/lib/x86_64` but not of `/usr/libexec`. The methods of
+//! `Trie<PathKey, V>` taking `&Path` queries encode them the same way, and
+//! `Trie::longest_common_prefix` only ever breaks keys between components, returning a real
+//! ancestor path.
+//!
+//! ```rust
+//! # use qp_trie::Trie;
+//! use qp_trie::path::PathKey;
+//! use std::path::Path;
+//!
+//! let mut settings = Trie::new();
+//! settings.insert(PathKey::from("/usr"), "usr");
+//! settings.insert(PathKey::from("/usr/lib"), "lib");
+//!
+//! let (dir, &setting) = settings.deepest_ancestor("/usr/lib/x86_64/libc.so").unwrap();
+//! assert_eq!((dir.as_path(), setting), (Path::new("/usr/lib"), "lib"));
+//!
+//! let (dir, &setting) = settings.deepest_ancestor("/usr/libexec/gcc").unwrap();
+//! assert_eq!((dir.as_path(), setting), (Path::new("/usr"), "usr"));
+//!
+//! assert_eq!(settings.iter_prefix_path("/usr/lib").count(), 1);
+//! assert_eq!(settings.longest_common_prefix_path("/usr/local"), Path::new("/usr"));
+//! ```
+//!
+//! Paths are compared component by component, like `Path` itself: `/usr//lib/` and `/usr/./lib`
+//! are the same key as `/usr/lib`, but no attempt is made to resolve `..` or symbolic links.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::path::{self, Path, PathBuf};
+use std::vec::Vec;
+
+use iter::{Iter, IterMut};
+use node::{Leaf, Node};
+use trie::{Break, Trie};
+
+/// A path stored by its components, for use as a trie key. It borrows as the bytes of its
+/// components, each followed by a separator, which is what the trie sees, and as the path itself.
+#[derive(Clone)]
+pub struct PathKey {
+    // The path with its components normalized, followed by a separator unless it is empty or
+    // already ends with one (as a root does).
+    encoded: OsString,
+    // The length of the path without that trailing separator.
+    len: usize,
+}
+
+impl PathKey {
+    /// Create a key from a path, normalizing its components.
+    pub fn new<P: AsRef<Path>>(path: P) -> PathKey {
+        let mut encoded = path
+            .as_ref()
+            .components()
+            .collect::<PathBuf>()
+            .into_os_string();
+        let len = encoded.len();
+
+        match encoded.as_encoded_bytes().last() {
+            Some(&last) if !path::is_separator(last as char) => {
+                encoded.push(path::MAIN_SEPARATOR_STR)
+            }
+            _ => {}
+        }
+
+        PathKey { encoded, len }
+    }
+
+    /// The path of the key.
+    #[inline]
+    pub fn as_path(&self) -> &Path {
+        self.cut(self.len)
+    }
+
+    /// The key as stored in the trie.
+    #[inline]
+    pub fn encoded(&self) -> &[u8] {
+        self.encoded.as_encoded_bytes()
+    }
+
+    /// Unwrap the key into its path.
+    pub fn into_path_buf(self) -> PathBuf {
+        self.as_path().to_path_buf()
+    }
+
+    // The first `len` bytes of the encoded key, as a path.
+    #[inline]
+    fn cut(&self, len: usize) -> &Path {
+        let bytes = &self.encoded()[..len];
+
+        // Safety: `bytes` comes from an `OsStr`, and is only ever cut immediately before or after
+        // a separator, which is ASCII.
+        Path::new(unsafe { OsStr::from_encoded_bytes_unchecked(bytes) })
+    }
+}
+
+impl<'a> From<&'a Path> for PathKey {
+    #[inline]
+    fn from(path: &'a Path) -> PathKey {
+        PathKey::new(path)
+    }
+}
+
+impl From<PathBuf> for PathKey {
+    #[inline]
+    fn from(path: PathBuf) -> PathKey {
+        PathKey::new(path)
+    }
+}
+
+impl<'a> From<&'a OsStr> for PathKey {
+    #[inline]
+    fn from(path: &'a OsStr) -> PathKey {
+        PathKey::new(path)
+    }
+}
+
+impl From<OsString> for PathKey {
+    #[inline]
+    fn from(path: OsString) -> PathKey {
+        PathKey::new(path)
+    }
+}
+
+impl<'a> From<&'a str> for PathKey {
+    #[inline]
+    fn from(path: &'a str) -> PathKey {
+        PathKey::new(path)
+    }
+}
+
+impl Borrow<[u8]> for PathKey {
+    #[inline]
+    fn borrow(&self) -> &[u8] {
+        self.encoded()
+    }
+}
+
+impl Borrow<PathPrefix> for PathKey {
+    #[inline]
+    fn borrow(&self) -> &PathPrefix {
+        PathPrefix::new(self.as_path())
+    }
+}
+
+impl AsRef<Path> for PathKey {
+    #[inline]
+    fn as_ref(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl fmt::Debug for PathKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_path().fmt(f)
+    }
+}
+
+/// An ancestor of the path of a `PathKey`, as returned by `Trie::longest_common_prefix`.
+///
+/// It is deliberately not comparable or hashable as a key: the trie orders `PathKey`s by their
+/// encoded components, which `Path` does not (`a-b` sorts after `a/b` as a path, but before it as
+/// a key), so looking them up by path would not be meaningful.
+#[repr(transparent)]
+pub struct PathPrefix(Path);
+
+impl PathPrefix {
+    #[inline]
+    fn new(path: &Path) -> &PathPrefix {
+        unsafe { &*(path as *const Path as *const PathPrefix) }
+    }
+
+    /// The prefix as a path.
+    #[inline]
+    pub fn as_path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Deref for PathPrefix {
+    type Target = Path;
+
+    #[inline]
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for PathPrefix {
+    #[inline]
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl PartialEq<Path> for PathPrefix {
+    #[inline]
+    fn eq(&self, other: &Path) -> bool {
+        &self.0 == other
+    }
+}
+
+impl<'a> PartialEq<&'a Path> for PathPrefix {
+    #[inline]
+    fn eq(&self, other: &&'a Path) -> bool {
+        &self.0 == *other
+    }
+}
+
+impl fmt::Debug for PathPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// Comparisons and hashing go by the encoded bytes, agreeing with the `Borrow<[u8]>` impl. Two
+// keys have the same encoding exactly when their paths have the same components, so equality
+// agrees with that of `Path` too.
+impl PartialEq for PathKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.encoded == other.encoded
+    }
+}
+
+impl Eq for PathKey {}
+
+impl PartialOrd for PathKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PathKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.encoded().cmp(other.encoded())
+    }
+}
+
+impl Hash for PathKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.encoded().hash(state)
+    }
+}
+
+impl Break for PathKey {
+    type Split = PathPrefix;
+
+    #[inline]
+    fn empty<'a>() -> &'a PathPrefix {
+        PathPrefix::new(Path::new(""))
+    }
+
+    // Break after the last whole component within the first `loc` bytes of the encoded key.
+    fn find_break(&self, loc: usize) -> &PathPrefix {
+        let bytes = &self.encoded()[..loc];
+
+        match bytes.iter().rposition(|&b| path::is_separator(b as char)) {
+            Some(end) => {
+                // The separator ends a component, unless it is part of the root.
+                let (with, without) = (self.cut(end + 1), self.cut(end));
+
+                if with.components().eq(without.components()) {
+                    PathPrefix::new(without)
+                } else {
+                    PathPrefix::new(with)
+                }
+            }
+            None => Self::empty(),
+        }
+    }
+}
+
+/// An iterator over the entries of a trie of `PathKey`s whose keys are ancestors of a path,
+/// deepest first, produced by `Trie::iter_ancestors`.
+pub struct Ancestors<'a, V: 'a> {
+    // The ancestors, shallowest first.
+    found: Vec<&'a Leaf<PathKey, V>>,
+}
+
+impl<'a, V: 'a> Iterator for Ancestors<'a, V> {
+    type Item = (&'a PathKey, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.found.pop().map(|leaf| (&leaf.key, &leaf.val))
+    }
+}
+
+// Visit the leaves whose keys are ancestors of the encoded path `query`, shallowest first. The
+// encoding of an ancestor is a prefix of that of the path, so every ancestor lies on the path down
+// the trie to `query`: either as the head of a branch, where the keys which have ended by its
+// choice point are kept, or as the leaf at the end.
+fn visit_ancestors<'a, V, F>(root: Option<&'a Node<PathKey, V>>, query: &[u8], mut visit: F)
+where
+    F: FnMut(&'a Leaf<PathKey, V>),
+{
+    let mut node = match root {
+        Some(root) => root,
+        None => return,
+    };
+
+    loop {
+        match *node {
+            Node::Leaf(ref leaf) => {
+                if query.starts_with(leaf.key_slice()) {
+                    visit(leaf);
+                }
+                return;
+            }
+            Node::Branch(ref branch) => {
+                // An empty key is too short to reach any choice point, so it finds the head.
+                if let Some(Node::Leaf(head)) = branch.child(&[]) {
+                    if query.starts_with(head.key_slice()) {
+                        visit(head);
+                    }
+                }
+
+                node = match branch.child(query) {
+                    Some(child) if branch.index(query) != 0 => child,
+                    _ => return,
+                };
+            }
+        }
+    }
+}
+
+#[inline]
+fn encode(path: &Path) -> Vec<u8> {
+    PathKey::new(path).encoded.into_encoded_bytes()
+}
+
+impl<V> Trie<PathKey, V> {
+    /// Iterate over all entries whose keys lie beneath the given path, including the path itself.
+    pub fn iter_prefix_path<P: AsRef<Path>>(&self, prefix: P) -> Iter<'_, PathKey, V> {
+        self.iter_prefix(&encode(prefix.as_ref())[..])
+    }
+
+    /// Iterate mutably over all entries whose keys lie beneath the given path, including the path
+    /// itself.
+    pub fn iter_prefix_mut_path<P: AsRef<Path>>(&mut self, prefix: P) -> IterMut<'_, PathKey, V> {
+        self.iter_prefix_mut(&encode(prefix.as_ref())[..])
+    }
+
+    /// Returns true if there is an entry for the given path.
+    pub fn contains_path<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.contains_key(&encode(path.as_ref())[..])
+    }
+
+    /// Convenience function for getting with a path.
+    pub fn get_path<P: AsRef<Path>>(&self, path: P) -> Option<&V> {
+        self.get(&encode(path.as_ref())[..])
+    }
+
+    /// Convenience function for getting mutably with a path.
+    pub fn get_mut_path<P: AsRef<Path>>(&mut self, path: P) -> Option<&mut V> {
+        self.get_mut(&encode(path.as_ref())[..])
+    }
+
+    /// Convenience function for inserting with a path.
+    pub fn insert_path<P: AsRef<Path>>(&mut self, path: P, val: V) -> Option<V> {
+        self.insert(PathKey::new(path), val)
+    }
+
+    /// Convenience function for removing with a path.
+    pub fn remove_path<P: AsRef<Path>>(&mut self, path: P) -> Option<V> {
+        self.remove(&encode(path.as_ref())[..])
+    }
+
+    /// Remove all entries whose keys lie beneath the given path, including the path itself,
+    /// returning them as a trie of their own.
+    pub fn remove_prefix_path<P: AsRef<Path>>(&mut self, prefix: P) -> Trie<PathKey, V> {
+        self.remove_prefix(&encode(prefix.as_ref())[..])
+    }
+
+    /// Get the deepest path which is an ancestor of, or the same as, both the given path and some
+    /// key in the trie.
+    pub fn longest_common_prefix_path<P: AsRef<Path>>(&self, path: P) -> &Path {
+        self.longest_common_prefix(&encode(path.as_ref())[..])
+            .as_path()
+    }
+
+    /// Iterate over the entries whose keys are ancestors of the given path, including the path
+    /// itself, deepest first. They are all found in a single descent of the trie.
+    pub fn iter_ancestors<P: AsRef<Path>>(&self, path: P) -> Ancestors<'_, V> {
+        let mut found = Vec::new();
+        visit_ancestors(self.root(), &encode(path.as_ref()), |leaf| found.push(leaf));
+        Ancestors { found }
+    }
+
+    /// Find the entry for the deepest ancestor of the given path which has one, including the
+    /// path itself, as when looking up the settings which apply to a file. This takes a single
+    /// descent of the trie.
+    pub fn deepest_ancestor<P: AsRef<Path>>(&self, path: P) -> Option<(&PathKey, &V)> {
+        let mut deepest = None;
+        visit_ancestors(self.root(), &encode(path.as_ref()), |leaf| {
+            deepest = Some((&leaf.key, &leaf.val))
+        });
+        deepest
+    }
+}
@@ -0,0 +1,251 @@
+use core::borrow::Borrow;
+use core::marker::PhantomData;
+
+use unreachable::UncheckedOptionExt;
+
+use entry::{Graft, Grafted};
+use iter::{Iter, IterMut};
+use measure::Measure;
+use node::Node;
+use trie::Trie;
+use util::nybble_get_mismatch;
+
+pub fn make_prefix_entry<'a, 'b, K: 'a + Borrow<[u8]>, V: 'a, M: Measure<K, V>>(
+    prefix: &'b [u8],
+    root: &'a mut Option<Node<K, V, M>>,
+    count: &'a mut usize,
+) -> PrefixEntry<'a, 'b, K, V, M> {
+    let mismatch = match *root {
+        Some(ref node) => nybble_get_mismatch(node.get_exemplar(prefix).key_slice(), prefix),
+        None => {
+            return PrefixEntry::Vacant(VacantPrefixEntry {
+                prefix,
+                graft: Graft {
+                    root,
+                    point: None,
+                    count,
+                },
+            })
+        }
+    };
+
+    // If the exemplar for the prefix doesn't begin with it, then no key does, and every key which
+    // does begin with it mismatches the exemplar at the same place.
+    if let Some((b, i)) = mismatch {
+        if i < prefix.len() * 2 {
+            return PrefixEntry::Vacant(VacantPrefixEntry {
+                prefix,
+                graft: Graft {
+                    root,
+                    point: Some((i, b)),
+                    count,
+                },
+            });
+        }
+    }
+
+    // Descend to the node holding all and only the keys with the prefix, as
+    // `Node::get_prefix_validated_mut` does, but keeping track of its parent.
+    let root_ptr = root as *mut Option<Node<K, V, M>>;
+    let mut parent = None;
+    // unsafe: the trie is nonempty.
+    let mut node = unsafe { root.as_mut().unchecked_unwrap() } as *mut Node<K, V, M>;
+
+    loop {
+        // unsafe: `node` always points into the trie, which we hold a mutable borrow of.
+        let index = match unsafe { &*node } {
+            Node::Branch(branch) if branch.choice() < prefix.len() * 2 => branch.index(prefix),
+            _ => break,
+        };
+
+        parent = Some((node, index));
+        // unsafe: the child must exist, as there are keys with the given prefix.
+        node = unsafe { (*node).unwrap_branch_mut() }.entry_mut(index);
+    }
+
+    PrefixEntry::Occupied(OccupiedPrefixEntry {
+        _dummy: PhantomData,
+        prefix,
+        root: root_ptr,
+        parent,
+        node,
+        count,
+    })
+}
+
+/// An entry in the trie corresponding to a prefix, which is occupied if any key in the trie begins
+/// with the prefix and vacant otherwise.
+#[derive(Debug)]
+pub enum PrefixEntry<'a, 'b, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
+    Vacant(VacantPrefixEntry<'a, 'b, K, V, M>),
+    Occupied(OccupiedPrefixEntry<'a, 'b, K, V, M>),
+}
+
+impl<'a, 'b, K: 'a + Borrow<[u8]>, V: 'a, M: Measure<K, V>> PrefixEntry<'a, 'b, K, V, M> {
+    /// Get the prefix associated with this entry.
+    pub fn prefix(&self) -> &'b [u8] {
+        match self {
+            PrefixEntry::Vacant(vacant) => vacant.prefix(),
+            PrefixEntry::Occupied(occupied) => occupied.prefix(),
+        }
+    }
+}
+
+/// A prefix entry for which there are no keys in the trie.
+#[derive(Debug)]
+pub struct VacantPrefixEntry<'a, 'b, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
+    prefix: &'b [u8],
+    graft: Graft<'a, K, V, M>,
+}
+
+impl<'a, 'b, K: 'a + Borrow<[u8]>, V: 'a, M: Measure<K, V>> VacantPrefixEntry<'a, 'b, K, V, M> {
+    /// Get the prefix associated with this entry.
+    pub fn prefix(&self) -> &'b [u8] {
+        self.prefix
+    }
+
+    /// Insert a key/value pair under the prefix, returning the now-occupied entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key does not begin with the prefix.
+    pub fn insert(self, key: K, val: V) -> OccupiedPrefixEntry<'a, 'b, K, V, M> {
+        assert!(
+            key.borrow().starts_with(self.prefix),
+            "key does not begin with the entry's prefix"
+        );
+
+        let Grafted {
+            root,
+            parent,
+            node,
+            count,
+        } = self.graft.insert_node(key, val);
+
+        OccupiedPrefixEntry {
+            _dummy: PhantomData,
+            prefix: self.prefix,
+            root,
+            parent,
+            node,
+            count,
+        }
+    }
+
+    /// Insert a batch of key/value pairs under the prefix, returning the resulting entry, which is
+    /// only vacant if the batch was empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any key does not begin with the prefix.
+    pub fn insert_all<I>(self, iterable: I) -> PrefixEntry<'a, 'b, K, V, M>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut iter = iterable.into_iter();
+
+        match iter.next() {
+            Some((key, val)) => {
+                let mut occupied = self.insert(key, val);
+                occupied.extend(iter);
+                PrefixEntry::Occupied(occupied)
+            }
+            None => PrefixEntry::Vacant(self),
+        }
+    }
+}
+
+/// A prefix entry for which there are keys in the trie. It holds the node containing all and only
+/// those keys, so that they can be visited, added to, or removed without searching for it again.
+#[derive(Debug)]
+pub struct OccupiedPrefixEntry<'a, 'b, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
+    _dummy: PhantomData<&'a mut ()>,
+
+    prefix: &'b [u8],
+    root: *mut Option<Node<K, V, M>>,
+    parent: Option<(*mut Node<K, V, M>, u8)>,
+    node: *mut Node<K, V, M>,
+    count: &'a mut usize,
+}
+
+impl<'a, 'b, K: 'a + Borrow<[u8]>, V: 'a, M: Measure<K, V>> OccupiedPrefixEntry<'a, 'b, K, V, M> {
+    /// Get the prefix associated with this entry.
+    pub fn prefix(&self) -> &'b [u8] {
+        self.prefix
+    }
+
+    /// Count the number of entries under the prefix. This traverses all of them, so it takes time
+    /// linear in their number.
+    pub fn count(&self) -> usize {
+        unsafe { &*self.node }.count()
+    }
+
+    /// Iterate over all entries under the prefix.
+    pub fn iter(&self) -> Iter<'_, K, V, M> {
+        unsafe { &*self.node }.iter()
+    }
+
+    /// Iterate over all entries under the prefix, given a mutable reference to the associated
+    /// value.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V, M> {
+        unsafe { &mut *self.node }.iter_mut()
+    }
+
+    /// Insert a key/value pair under the prefix, returning the old value if an entry already
+    /// existed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key does not begin with the prefix.
+    pub fn insert(&mut self, key: K, val: V) -> Option<V> {
+        assert!(
+            key.borrow().starts_with(self.prefix),
+            "key does not begin with the entry's prefix"
+        );
+
+        // Every key beneath the node shares the prefix, so the new key's graft point must be
+        // beneath the node's parent, and it is safe to insert directly into the node.
+        let old = unsafe { &mut *self.node }.insert(key, val);
+        if old.is_none() {
+            *self.count += 1;
+        }
+        old
+    }
+
+    /// Remove all entries under the prefix from the trie, producing a trie containing them.
+    pub fn remove(self) -> Trie<K, V, M> {
+        let node = match self.parent {
+            None => unsafe { (*self.root).take().unchecked_unwrap() },
+            Some((parent, index)) => {
+                let parent = unsafe { &mut *parent };
+                let node = unsafe { parent.unwrap_branch_mut() }.remove(index);
+
+                // The parent's arity has reduced - we may be able to compress.
+                if unsafe { parent.unwrap_branch_mut() }.is_singleton() {
+                    *parent = unsafe { parent.unwrap_branch_mut() }.clear_last();
+                }
+
+                node
+            }
+        };
+
+        let count = node.count();
+        *self.count -= count;
+        Trie::from_root(Some(node), count)
+    }
+}
+
+impl<'a, 'b, K: 'a + Borrow<[u8]>, V: 'a, M: Measure<K, V>> Extend<(K, V)>
+    for OccupiedPrefixEntry<'a, 'b, K, V, M>
+{
+    /// Insert a batch of key/value pairs under the prefix.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any key does not begin with the prefix.
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iterable: I) {
+        for (key, val) in iterable {
+            self.insert(key, val);
+        }
+    }
+}
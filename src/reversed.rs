@@ -0,0 +1,255 @@
+//! Keys stored back to front, so that suffix queries become prefix queries.
+//!
+//! Wrapping keys in a `ReversedKey` stores them in the trie reversed, either byte by byte or, for
+//! DNS names, label by label. `Trie::iter_suffix`, `Trie::remove_suffix` and
+//! `Trie::longest_common_suffix` then take suffixes in the original orientation and hand back the
+//! original keys.
+//!
+//! ```rust
+//! # use qp_trie::Trie;
+//! use qp_trie::reversed::{Labels, ReversedKey};
+//!
+//! let mut blocklist = Trie::new();
+//!
+//! for name in ["ads.example.com", "example.com", "tracker.example.com", "example.org"] {
+//!     blocklist.insert(ReversedKey::<Labels>::new(name), ());
+//! }
+//!
+//! let mut subdomains = blocklist
+//!     .iter_suffix(".example.com")
+//!     .map(|(name, _)| name)
+//!     .collect::<Vec<_>>();
+//! subdomains.sort();
+//!
+//! assert_eq!(subdomains, [&b"ads.example.com"[..], &b"tracker.example.com"[..]]);
+//! ```
+
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
+
+use iter::Iter;
+use trie::Trie;
+use util::nybble_mismatch;
+
+/// A way of reversing keys.
+pub trait Reversal {
+    /// Append the reversal of `key` to `out`. This must be a bijection which preserves lengths.
+    fn reverse_into(key: &[u8], out: &mut Vec<u8>);
+
+    /// Append the original of the reversed key `reversed` to `out`, undoing `reverse_into`. By
+    /// default this reverses again, which is right for reversals which are their own inverse.
+    #[inline]
+    fn restore_into(reversed: &[u8], out: &mut Vec<u8>) {
+        Self::reverse_into(reversed, out)
+    }
+
+    /// Given reversed keys which agree on their first `common` bytes, return how many of those
+    /// bytes should count as a common suffix of the original keys.
+    #[inline]
+    fn align(left: &[u8], right: &[u8], common: usize) -> usize {
+        let _ = (left, right);
+        common
+    }
+}
+
+/// Reverse keys byte by byte, as for file names and their extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Bytes {}
+
+impl Reversal for Bytes {
+    #[inline]
+    fn reverse_into(key: &[u8], out: &mut Vec<u8>) {
+        out.extend(key.iter().rev());
+    }
+}
+
+/// Reverse the order of the dot-separated labels of keys, as for DNS names, leaving the labels
+/// themselves intact: `www.example.com` is stored as `com.example.www`. Suffixes are reversed the
+/// same way, so a suffix beginning with a dot such as `.example.com` matches exactly the names
+/// beneath `example.com`, and common suffixes are only ever whole labels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Labels {}
+
+impl Reversal for Labels {
+    fn reverse_into(key: &[u8], out: &mut Vec<u8>) {
+        for (i, label) in key.rsplit(|&b| b == b'.').enumerate() {
+            if i > 0 {
+                out.push(b'.');
+            }
+            out.extend_from_slice(label);
+        }
+    }
+
+    fn align(left: &[u8], right: &[u8], common: usize) -> usize {
+        let ends_label = |key: &[u8]| !matches!(key.get(common), Some(&b) if b != b'.');
+
+        if ends_label(left) && ends_label(right) {
+            common
+        } else {
+            left[..common]
+                .iter()
+                .rposition(|&b| b == b'.')
+                .map_or(0, |dot| dot + 1)
+        }
+    }
+}
+
+/// A key stored in reverse, according to `R`. It borrows as its reversed bytes, which is what the
+/// trie sees. Only the reversed bytes are kept, so the original key is rebuilt each time it is
+/// asked for.
+pub struct ReversedKey<R = Bytes> {
+    reversed: Vec<u8>,
+    reversal: PhantomData<R>,
+}
+
+impl<R: Reversal> ReversedKey<R> {
+    /// Wrap a key, reversing it.
+    pub fn new<K: AsRef<[u8]>>(key: K) -> ReversedKey<R> {
+        ReversedKey {
+            reversed: reverse::<R>(key.as_ref()),
+            reversal: PhantomData,
+        }
+    }
+
+    /// The key in its original orientation.
+    pub fn key(&self) -> Vec<u8> {
+        restore::<R>(&self.reversed)
+    }
+
+    /// Unwrap the key, in its original orientation.
+    #[inline]
+    pub fn into_inner(self) -> Vec<u8> {
+        self.key()
+    }
+}
+
+impl<R> ReversedKey<R> {
+    /// The key as stored in the trie.
+    #[inline]
+    pub fn reversed(&self) -> &[u8] {
+        &self.reversed
+    }
+}
+
+impl<R> Borrow<[u8]> for ReversedKey<R> {
+    #[inline]
+    fn borrow(&self) -> &[u8] {
+        &self.reversed
+    }
+}
+
+impl<R> Clone for ReversedKey<R> {
+    fn clone(&self) -> Self {
+        ReversedKey {
+            reversed: self.reversed.clone(),
+            reversal: PhantomData,
+        }
+    }
+}
+
+impl<R: Reversal> fmt::Debug for ReversedKey<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.key().fmt(f)
+    }
+}
+
+// Comparisons and hashing go by the reversed bytes, agreeing with the `Borrow<[u8]>` impl.
+impl<R> PartialEq for ReversedKey<R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.reversed == other.reversed
+    }
+}
+
+impl<R> Eq for ReversedKey<R> {}
+
+impl<R> PartialOrd for ReversedKey<R> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<R> Ord for ReversedKey<R> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.reversed.cmp(&other.reversed)
+    }
+}
+
+impl<R> Hash for ReversedKey<R> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.reversed.hash(state)
+    }
+}
+
+#[inline]
+fn reverse<R: Reversal>(key: &[u8]) -> Vec<u8> {
+    let mut reversed = Vec::with_capacity(key.len());
+    R::reverse_into(key, &mut reversed);
+    reversed
+}
+
+#[inline]
+fn restore<R: Reversal>(reversed: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(reversed.len());
+    R::restore_into(reversed, &mut key);
+    key
+}
+
+/// An iterator over the entries of a trie of `ReversedKey`s, giving back the original keys.
+#[derive(Clone, Debug)]
+pub struct SuffixIter<'a, R: Reversal + 'a, V: 'a> {
+    inner: Iter<'a, ReversedKey<R>, V>,
+}
+
+impl<'a, R: Reversal + 'a, V: 'a> Iterator for SuffixIter<'a, R, V> {
+    type Item = (Vec<u8>, &'a V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(key, val)| (key.key(), val))
+    }
+}
+
+impl<R: Reversal, V> Trie<ReversedKey<R>, V> {
+    /// Iterate over all elements whose original keys end with the given suffix.
+    pub fn iter_suffix<Q>(&self, suffix: &Q) -> SuffixIter<'_, R, V>
+    where
+        Q: ?Sized + AsRef<[u8]>,
+    {
+        SuffixIter {
+            inner: self.iter_prefix(&reverse::<R>(suffix.as_ref())[..]),
+        }
+    }
+
+    /// Remove all elements whose original keys end with the given suffix, returning them as a
+    /// trie of their own.
+    pub fn remove_suffix<Q>(&mut self, suffix: &Q) -> Trie<ReversedKey<R>, V>
+    where
+        Q: ?Sized + AsRef<[u8]>,
+    {
+        self.remove_prefix(&reverse::<R>(suffix.as_ref())[..])
+    }
+
+    /// Get the longest common suffix of all the original keys in the trie and the given key.
+    pub fn longest_common_suffix<Q>(&self, key: &Q) -> Vec<u8>
+    where
+        Q: ?Sized + AsRef<[u8]>,
+    {
+        let reversed = reverse::<R>(key.as_ref());
+        let exemplar = match self.root() {
+            Some(root) => root.get_exemplar(&reversed).key.reversed(),
+            None => return Vec::new(),
+        };
+
+        let common = match nybble_mismatch(exemplar, &reversed) {
+            Some(i) => i / 2,
+            None => reversed.len(),
+        };
+        let common = R::align(exemplar, &reversed, common);
+
+        restore::<R>(&exemplar[..common])
+    }
+}
@@ -0,0 +1,150 @@
+//! Serde support for `Trie`, along with alternative representations which can be selected with
+//! `#[serde(with = "...")]`.
+
+use subtrie::SubTrie;
+use trie::Trie;
+use wrapper::BString;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt;
+use core::marker::PhantomData;
+
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+pub mod nested;
+pub mod pairs;
+mod seed;
+pub mod structural;
+
+pub use self::seed::TrieSeed;
+
+/// Keys which can be rebuilt from their byte representation. This is needed by representations
+/// which do not store every key in full.
+pub trait KeyFromBytes: Borrow<[u8]> + Sized {
+    /// Rebuild a key from its bytes, returning `None` if the bytes do not represent a valid key.
+    fn from_key_bytes(bytes: Vec<u8>) -> Option<Self>;
+}
+
+impl KeyFromBytes for Vec<u8> {
+    #[inline]
+    fn from_key_bytes(bytes: Vec<u8>) -> Option<Self> {
+        Some(bytes)
+    }
+}
+
+impl KeyFromBytes for Box<[u8]> {
+    #[inline]
+    fn from_key_bytes(bytes: Vec<u8>) -> Option<Self> {
+        Some(bytes.into_boxed_slice())
+    }
+}
+
+impl KeyFromBytes for BString {
+    #[inline]
+    fn from_key_bytes(bytes: Vec<u8>) -> Option<Self> {
+        String::from_utf8(bytes).ok().map(BString::from)
+    }
+}
+
+impl<K, V> Serialize for Trie<K, V>
+where
+    K: Serialize + Borrow<[u8]>,
+    V: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.count()))?;
+        for (k, v) in self.iter() {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
+
+impl<'a, K, V> Serialize for SubTrie<'a, K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        if let Some(node) = self.root {
+            for (k, v) in node.iter() {
+                map.serialize_entry(k, v)?;
+            }
+        }
+        map.end()
+    }
+}
+
+impl<K: Borrow<[u8]>, V> Trie<K, V> {
+    /// Serialize all entries with a given prefix as a map, in the same way as `Trie` itself is
+    /// serialized.
+    pub fn serialize_prefix<Q, S>(&self, prefix: &Q, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Borrow<Q> + Serialize,
+        Q: ?Sized + Borrow<[u8]>,
+        V: Serialize,
+        S: Serializer,
+    {
+        self.subtrie(prefix).serialize(serializer)
+    }
+}
+
+struct TrieVisitor<K, V> {
+    marker: PhantomData<fn() -> Trie<K, V>>,
+}
+
+impl<K, V> TrieVisitor<K, V> {
+    fn new() -> Self {
+        TrieVisitor {
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'de, K, V> Visitor<'de> for TrieVisitor<K, V>
+where
+    K: Deserialize<'de> + Borrow<[u8]>,
+    V: Deserialize<'de>,
+{
+    type Value = Trie<K, V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a qp-trie")
+    }
+
+    fn visit_map<M>(self, mut access: M) -> Result<Self::Value, M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        let mut trie = Trie::new();
+        while let Some((key, value)) = access.next_entry()? {
+            trie.insert(key, value);
+        }
+
+        Ok(trie)
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for Trie<K, V>
+where
+    K: Deserialize<'de> + Borrow<[u8]>,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(TrieVisitor::new())
+    }
+}
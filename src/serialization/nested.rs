@@ -0,0 +1,413 @@
+//! A representation of a `Trie` with hierarchical keys as nested maps, for use with
+//! `#[serde(with = "qp_trie::serialization::nested")]`.
+//!
+//! Keys are split into segments on `/` (or on any other byte, with `Trie::as_tree` and
+//! `TreeSeed`), so that the keys `a/b` and `a/c` become `{"a": {"b": ..., "c": ...}}`. Segments
+//! which are valid UTF-8 are serialized as strings, and other segments as byte strings.
+//!
+//! A key which is also a directory of other keys (such as `a` alongside `a/b`) has no place in
+//! the nested representation, and serializing a trie containing one fails. When deserializing,
+//! every map is taken to be a directory, so values must not themselves be maps; and since values
+//! are inspected before being handed on to their `Deserialize` impl, value types which need a
+//! hint from the format (such as `Option`) are not supported.
+//!
+//! # Example
+//!
+//! ```rust
+//! # extern crate qp_trie;
+//! # extern crate serde;
+//! # extern crate serde_json;
+//! use qp_trie::serialization::nested::TreeSeed;
+//! use qp_trie::wrapper::BString;
+//! use qp_trie::Trie;
+//! use serde::de::DeserializeSeed;
+//!
+//! # fn main() {
+//! let mut trie = Trie::new();
+//! trie.insert_str("a.b", 1);
+//! trie.insert_str("a.c", 2);
+//!
+//! let json = serde_json::to_string(&trie.as_tree(b'.')).unwrap();
+//! assert_eq!(json, r#"{"a":{"b":1,"c":2}}"#);
+//!
+//! let mut deserializer = serde_json::Deserializer::from_str(&json);
+//! let tree: Trie<BString, u32> = TreeSeed::new(b'.').deserialize(&mut deserializer).unwrap();
+//! assert_eq!(tree, trie);
+//! # }
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt;
+use core::marker::PhantomData;
+use core::str;
+
+use serde::de::value::{
+    BoolDeserializer, BorrowedBytesDeserializer, BorrowedStrDeserializer, BytesDeserializer,
+    CharDeserializer, F64Deserializer, I64Deserializer, SeqAccessDeserializer, StrDeserializer,
+    StringDeserializer, U64Deserializer, UnitDeserializer,
+};
+use serde::de::{self, Deserialize, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Error, Serialize, SerializeMap, Serializer};
+
+use node::Node;
+use serialization::KeyFromBytes;
+use trie::Trie;
+
+/// Serialize a trie as nested maps, splitting keys on `/`.
+pub fn serialize<K, V, S>(trie: &Trie<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Borrow<[u8]>,
+    V: Serialize,
+    S: Serializer,
+{
+    serialize_with_separator(trie, b'/', serializer)
+}
+
+/// Serialize a trie as nested maps, splitting keys on the given separator.
+pub fn serialize_with_separator<K, V, S>(
+    trie: &Trie<K, V>,
+    separator: u8,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    K: Borrow<[u8]>,
+    V: Serialize,
+    S: Serializer,
+{
+    trie.as_tree(separator).serialize(serializer)
+}
+
+/// Deserialize a trie from nested maps, joining keys with `/`.
+pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<Trie<K, V>, D::Error>
+where
+    K: KeyFromBytes,
+    V: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    TreeSeed::new(b'/').deserialize(deserializer)
+}
+
+impl<K: Borrow<[u8]>, V> Trie<K, V> {
+    /// View the trie as a tree of nested maps, splitting keys on the given separator. The view
+    /// implements `Serialize`; see the `nested` module for details.
+    pub fn as_tree(&self, separator: u8) -> AsTree<'_, K, V> {
+        AsTree {
+            level: Level {
+                node: self.root(),
+                depth: 0,
+                separator,
+            },
+        }
+    }
+}
+
+/// A view of a trie as a tree of nested maps, produced by `Trie::as_tree`.
+pub struct AsTree<'a, K: 'a, V: 'a> {
+    level: Level<'a, K, V>,
+}
+
+impl<'a, K: Borrow<[u8]>, V: Serialize> Serialize for AsTree<'a, K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.level.serialize(serializer)
+    }
+}
+
+/// A `DeserializeSeed` which reads a trie from nested maps, joining the keys of each level with
+/// the given separator. This is the inverse of `Trie::as_tree`.
+pub struct TreeSeed<K, V> {
+    separator: u8,
+    marker: PhantomData<fn() -> Trie<K, V>>,
+}
+
+impl<K, V> TreeSeed<K, V> {
+    /// Create a seed which joins keys with the given separator.
+    pub fn new(separator: u8) -> TreeSeed<K, V> {
+        TreeSeed {
+            separator,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'de, K: KeyFromBytes, V: Deserialize<'de>> DeserializeSeed<'de> for TreeSeed<K, V> {
+    type Value = Trie<K, V>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Trie<K, V>, D::Error> {
+        let mut trie = Trie::new();
+        let mut prefix = Vec::new();
+
+        deserializer.deserialize_map(LevelSeed {
+            trie: &mut trie,
+            prefix: &mut prefix,
+            separator: self.separator,
+        })?;
+
+        Ok(trie)
+    }
+}
+
+// An entry of a level of the nested representation, which is either a value or a nested level.
+enum Item<'a, K: 'a, V: 'a> {
+    Value(&'a [u8], &'a V),
+    // The key prefix up to (but not including) the separator, the node containing all and only
+    // the keys which begin with that prefix and the separator, and the depth of the nested level.
+    Level(&'a [u8], &'a Node<K, V>, usize),
+}
+
+// A single level of the nested representation: the node containing all and only the keys which
+// begin with some prefix of length `depth`, which ends in a separator if it is not empty.
+struct Level<'a, K: 'a, V: 'a> {
+    node: Option<&'a Node<K, V>>,
+    depth: usize,
+    separator: u8,
+}
+
+impl<'a, K: Borrow<[u8]>, V> Level<'a, K, V> {
+    // Visit the entries of the level in trie order. Nested levels are found by descending the trie
+    // only as far as the first branch whose keys all share a separator past the level's depth, so
+    // their keys are never visited here.
+    fn for_each<E, F>(&self, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(Item<'a, K, V>) -> Result<(), E>,
+    {
+        let mut stack: Vec<&'a Node<K, V>> = self.node.into_iter().collect();
+
+        while let Some(node) = stack.pop() {
+            let shared = match *node {
+                Node::Leaf(ref leaf) => leaf.key_slice(),
+                Node::Branch(ref branch) => &node.first_leaf().key_slice()[..branch.choice() / 2],
+            };
+
+            let separator = shared[self.depth..]
+                .iter()
+                .position(|&b| b == self.separator);
+
+            if let Some(i) = separator {
+                let end = self.depth + i;
+                f(Item::Level(&shared[..end], node, end + 1))?;
+                continue;
+            }
+
+            match *node {
+                Node::Leaf(ref leaf) => f(Item::Value(&shared[self.depth..], &leaf.val))?,
+                Node::Branch(ref branch) => stack.extend(branch.iter().rev()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Segment<'a>(&'a [u8]);
+
+impl<'a> Serialize for Segment<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match str::from_utf8(self.0) {
+            Ok(s) => serializer.serialize_str(s),
+            Err(..) => serializer.serialize_bytes(self.0),
+        }
+    }
+}
+
+impl<'a, K: Borrow<[u8]>, V: Serialize> Serialize for Level<'a, K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut len = 0;
+        self.for_each(|_| -> Result<(), S::Error> {
+            len += 1;
+            Ok(())
+        })?;
+
+        let mut map = serializer.serialize_map(Some(len))?;
+        self.for_each(|item| match item {
+            Item::Value(segment, val) => map.serialize_entry(&Segment(segment), val),
+            Item::Level(prefix, node, depth) => {
+                if self.node.and_then(|root| root.get(prefix)).is_some() {
+                    return Err(S::Error::custom(
+                        "a key is also a prefix of other keys up to a separator",
+                    ));
+                }
+
+                map.serialize_entry(
+                    &Segment(&prefix[self.depth..]),
+                    &Level {
+                        node: Some(node),
+                        depth,
+                        separator: self.separator,
+                    },
+                )
+            }
+        })?;
+        map.end()
+    }
+}
+
+// Deserializes a map of segments, inserting its values into `trie` with keys beginning with
+// `prefix`.
+struct LevelSeed<'s, K: 's, V: 's> {
+    trie: &'s mut Trie<K, V>,
+    prefix: &'s mut Vec<u8>,
+    separator: u8,
+}
+
+impl<'de, 's, K: KeyFromBytes, V: Deserialize<'de>> Visitor<'de> for LevelSeed<'s, K, V> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of key segments")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let len = self.prefix.len();
+
+        while let Some(SegmentBuf(segment)) = map.next_key()? {
+            if segment.contains(&self.separator) {
+                return Err(de::Error::custom("key segment contains the separator"));
+            }
+
+            self.prefix.extend_from_slice(&segment);
+            map.next_value_seed(ValueSeed {
+                trie: &mut *self.trie,
+                prefix: &mut *self.prefix,
+                separator: self.separator,
+            })?;
+            self.prefix.truncate(len);
+        }
+
+        Ok(())
+    }
+}
+
+struct SegmentBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for SegmentBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(SegmentVisitor)
+    }
+}
+
+struct SegmentVisitor;
+
+impl<'de> Visitor<'de> for SegmentVisitor {
+    type Value = SegmentBuf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a key segment")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<SegmentBuf, E> {
+        Ok(SegmentBuf(v.as_bytes().to_vec()))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<SegmentBuf, E> {
+        Ok(SegmentBuf(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<SegmentBuf, E> {
+        Ok(SegmentBuf(v))
+    }
+}
+
+// Deserializes the value for a segment, which is a nested level if it is a map, and a value of
+// type `V` otherwise. Since the value has to be inspected before `V` gets to see it, anything
+// which isn't a map is handed back to `V` through one of serde's value deserializers.
+struct ValueSeed<'s, K: 's, V: 's> {
+    trie: &'s mut Trie<K, V>,
+    prefix: &'s mut Vec<u8>,
+    separator: u8,
+}
+
+impl<'de, 's, K: KeyFromBytes, V: Deserialize<'de>> ValueSeed<'s, K, V> {
+    fn insert<E: de::Error>(self, val: Result<V, E>) -> Result<(), E> {
+        let key = K::from_key_bytes(self.prefix.clone()).ok_or_else(|| E::custom("invalid key"))?;
+        self.trie.insert(key, val?);
+        Ok(())
+    }
+}
+
+impl<'de, 's, K: KeyFromBytes, V: Deserialize<'de>> DeserializeSeed<'de> for ValueSeed<'s, K, V> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, 's, K: KeyFromBytes, V: Deserialize<'de>> Visitor<'de> for ValueSeed<'s, K, V> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a value or a map of key segments")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<(), A::Error> {
+        self.prefix.push(self.separator);
+        LevelSeed {
+            trie: self.trie,
+            prefix: self.prefix,
+            separator: self.separator,
+        }
+        .visit_map(map)
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<(), E> {
+        self.insert(V::deserialize(BoolDeserializer::new(v)))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<(), E> {
+        self.insert(V::deserialize(I64Deserializer::new(v)))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<(), E> {
+        self.insert(V::deserialize(U64Deserializer::new(v)))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<(), E> {
+        self.insert(V::deserialize(F64Deserializer::new(v)))
+    }
+
+    fn visit_char<E: de::Error>(self, v: char) -> Result<(), E> {
+        self.insert(V::deserialize(CharDeserializer::new(v)))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<(), E> {
+        self.insert(V::deserialize(StrDeserializer::new(v)))
+    }
+
+    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<(), E> {
+        self.insert(V::deserialize(BorrowedStrDeserializer::new(v)))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<(), E> {
+        self.insert(V::deserialize(StringDeserializer::new(v)))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<(), E> {
+        self.insert(V::deserialize(BytesDeserializer::new(v)))
+    }
+
+    fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> Result<(), E> {
+        self.insert(V::deserialize(BorrowedBytesDeserializer::new(v)))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<(), E> {
+        self.insert(V::deserialize(UnitDeserializer::new()))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        self.insert(V::deserialize(UnitDeserializer::new()))
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        self.insert(V::deserialize(deserializer))
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        self.insert(V::deserialize(deserializer))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<(), A::Error> {
+        self.insert(V::deserialize(SeqAccessDeserializer::new(seq)))
+    }
+}
@@ -0,0 +1,67 @@
+//! A representation of a `Trie` as a sequence of key/value pairs, for use with
+//! `#[serde(with = "qp_trie::serialization::pairs")]`. This is useful for formats which only allow
+//! strings as map keys.
+
+use core::borrow::Borrow;
+use core::fmt;
+use core::marker::PhantomData;
+
+use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+use trie::Trie;
+
+/// Serialize a trie as a sequence of key/value pairs.
+pub fn serialize<K, V, S>(trie: &Trie<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Serialize + Borrow<[u8]>,
+    V: Serialize,
+    S: Serializer,
+{
+    let mut seq = serializer.serialize_seq(Some(trie.count()))?;
+    for entry in trie.iter() {
+        seq.serialize_element(&entry)?;
+    }
+    seq.end()
+}
+
+/// Deserialize a trie from a sequence of key/value pairs. Later pairs overwrite earlier pairs with
+/// the same key.
+pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<Trie<K, V>, D::Error>
+where
+    K: Deserialize<'de> + Borrow<[u8]>,
+    V: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_seq(PairsVisitor {
+        marker: PhantomData,
+    })
+}
+
+struct PairsVisitor<K, V> {
+    marker: PhantomData<fn() -> Trie<K, V>>,
+}
+
+impl<'de, K, V> Visitor<'de> for PairsVisitor<K, V>
+where
+    K: Deserialize<'de> + Borrow<[u8]>,
+    V: Deserialize<'de>,
+{
+    type Value = Trie<K, V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence of key/value pairs")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut trie = Trie::new();
+        while let Some((key, value)) = seq.next_element()? {
+            trie.insert(key, value);
+        }
+
+        Ok(trie)
+    }
+}
@@ -0,0 +1,231 @@
+use core::borrow::Borrow;
+use core::fmt;
+use core::marker::PhantomData;
+
+use serde::de::{Deserialize, DeserializeSeed, Deserializer, Error, MapAccess, Visitor};
+
+use entry::Entry;
+use trie::Trie;
+
+/// What to do when a key appears more than once in a deserialized map.
+enum Duplicates<F> {
+    Reject,
+    KeepFirst,
+    KeepLast,
+    Merge(F),
+}
+
+/// A configurable `DeserializeSeed` for `Trie`, for loading maps from untrusted sources.
+///
+/// By default, a `TrieSeed` behaves exactly like `Trie`'s `Deserialize` impl: later values for a
+/// duplicated key overwrite earlier ones, and there are no limits on size. Limits are checked as
+/// entries are read, so an input with too many entries or too many key bytes in total is rejected
+/// without being loaded in full. Each key is checked against `max_key_len` only once it has been
+/// deserialized, though, so an oversized key is read into memory before it is rejected; bounding
+/// the memory spent on any single key is up to the deserializer, as with bincode's size limits.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate qp_trie;
+/// # extern crate serde;
+/// # extern crate serde_json;
+/// use qp_trie::serialization::TrieSeed;
+/// use qp_trie::wrapper::BString;
+/// use qp_trie::Trie;
+/// use serde::de::DeserializeSeed;
+///
+/// # fn main() {
+/// let json = r#"{"a": 1, "b": 2, "a": 3}"#;
+///
+/// let seed = TrieSeed::new().merge_duplicates(|old: &mut u32, new| *old += new);
+/// let trie: Trie<BString, u32> = seed
+///     .deserialize(&mut serde_json::Deserializer::from_str(json))
+///     .unwrap();
+/// assert_eq!(trie.get_str("a"), Some(&4));
+///
+/// let seed = TrieSeed::<BString, u32>::new().reject_duplicates();
+/// assert!(seed
+///     .deserialize(&mut serde_json::Deserializer::from_str(json))
+///     .is_err());
+///
+/// let seed = TrieSeed::<BString, u32>::new().max_entries(1);
+/// assert!(seed
+///     .deserialize(&mut serde_json::Deserializer::from_str(json))
+///     .is_err());
+/// # }
+/// ```
+pub struct TrieSeed<K, V, F = fn(&mut V, V)> {
+    duplicates: Duplicates<F>,
+    max_entries: Option<usize>,
+    max_key_len: Option<usize>,
+    max_key_bytes: Option<usize>,
+    marker: PhantomData<fn() -> Trie<K, V>>,
+}
+
+impl<K, V> TrieSeed<K, V> {
+    /// Create a seed which keeps the last value for a duplicated key and imposes no limits.
+    pub fn new() -> TrieSeed<K, V> {
+        TrieSeed {
+            duplicates: Duplicates::KeepLast,
+            max_entries: None,
+            max_key_len: None,
+            max_key_bytes: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<K, V> Default for TrieSeed<K, V> {
+    fn default() -> TrieSeed<K, V> {
+        TrieSeed::new()
+    }
+}
+
+impl<K, V, F> TrieSeed<K, V, F> {
+    /// Fail if any key appears more than once.
+    pub fn reject_duplicates(mut self) -> Self {
+        self.duplicates = Duplicates::Reject;
+        self
+    }
+
+    /// Keep the first value seen for a duplicated key, discarding the rest.
+    pub fn keep_first(mut self) -> Self {
+        self.duplicates = Duplicates::KeepFirst;
+        self
+    }
+
+    /// Keep the last value seen for a duplicated key, discarding the rest. This is the default.
+    pub fn keep_last(mut self) -> Self {
+        self.duplicates = Duplicates::KeepLast;
+        self
+    }
+
+    /// Combine the values of a duplicated key with the given closure, which is passed the value
+    /// kept so far and the new value.
+    pub fn merge_duplicates<G: FnMut(&mut V, V)>(self, merge: G) -> TrieSeed<K, V, G> {
+        TrieSeed {
+            duplicates: Duplicates::Merge(merge),
+            max_entries: self.max_entries,
+            max_key_len: self.max_key_len,
+            max_key_bytes: self.max_key_bytes,
+            marker: PhantomData,
+        }
+    }
+
+    /// Fail if the map has more than `max` entries. Duplicated keys count once for every time
+    /// they appear.
+    pub fn max_entries(mut self, max: usize) -> Self {
+        self.max_entries = Some(max);
+        self
+    }
+
+    /// Fail if any key is longer than `max` bytes. This is checked after the key has been
+    /// deserialized, before it is inserted.
+    pub fn max_key_len(mut self, max: usize) -> Self {
+        self.max_key_len = Some(max);
+        self
+    }
+
+    /// Fail if the lengths of all keys read add up to more than `max` bytes.
+    pub fn max_key_bytes(mut self, max: usize) -> Self {
+        self.max_key_bytes = Some(max);
+        self
+    }
+}
+
+impl<'de, K, V, F> DeserializeSeed<'de> for TrieSeed<K, V, F>
+where
+    K: Deserialize<'de> + Borrow<[u8]>,
+    V: Deserialize<'de>,
+    F: FnMut(&mut V, V),
+{
+    type Value = Trie<K, V>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, K, V, F> Visitor<'de> for TrieSeed<K, V, F>
+where
+    K: Deserialize<'de> + Borrow<[u8]>,
+    V: Deserialize<'de>,
+    F: FnMut(&mut V, V),
+{
+    type Value = Trie<K, V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a qp-trie")
+    }
+
+    fn visit_map<M>(mut self, mut access: M) -> Result<Self::Value, M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        if let (Some(max), Some(len)) = (self.max_entries, access.size_hint()) {
+            if len > max {
+                return Err(M::Error::custom(format_args!(
+                    "map has {} entries, more than the maximum of {}",
+                    len, max
+                )));
+            }
+        }
+
+        let mut trie = Trie::new();
+        let mut entries = 0;
+        let mut key_bytes = 0usize;
+
+        while let Some(key) = access.next_key::<K>()? {
+            entries += 1;
+            if let Some(max) = self.max_entries {
+                if entries > max {
+                    return Err(M::Error::custom(format_args!(
+                        "map has more than the maximum of {} entries",
+                        max
+                    )));
+                }
+            }
+
+            let len = key.borrow().len();
+            if let Some(max) = self.max_key_len {
+                if len > max {
+                    return Err(M::Error::custom(format_args!(
+                        "key of {} bytes is longer than the maximum of {}",
+                        len, max
+                    )));
+                }
+            }
+
+            key_bytes = key_bytes.saturating_add(len);
+            if let Some(max) = self.max_key_bytes {
+                if key_bytes > max {
+                    return Err(M::Error::custom(format_args!(
+                        "keys are longer than the maximum of {} bytes in total",
+                        max
+                    )));
+                }
+            }
+
+            let value = access.next_value()?;
+            match trie.entry(key) {
+                Entry::Vacant(vacant) => {
+                    vacant.insert(value);
+                }
+                Entry::Occupied(mut occupied) => match self.duplicates {
+                    Duplicates::Reject => return Err(M::Error::custom("duplicate key")),
+                    Duplicates::KeepFirst => {}
+                    Duplicates::KeepLast => {
+                        occupied.insert(value);
+                    }
+                    Duplicates::Merge(ref mut merge) => merge(occupied.get_mut(), value),
+                },
+            }
+        }
+
+        Ok(trie)
+    }
+}
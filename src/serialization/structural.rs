@@ -0,0 +1,511 @@
+//! A structure-preserving representation of a `Trie`, for use with
+//! `#[serde(with = "qp_trie::serialization::structural")]`.
+//!
+//! The default representation of a `Trie` is a flat map, and deserializing it inserts every entry
+//! from the root. This representation instead writes out every branch with its choice point and
+//! bitmap of occupied children, and writes each key without the bytes it shares with the rest of
+//! its branch. Deserializing it rebuilds the nodes directly, in time linear in the size of the
+//! input. The rebuilt trie is checked for consistency, so input which does not describe a valid
+//! trie is rejected rather than producing a broken one.
+//!
+//! Deep tries nest deeply in this representation, and each level of nesting is deserialized
+//! recursively. Nesting is therefore limited, to `DEFAULT_MAX_DEPTH` branches by `deserialize` and
+//! as configured by a `StructuralSeed`, so that crafted input cannot overflow the stack; tries
+//! nested more deeply than the limit cannot be read back. Formats with recursion limits of their
+//! own (such as `serde_json`) may refuse even shallower tries. Compact binary formats such as
+//! `bincode` are a better fit. None of this bounds the memory used by the keys and values read,
+//! so input from an untrusted source also needs size limits in the deserializer.
+//!
+//! # Example
+//!
+//! ```rust
+//! # extern crate bincode;
+//! # extern crate qp_trie;
+//! # #[macro_use]
+//! # extern crate serde;
+//! use qp_trie::Trie;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Snapshot {
+//!     #[serde(with = "qp_trie::serialization::structural")]
+//!     trie: Trie<Vec<u8>, u32>,
+//! }
+//!
+//! # fn main() {
+//! let mut trie = Trie::new();
+//! trie.insert(b"abc".to_vec(), 1);
+//! trie.insert(b"abd".to_vec(), 2);
+//!
+//! let bytes = bincode::serialize(&Snapshot { trie: trie.clone() }).unwrap();
+//! let snapshot: Snapshot = bincode::deserialize(&bytes).unwrap();
+//!
+//! assert_eq!(snapshot.trie, trie);
+//! # }
+//! ```
+
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt;
+use core::marker::PhantomData;
+
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, SeqAccess, VariantAccess, Visitor,
+};
+use serde::ser::{SerializeSeq, SerializeTupleVariant, Serializer};
+use serde::{Deserialize, Serialize};
+
+use node::{Branch, Leaf, Node};
+use serialization::KeyFromBytes;
+use trie::Trie;
+use util::{nybble_index, nybble_mismatch};
+
+const VARIANTS: &[&str] = &["Leaf", "Branch"];
+
+/// Serialize a trie, preserving its structure.
+pub fn serialize<K, V, S>(trie: &Trie<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Borrow<[u8]>,
+    V: Serialize,
+    S: Serializer,
+{
+    match trie.root() {
+        Some(node) => serializer.serialize_some(&NodeRef { node, skip: 0 }),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// The number of nested branches `deserialize` accepts.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Deserialize a trie which was serialized with `serialize`, accepting at most
+/// `DEFAULT_MAX_DEPTH` nested branches.
+pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<Trie<K, V>, D::Error>
+where
+    K: KeyFromBytes,
+    V: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    StructuralSeed::new().deserialize(deserializer)
+}
+
+#[derive(Clone, Copy)]
+struct Limits {
+    max_depth: usize,
+    max_key_len: usize,
+}
+
+/// A configurable `DeserializeSeed` for the structural representation of a `Trie`.
+///
+/// Branches are deserialized recursively, so the depth of nesting accepted is limited in order to
+/// bound the stack used. Choice points must increase with depth, so limiting the length of keys
+/// limits the depth as well: a trie whose keys are at most `n` bytes long has branches nested at
+/// most `2 * n` deep.
+pub struct StructuralSeed<K, V> {
+    limits: Limits,
+    marker: PhantomData<fn() -> Trie<K, V>>,
+}
+
+impl<K, V> StructuralSeed<K, V> {
+    /// Create a seed accepting at most `DEFAULT_MAX_DEPTH` nested branches, and keys of any length.
+    pub fn new() -> StructuralSeed<K, V> {
+        StructuralSeed {
+            limits: Limits {
+                max_depth: DEFAULT_MAX_DEPTH,
+                max_key_len: usize::MAX,
+            },
+            marker: PhantomData,
+        }
+    }
+
+    /// Fail if branches are nested more than `max` deep.
+    pub fn max_depth(mut self, max: usize) -> Self {
+        self.limits.max_depth = max;
+        self
+    }
+
+    /// Fail if any key is longer than `max` bytes, or if a branch's choice point implies one.
+    pub fn max_key_len(mut self, max: usize) -> Self {
+        self.limits.max_key_len = max;
+        self
+    }
+}
+
+impl<K, V> Default for StructuralSeed<K, V> {
+    fn default() -> StructuralSeed<K, V> {
+        StructuralSeed::new()
+    }
+}
+
+impl<'de, K: KeyFromBytes, V: Deserialize<'de>> DeserializeSeed<'de> for StructuralSeed<K, V> {
+    type Value = Trie<K, V>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Trie<K, V>, D::Error> {
+        deserializer.deserialize_option(TrieVisitor {
+            limits: self.limits,
+            marker: PhantomData,
+        })
+    }
+}
+
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Serialize for Bytes<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+// A node, serialized without the first `skip` bytes of its keys.
+struct NodeRef<'a, K: 'a, V: 'a> {
+    node: &'a Node<K, V>,
+    skip: usize,
+}
+
+impl<'a, K: Borrow<[u8]>, V: Serialize> Serialize for NodeRef<'a, K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self.node {
+            Node::Leaf(ref leaf) => {
+                let mut variant = serializer.serialize_tuple_variant("Node", 0, "Leaf", 2)?;
+                variant.serialize_field(&Bytes(&leaf.key_slice()[self.skip..]))?;
+                variant.serialize_field(&leaf.val)?;
+                variant.end()
+            }
+            Node::Branch(ref branch) => {
+                let shared = branch.choice() / 2;
+                let key = self.node.first_leaf().key_slice();
+
+                let mut variant = serializer.serialize_tuple_variant("Node", 1, "Branch", 4)?;
+                variant.serialize_field(&Bytes(&key[self.skip..shared]))?;
+                variant.serialize_field(&(branch.choice() as u64))?;
+                variant.serialize_field(&branch.bitmap())?;
+                variant.serialize_field(&Children {
+                    branch,
+                    skip: shared,
+                })?;
+                variant.end()
+            }
+        }
+    }
+}
+
+struct Children<'a, K: 'a, V: 'a> {
+    branch: &'a Branch<K, V>,
+    skip: usize,
+}
+
+impl<'a, K: Borrow<[u8]>, V: Serialize> Serialize for Children<'a, K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.branch.iter().len()))?;
+        for node in self.branch.iter() {
+            seq.serialize_element(&NodeRef {
+                node,
+                skip: self.skip,
+            })?;
+        }
+        seq.end()
+    }
+}
+
+struct TrieVisitor<K, V> {
+    limits: Limits,
+    marker: PhantomData<fn() -> Trie<K, V>>,
+}
+
+impl<'de, K: KeyFromBytes, V: Deserialize<'de>> Visitor<'de> for TrieVisitor<K, V> {
+    type Value = Trie<K, V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an optional qp-trie node")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(Trie::new())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(Trie::new())
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let mut prefix = Vec::new();
+        let mut count = 0;
+
+        let root = NodeSeed {
+            prefix: &mut prefix,
+            parent: None,
+            count: &mut count,
+            depth: 0,
+            limits: self.limits,
+            marker: PhantomData,
+        }
+        .deserialize(deserializer)?;
+
+        Ok(Trie::from_root(Some(root), count))
+    }
+}
+
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_byte_buf(ByteBufVisitor)
+    }
+}
+
+struct ByteBufVisitor;
+
+impl<'de> Visitor<'de> for ByteBufVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a byte string")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(ByteBuf(bytes))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(variant_identifier)]
+enum Variant {
+    Leaf,
+    Branch,
+}
+
+// Deserializes a node whose keys all begin with `prefix`, beneath a branch with the choice point
+// `parent` nested `depth` branches deep. The number of leaves deserialized is added to `count`.
+struct NodeSeed<'s, K, V> {
+    prefix: &'s mut Vec<u8>,
+    parent: Option<usize>,
+    count: &'s mut usize,
+    depth: usize,
+    limits: Limits,
+    marker: PhantomData<fn() -> Node<K, V>>,
+}
+
+impl<'de, 's, K: KeyFromBytes, V: Deserialize<'de>> DeserializeSeed<'de> for NodeSeed<'s, K, V> {
+    type Value = Node<K, V>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Node<K, V>, D::Error> {
+        deserializer.deserialize_enum("Node", VARIANTS, self)
+    }
+}
+
+impl<'de, 's, K: KeyFromBytes, V: Deserialize<'de>> Visitor<'de> for NodeSeed<'s, K, V> {
+    type Value = Node<K, V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a qp-trie node")
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Node<K, V>, A::Error> {
+        match data.variant()? {
+            (Variant::Leaf, access) => access.tuple_variant(2, LeafVisitor(self)),
+            (Variant::Branch, access) => access.tuple_variant(4, BranchVisitor(self)),
+        }
+    }
+}
+
+struct LeafVisitor<'s, K, V>(NodeSeed<'s, K, V>);
+
+impl<'de, 's, K: KeyFromBytes, V: Deserialize<'de>> Visitor<'de> for LeafVisitor<'s, K, V> {
+    type Value = Node<K, V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a qp-trie leaf")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node<K, V>, A::Error> {
+        let ByteBuf(suffix) = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let val = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+
+        let len = self.0.prefix.len() + suffix.len();
+        if len > self.0.limits.max_key_len {
+            return Err(de::Error::custom(format_args!(
+                "key of {} bytes is longer than the maximum of {}",
+                len, self.0.limits.max_key_len
+            )));
+        }
+
+        let mut bytes = Vec::with_capacity(len);
+        bytes.extend_from_slice(self.0.prefix);
+        bytes.extend_from_slice(&suffix);
+        let key = K::from_key_bytes(bytes).ok_or_else(|| de::Error::custom("invalid key"))?;
+
+        *self.0.count += 1;
+
+        Ok(Node::Leaf(Leaf::new(key, val)))
+    }
+}
+
+struct BranchVisitor<'s, K, V>(NodeSeed<'s, K, V>);
+
+impl<'de, 's, K: KeyFromBytes, V: Deserialize<'de>> Visitor<'de> for BranchVisitor<'s, K, V> {
+    type Value = Node<K, V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a qp-trie branch")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node<K, V>, A::Error> {
+        let ByteBuf(shared) = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let choice: u64 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        let bitmap: u32 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+
+        let choice = choice as usize;
+        let skip = match self.0.parent {
+            Some(parent) if choice <= parent => {
+                return Err(de::Error::custom(
+                    "branch choice points must increase with depth",
+                ));
+            }
+            Some(parent) => parent / 2,
+            None => 0,
+        };
+
+        // Only the child in the head slot of a branch can end at its choice point, so the others
+        // are longer.
+        if choice / 2 >= self.0.limits.max_key_len {
+            return Err(de::Error::custom(format_args!(
+                "branch implies keys longer than the maximum of {} bytes",
+                self.0.limits.max_key_len
+            )));
+        }
+
+        if self.0.depth >= self.0.limits.max_depth {
+            return Err(de::Error::custom(format_args!(
+                "branches are nested more deeply than the maximum of {}",
+                self.0.limits.max_depth
+            )));
+        }
+
+        if shared.len() != choice / 2 - skip {
+            return Err(de::Error::custom("branch prefix has the wrong length"));
+        }
+
+        if bitmap >> 17 != 0 || bitmap.count_ones() < 2 {
+            return Err(de::Error::custom("invalid branch bitmap"));
+        }
+
+        let NodeSeed {
+            prefix,
+            count,
+            depth,
+            limits,
+            ..
+        } = self.0;
+        let len = prefix.len();
+        prefix.extend_from_slice(&shared);
+
+        let children = seq.next_element_seed(ChildrenSeed {
+            prefix: &mut *prefix,
+            choice,
+            bitmap,
+            count,
+            depth: depth + 1,
+            limits,
+            marker: PhantomData,
+        })?;
+
+        prefix.truncate(len);
+
+        children.ok_or_else(|| de::Error::invalid_length(3, &"a qp-trie branch"))
+    }
+}
+
+struct ChildrenSeed<'s, K, V> {
+    prefix: &'s mut Vec<u8>,
+    choice: usize,
+    bitmap: u32,
+    count: &'s mut usize,
+    depth: usize,
+    limits: Limits,
+    marker: PhantomData<fn() -> Node<K, V>>,
+}
+
+impl<'de, 's, K: KeyFromBytes, V: Deserialize<'de>> DeserializeSeed<'de>
+    for ChildrenSeed<'s, K, V>
+{
+    type Value = Node<K, V>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Node<K, V>, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 's, K: KeyFromBytes, V: Deserialize<'de>> Visitor<'de> for ChildrenSeed<'s, K, V> {
+    type Value = Node<K, V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence of qp-trie nodes")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node<K, V>, A::Error> {
+        let arity = self.bitmap.count_ones() as usize;
+        let mut children = Vec::with_capacity(arity);
+
+        while let Some(child) = seq.next_element_seed(NodeSeed {
+            prefix: &mut *self.prefix,
+            parent: Some(self.choice),
+            count: &mut *self.count,
+            depth: self.depth,
+            limits: self.limits,
+            marker: PhantomData,
+        })? {
+            if children.len() == arity {
+                return Err(de::Error::invalid_length(arity + 1, &self));
+            }
+
+            children.push(child);
+        }
+
+        if children.len() != arity {
+            return Err(de::Error::invalid_length(children.len(), &self));
+        }
+
+        // Every child must sit in the slot for its nybble at the choice point, and must agree with
+        // its siblings on every nybble before the choice point.
+        let exemplar = children[0].first_leaf().key_slice();
+        let indices = (0..17u8).filter(|&i| self.bitmap & (1 << i) != 0);
+        for (child, index) in children.iter().zip(indices) {
+            let key = child.first_leaf().key_slice();
+
+            if nybble_index(self.choice, key) != index {
+                return Err(de::Error::custom("branch child is in the wrong slot"));
+            }
+
+            if key != exemplar && nybble_mismatch(exemplar, key) != Some(self.choice) {
+                return Err(de::Error::custom("branch children do not share a prefix"));
+            }
+        }
+
+        Ok(Node::Branch(Branch::from_parts(
+            self.choice,
+            self.bitmap,
+            children,
+        )))
+    }
+}
@@ -7,18 +7,31 @@ use unreachable::UncheckedOptionExt;
 // A sparse array, holding up to 17 elements, indexed by nybbles with a special exception for
 // elements which are shorter than the "choice point" of the branch node which holds this sparse
 // array. This special exception is the "head".
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone)]
 pub struct Sparse<T> {
     index: u32,
     entries: Vec<T>,
 }
 
+// The bitmap of occupied indices only needs the low 17 bits of `index`. The top bit is left free
+// for the owner of the array to use as a flag, at no cost in space.
+const FLAG: u32 = 1 << 31;
+
+impl<T: PartialEq> PartialEq for Sparse<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bitmap() == other.bitmap() && self.entries == other.entries
+    }
+}
+
+impl<T: Eq> Eq for Sparse<T> {}
+
 impl<T: fmt::Debug> fmt::Debug for Sparse<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
             "Sparse {{ index: {:b}, entries: {:?} }}",
-            self.index, self.entries
+            self.bitmap(),
+            self.entries
         )
     }
 }
@@ -32,11 +45,45 @@ impl<T> Sparse<T> {
         }
     }
 
+    // Reassemble a sparse array from a bitmap and the elements for each set bit, in order.
+    #[inline]
+    pub fn from_parts(index: u32, entries: Vec<T>) -> Sparse<T> {
+        debug_assert!(index.count_ones() as usize == entries.len());
+        Sparse { index, entries }
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.entries.len()
     }
 
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // The raw bitmap of occupied nybble indices. Bit `i` is set if and only if the array holds an
+    // element for index `i`.
+    #[inline]
+    pub fn bitmap(&self) -> u32 {
+        self.index & !FLAG
+    }
+
+    // The flag kept alongside the bitmap, which is clear for a new array.
+    #[inline]
+    pub fn flag(&self) -> bool {
+        self.index & FLAG != 0
+    }
+
+    #[inline]
+    pub fn set_flag(&mut self, flag: bool) {
+        if flag {
+            self.index |= FLAG;
+        } else {
+            self.index &= !FLAG;
+        }
+    }
+
     // Go from a nybble-index to an index in the internal element vector.
     #[inline]
     fn actual(&self, idx: u8) -> usize {
@@ -70,29 +117,62 @@ impl<T> Sparse<T> {
         }
     }
 
-    // Immutably borrow the element corresponding to this index if it exists - otherwise, immutably
-    // borrow an arbitrary element of the array.
-    // TODO: Faster to not branch and just calculate the index and return it?
+    // A raw pointer to the corresponding element, if it exists. Unlike `Sparse::get_mut`, this does
+    // not create a mutable reference to the element storage, so pointers previously obtained to
+    // other elements remain valid.
     #[inline]
-    pub fn get_or_any(&self, idx: u8) -> &T {
+    pub fn get_ptr(&mut self, idx: u8) -> Option<*mut T> {
         if self.contains(idx) {
-            &self.entries[self.actual(idx)]
+            let i = self.actual(idx);
+
+            // unsafe: `i` is the position of a present element, so it is in bounds.
+            Some(unsafe { self.entries.as_mut_ptr().add(i) })
         } else {
-            &self.entries[0]
+            None
         }
     }
 
+    // The position in the internal element vector of the element for the given index if it exists,
+    // or zero otherwise. This avoids a branch: the popcount is masked out when the bit is unset.
+    #[inline]
+    fn actual_or_first(&self, idx: u8) -> usize {
+        let present = (self.index >> idx) & 1;
+        self.actual(idx) & (present as usize).wrapping_neg()
+    }
+
+    // Immutably borrow the element corresponding to this index if it exists - otherwise, immutably
+    // borrow an arbitrary element of the array.
+    //
+    // PRECONDITION:
+    // - The array is nonempty.
+    #[inline]
+    pub fn get_or_any(&self, idx: u8) -> &T {
+        debug_assert!(!self.is_empty());
+        let i = self.actual_or_first(idx);
+
+        // unsafe: `i` is either the position of a present element or zero, and the array is
+        // nonempty.
+        unsafe { self.entries.get_unchecked(i) }
+    }
+
     // Mutably borrow the element corresponding to this index if it exists - otherwise, mutably
     // borrow an arbitrary element of the array.
-    // TODO: Faster to not branch and just calculate the index and return it?
+    //
+    // PRECONDITION:
+    // - The array is nonempty.
     #[inline]
     pub fn get_or_any_mut(&mut self, idx: u8) -> &mut T {
-        if self.contains(idx) {
-            let i = self.actual(idx);
-            &mut self.entries[i]
-        } else {
-            &mut self.entries[0]
-        }
+        debug_assert!(!self.is_empty());
+        let i = self.actual_or_first(idx);
+
+        // unsafe: same rationale as `get_or_any`.
+        unsafe { self.entries.get_unchecked_mut(i) }
+    }
+
+    // A pointer to the internal element storage, for prefetching.
+    #[inline]
+    pub fn as_ptr(&self) -> *const T {
+        self.entries.as_ptr()
     }
 
     // Assuming that the array does not already contain an element for this index, insert the
@@ -123,6 +203,27 @@ impl<T> Sparse<T> {
         unsafe { self.entries.pop().unchecked_unwrap() }
     }
 
+    // Remove every element for which `f` returns false, keeping the remaining elements at their
+    // indices.
+    #[inline]
+    pub fn retain<F: FnMut(&mut T) -> bool>(&mut self, mut f: F) {
+        let mut remaining = self.bitmap();
+        let mut index = self.index;
+
+        self.entries.retain_mut(|elt| {
+            let bit = remaining & remaining.wrapping_neg();
+            remaining &= !bit;
+
+            let keep = f(elt);
+            if !keep {
+                index &= !bit;
+            }
+            keep
+        });
+
+        self.index = index;
+    }
+
     #[inline]
     pub fn iter(&self) -> Iter<T> {
         self.entries.iter()
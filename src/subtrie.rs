@@ -1,15 +1,23 @@
+use alloc::vec::Vec;
 use core::borrow::Borrow;
 use core::fmt;
 use core::ops::Index;
 
-use iter::Iter;
+use entry::Entry;
+use iter::{Iter, IterMut, Keys, Values};
+use measure::Measure;
 use node::Node;
+use trie::{Break, Trie};
+use util::nybble_mismatch;
+use wrapper::{BStr, BString};
 
-pub struct SubTrie<'a, K: 'a, V: 'a> {
-    pub(crate) root: Option<&'a Node<K, V>>,
+/// An immutable view into a trie, holding only the entries whose keys begin with a given prefix.
+pub struct SubTrie<'a, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
+    pub(crate) root: Option<&'a Node<K, V, M>>,
+    prefix: Vec<u8>,
 }
 
-impl<'a, K: fmt::Debug, V: fmt::Debug> fmt::Debug for SubTrie<'a, K, V> {
+impl<'a, K: fmt::Debug, V: fmt::Debug, M: Measure<K, V>> fmt::Debug for SubTrie<'a, K, V, M> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.root {
             Some(node) => f.debug_map().entries(node.iter()).finish(),
@@ -18,8 +26,8 @@ impl<'a, K: fmt::Debug, V: fmt::Debug> fmt::Debug for SubTrie<'a, K, V> {
     }
 }
 
-impl<'a, K: 'a, V: 'a> IntoIterator for SubTrie<'a, K, V> {
-    type IntoIter = Iter<'a, K, V>;
+impl<'a, K: 'a, V: 'a, M: Measure<K, V>> IntoIterator for SubTrie<'a, K, V, M> {
+    type IntoIter = Iter<'a, K, V, M>;
     type Item = (&'a K, &'a V);
 
     fn into_iter(self) -> Self::IntoIter {
@@ -27,45 +35,397 @@ impl<'a, K: 'a, V: 'a> IntoIterator for SubTrie<'a, K, V> {
     }
 }
 
-impl<'a, K: 'a, V: 'a> SubTrie<'a, K, V> {
+impl<'a, K: 'a, V: 'a, M: Measure<K, V>> SubTrie<'a, K, V, M> {
+    pub(crate) fn new(root: Option<&'a Node<K, V, M>>, prefix: Vec<u8>) -> SubTrie<'a, K, V, M> {
+        SubTrie { root, prefix }
+    }
+
+    /// The prefix which selected this subtrie. Every key in the subtrie begins with it.
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
     /// Returns true if the subtrie has no entries.
     pub fn is_empty(&self) -> bool {
         self.root.is_none()
     }
-}
 
-impl<'a, K: Borrow<[u8]>, V> SubTrie<'a, K, V> {
-    pub fn iter(&self) -> Iter<'a, K, V> {
+    /// Count the number of entries in the subtrie. Unlike `Trie::count`, this traverses the
+    /// subtrie, so it takes time linear in its size.
+    pub fn len(&self) -> usize {
+        self.root.map(Node::count).unwrap_or(0)
+    }
+
+    /// Iterate over all elements in the subtrie.
+    pub fn iter(&self) -> Iter<'a, K, V, M> {
         match self.root {
             Some(node) => node.iter(),
             None => Iter::default(),
         }
     }
 
-    pub fn iter_prefix<L: Borrow<[u8]>>(&self, prefix: L) -> Iter<'a, K, V> {
+    /// Iterate over all keys in the subtrie.
+    pub fn keys(&self) -> Keys<'a, K, V, M> {
+        match self.root {
+            Some(node) => Keys::new(node),
+            None => Keys::default(),
+        }
+    }
+
+    /// Iterate over all values in the subtrie.
+    pub fn values(&self) -> Values<'a, K, V, M> {
+        match self.root {
+            Some(node) => Values::new(node),
+            None => Values::default(),
+        }
+    }
+
+    /// Get the first entry of the subtrie in iteration order. Note that iteration order compares
+    /// the low nybble of each byte before the high nybble, so this is not necessarily the entry
+    /// with the lexicographically smallest key.
+    pub fn first(&self) -> Option<(&'a K, &'a V)> {
+        self.root.map(|node| {
+            let leaf = node.first_leaf();
+            (&leaf.key, &leaf.val)
+        })
+    }
+
+    /// Get the last entry of the subtrie in iteration order.
+    pub fn last(&self) -> Option<(&'a K, &'a V)> {
+        self.root.map(|node| {
+            let leaf = node.last_leaf();
+            (&leaf.key, &leaf.val)
+        })
+    }
+
+    /// Copy the entries of the subtrie into a new trie.
+    pub fn to_owned_trie(&self) -> Trie<K, V, M>
+    where
+        K: Clone,
+        V: Clone,
+        M::Summary: Clone,
+    {
+        Trie::from_root(self.root.cloned(), self.len())
+    }
+}
+
+impl<'a, K: Borrow<[u8]>, V, M: Measure<K, V>> SubTrie<'a, K, V, M> {
+    /// Iterate over all elements with a given prefix.
+    pub fn iter_prefix<Q>(&self, prefix: &Q) -> Iter<'a, K, V, M>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+    {
         match self.root.and_then(|node| node.get_prefix(prefix.borrow())) {
             Some(node) => node.iter(),
             None => Iter::default(),
         }
     }
 
-    pub fn subtrie<L: Borrow<[u8]>>(&self, prefix: L) -> SubTrie<'a, K, V> {
-        SubTrie {
-            root: self.root.and_then(|node| node.get_prefix(prefix.borrow())),
+    /// Get a narrower view, providing only values keyed with the given prefix.
+    pub fn subtrie<Q>(&self, prefix: &Q) -> SubTrie<'a, K, V, M>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        let prefix = prefix.borrow();
+        let root = self.root.and_then(|node| node.get_prefix(prefix));
+
+        // Every key in the narrower view begins with both prefixes, so keep the longer one.
+        let prefix = if prefix.len() > self.prefix.len() {
+            prefix
+        } else {
+            &self.prefix[..]
+        };
+        SubTrie::new(root, prefix.to_vec())
+    }
+
+    /// Get the longest common prefix of all the nodes in the subtrie and the given key.
+    pub fn longest_common_prefix<Q>(&self, key: &Q) -> &'a K::Split
+    where
+        K: Borrow<Q> + Break,
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        match self.root {
+            Some(root) => {
+                let exemplar = root.get_exemplar(key.borrow());
+
+                match nybble_mismatch(exemplar.key_slice(), key.borrow()) {
+                    Some(i) => exemplar.key.find_break(i / 2),
+                    None => exemplar.key.borrow(),
+                }
+            }
+            None => K::empty(),
         }
     }
 
-    pub fn get<L: Borrow<[u8]>>(&self, key: L) -> Option<&'a V> {
+    /// Returns true if there is an entry for the given key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        self.root.and_then(|node| node.get(key.borrow())).is_some()
+    }
+
+    /// Get an immutable reference to the value associated with a given key, if it is in the
+    /// subtrie.
+    pub fn get<Q>(&self, key: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+    {
         self.root
             .and_then(|node| node.get(key.borrow()))
             .map(|leaf| &leaf.val)
     }
+
+    /// Get immutable references to the key and value associated with a given key, if it is in the
+    /// subtrie.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&'a K, &'a V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        self.root
+            .and_then(|node| node.get(key.borrow()))
+            .map(|leaf| (&leaf.key, &leaf.val))
+    }
 }
 
-impl<'a, K: Borrow<[u8]>, V, L: Borrow<[u8]>> Index<L> for SubTrie<'a, K, V> {
+impl<'a, 'b, K: Borrow<[u8]>, V, M: Measure<K, V>, Q> Index<&'b Q> for SubTrie<'a, K, V, M>
+where
+    K: Borrow<Q>,
+    Q: ?Sized + Borrow<[u8]>,
+{
     type Output = V;
 
-    fn index(&self, key: L) -> &V {
+    fn index(&self, key: &Q) -> &V {
         self.get(key).unwrap()
     }
 }
+
+impl<'a, V, M: Measure<BString, V>> SubTrie<'a, BString, V, M> {
+    /// Convenience function for iterating over suffixes with a string.
+    pub fn iter_prefix_str<Q>(&self, key: &Q) -> Iter<'a, BString, V, M>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.iter_prefix(AsRef::<BStr>::as_ref(key.borrow()))
+    }
+
+    /// Convenience function for viewing subtries with a string prefix.
+    pub fn subtrie_str<Q>(&self, prefix: &Q) -> SubTrie<'a, BString, V, M>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.subtrie(AsRef::<BStr>::as_ref(prefix.borrow()))
+    }
+
+    /// Returns true if there is an entry for the given string key.
+    pub fn contains_key_str<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.contains_key(AsRef::<BStr>::as_ref(key.borrow()))
+    }
+
+    /// Convenience function for getting with a string.
+    pub fn get_str<Q>(&self, key: &Q) -> Option<&'a V>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.get(AsRef::<BStr>::as_ref(key.borrow()))
+    }
+}
+
+/// A mutable view into a trie, holding only the entries whose keys begin with a given prefix.
+/// Keys outside of the prefix cannot be read or modified through the view.
+///
+/// # Example
+///
+/// ```rust
+/// # use qp_trie::Trie;
+/// let mut trie = Trie::new();
+/// trie.insert_str("alice/x", 1);
+/// trie.insert_str("alice/y", 2);
+/// trie.insert_str("bob/x", 3);
+///
+/// {
+///     let mut alice = trie.subtrie_mut_str("alice/");
+///     alice.insert_str("alice/z", 4);
+///     alice.retain(|_, v| *v % 2 == 0);
+///     assert_eq!(alice.get_str("bob/x"), None);
+///     assert_eq!(alice.remove_str("bob/x"), None);
+/// }
+///
+/// assert_eq!(trie.count(), 3);
+/// assert_eq!(trie.get_str("alice/x"), None);
+/// assert_eq!(trie.get_str("bob/x"), Some(&3));
+/// ```
+pub struct SubTrieMut<'a, K: 'a, V: 'a, M: 'a + Measure<K, V> = ()> {
+    trie: &'a mut Trie<K, V, M>,
+    prefix: Vec<u8>,
+}
+
+impl<'a, K: fmt::Debug + Borrow<[u8]>, V: fmt::Debug, M: Measure<K, V>> fmt::Debug
+    for SubTrieMut<'a, K, V, M>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_subtrie().fmt(f)
+    }
+}
+
+impl<'a, K: Borrow<[u8]>, V, M: Measure<K, V>> SubTrieMut<'a, K, V, M> {
+    pub(crate) fn new(trie: &'a mut Trie<K, V, M>, prefix: Vec<u8>) -> SubTrieMut<'a, K, V, M> {
+        SubTrieMut { trie, prefix }
+    }
+
+    /// The prefix which all keys in the view begin with.
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    // Mutably borrow the node holding all and only the entries in the view, if there are any.
+    pub(crate) fn node_mut(&mut self) -> Option<&mut Node<K, V, M>> {
+        let prefix = &self.prefix[..];
+        self.trie
+            .root_mut()
+            .and_then(|node| node.get_prefix_mut(prefix))
+    }
+
+    /// Borrow the view immutably.
+    pub fn as_subtrie(&self) -> SubTrie<'_, K, V, M> {
+        self.trie.subtrie(&self.prefix[..])
+    }
+
+    /// Returns true if the view has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.as_subtrie().is_empty()
+    }
+
+    /// Iterate over all elements in the view.
+    pub fn iter(&self) -> Iter<'_, K, V, M> {
+        self.trie.iter_prefix(&self.prefix[..])
+    }
+
+    /// Iterate over all elements in the view, given a mutable reference to the associated value.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V, M> {
+        self.trie.iter_prefix_mut(&self.prefix[..])
+    }
+
+    /// Get an immutable reference to the value associated with a given key, if it is in the view.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        if key.borrow().starts_with(&self.prefix) {
+            self.trie.get(key)
+        } else {
+            None
+        }
+    }
+
+    /// Get a mutable reference to the value associated with a given key, if it is in the view.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        if key.borrow().starts_with(&self.prefix) {
+            self.trie.get_mut(key)
+        } else {
+            None
+        }
+    }
+
+    /// Insert a key/value pair into the trie, returning the old value if an entry already existed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key does not begin with the view's prefix.
+    pub fn insert(&mut self, key: K, val: V) -> Option<V> {
+        assert!(
+            key.borrow().starts_with(&self.prefix),
+            "key does not begin with the subtrie's prefix"
+        );
+        self.trie.insert(key, val)
+    }
+
+    /// Remove the key/value pair associated with a given key from the trie, returning
+    /// `Some(val)` if a corresponding key/value pair was found in the view.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        if key.borrow().starts_with(&self.prefix) {
+            self.trie.remove(key)
+        } else {
+            None
+        }
+    }
+
+    /// Get the corresponding entry for the given key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key does not begin with the view's prefix.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, M> {
+        assert!(
+            key.borrow().starts_with(&self.prefix),
+            "key does not begin with the subtrie's prefix"
+        );
+        self.trie.entry(key)
+    }
+
+    /// Retain only the entries in the view for which `f` returns true. Entries outside of the view
+    /// are untouched.
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, f: F) {
+        self.trie.retain_prefix(&self.prefix, f);
+    }
+
+    /// Remove all entries in the view from the trie.
+    pub fn clear(&mut self) {
+        self.trie.remove_prefix(&self.prefix[..]);
+    }
+}
+
+impl<'a, V, M: Measure<BString, V>> SubTrieMut<'a, BString, V, M> {
+    /// Convenience function for getting with a string.
+    pub fn get_str<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.get(AsRef::<BStr>::as_ref(key.borrow()))
+    }
+
+    /// Convenience function for getting mutably with a string.
+    pub fn get_mut_str<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.get_mut(AsRef::<BStr>::as_ref(key.borrow()))
+    }
+
+    /// Convenience function for inserting with a string.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key does not begin with the view's prefix.
+    pub fn insert_str<Q>(&mut self, key: &Q, val: V) -> Option<V>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.insert(key.borrow().into(), val)
+    }
+
+    /// Convenience function for removing with a string.
+    pub fn remove_str<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.remove(AsRef::<BStr>::as_ref(key.borrow()))
+    }
+}
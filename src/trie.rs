@@ -1,13 +1,18 @@
 use alloc::borrow::ToOwned;
+use alloc::vec::Vec;
 use core::borrow::Borrow;
 use core::fmt;
 use core::iter::FromIterator;
 use core::ops::{Index, IndexMut};
+use core::ptr;
 
-use entry::{make_entry, Entry};
+use entry::{make_entry, make_entry_ref, Entry, EntryRef, OccupiedError};
+use frozen;
 use iter::{IntoIter, Iter, IterMut, Keys, Values, ValuesMut};
+use measure::Measure;
 use node::{Leaf, Node};
-use subtrie::SubTrie;
+use prefix_entry::{make_prefix_entry, PrefixEntry};
+use subtrie::{SubTrie, SubTrieMut};
 use util::nybble_mismatch;
 use wrapper::{BStr, BString};
 
@@ -21,6 +26,9 @@ use wrapper::{BStr, BString};
 /// a byte slice.) As a stopgap, `qp_trie::wrapper::{BString, BStr}` are provided, as are the
 /// `.whatever_str()` convenience methods on `qp_trie::Trie<BString, _>`.
 ///
+/// A trie may also keep a summary of its entries under a `Measure`, `M`, cached in each of its
+/// branches. By default it keeps none.
+///
 /// # Example
 ///
 /// ```rust
@@ -67,19 +75,38 @@ use wrapper::{BStr, BString};
 /// *subtrie.get_mut_str("bdde").unwrap() = 0;
 /// assert_eq!(subtrie.get_str("bdde"), Some(&0));
 /// ```
-#[derive(Clone, PartialEq, Eq)]
-pub struct Trie<K, V> {
-    root: Option<Node<K, V>>,
+pub struct Trie<K, V, M: Measure<K, V> = ()> {
+    root: Option<Node<K, V, M>>,
     count: usize,
 }
 
+impl<K: Clone, V: Clone, M: Measure<K, V>> Clone for Trie<K, V, M>
+where
+    M::Summary: Clone,
+{
+    fn clone(&self) -> Self {
+        Trie {
+            root: self.root.clone(),
+            count: self.count,
+        }
+    }
+}
+
+impl<K: PartialEq, V: PartialEq, M: Measure<K, V>> PartialEq for Trie<K, V, M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.count == other.count && self.root == other.root
+    }
+}
+
+impl<K: Eq, V: Eq, M: Measure<K, V>> Eq for Trie<K, V, M> {}
+
 impl<K, V> Default for Trie<K, V> {
     fn default() -> Self {
         Trie::new()
     }
 }
 
-impl<K: fmt::Debug + ToOwned, V: fmt::Debug> fmt::Debug for Trie<K, V> {
+impl<K: fmt::Debug + ToOwned, V: fmt::Debug, M: Measure<K, V>> fmt::Debug for Trie<K, V, M> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.root {
             Some(ref node) => f.debug_map().entries(node.iter()).finish(),
@@ -88,8 +115,8 @@ impl<K: fmt::Debug + ToOwned, V: fmt::Debug> fmt::Debug for Trie<K, V> {
     }
 }
 
-impl<K, V> IntoIterator for Trie<K, V> {
-    type IntoIter = IntoIter<K, V>;
+impl<K, V, M: Measure<K, V>> IntoIterator for Trie<K, V, M> {
+    type IntoIter = IntoIter<K, V, M>;
     type Item = (K, V);
 
     fn into_iter(self) -> Self::IntoIter {
@@ -114,7 +141,7 @@ impl<K: Borrow<[u8]>, V> FromIterator<(K, V)> for Trie<K, V> {
     }
 }
 
-impl<K: Borrow<[u8]>, V> Extend<(K, V)> for Trie<K, V> {
+impl<K: Borrow<[u8]>, V, M: Measure<K, V>> Extend<(K, V)> for Trie<K, V, M> {
     fn extend<I>(&mut self, iterable: I)
     where
         I: IntoIterator<Item = (K, V)>,
@@ -125,9 +152,9 @@ impl<K: Borrow<[u8]>, V> Extend<(K, V)> for Trie<K, V> {
     }
 }
 
-impl<K, V> Trie<K, V> {
-    /// Create a new, empty trie.
-    pub fn new() -> Trie<K, V> {
+impl<K, V, M: Measure<K, V>> Trie<K, V, M> {
+    /// Create a new, empty trie summarizing its entries with the measure `M`.
+    pub fn with_measure() -> Trie<K, V, M> {
         Trie {
             root: None,
             count: 0,
@@ -135,23 +162,15 @@ impl<K, V> Trie<K, V> {
     }
 
     /// Iterate over all elements in the trie.
-    pub fn iter(&self) -> Iter<K, V> {
+    pub fn iter(&self) -> Iter<K, V, M> {
         match self.root {
             Some(ref node) => Iter::new(node),
             None => Iter::default(),
         }
     }
 
-    /// Iterate over all elements in the trie, given a mutable reference to the associated value.
-    pub fn iter_mut(&mut self) -> IterMut<K, V> {
-        match self.root {
-            Some(ref mut node) => IterMut::new(node),
-            None => IterMut::default(),
-        }
-    }
-
     /// Iterate over all keys in the trie.
-    pub fn keys(&self) -> Keys<K, V> {
+    pub fn keys(&self) -> Keys<K, V, M> {
         match self.root {
             Some(ref node) => Keys::new(node),
             None => Keys::default(),
@@ -159,15 +178,23 @@ impl<K, V> Trie<K, V> {
     }
 
     /// Iterate over all values in the trie.
-    pub fn values(&self) -> Values<K, V> {
+    pub fn values(&self) -> Values<K, V, M> {
         match self.root {
             Some(ref node) => Values::new(node),
             None => Values::default(),
         }
     }
 
+    /// Iterate over all elements in the trie, given a mutable reference to the associated value.
+    pub fn iter_mut(&mut self) -> IterMut<K, V, M> {
+        match self.root {
+            Some(ref mut node) => IterMut::new(node),
+            None => IterMut::default(),
+        }
+    }
+
     /// Iterate over all values in the trie, mutably.
-    pub fn values_mut(&mut self) -> ValuesMut<K, V> {
+    pub fn values_mut(&mut self) -> ValuesMut<K, V, M> {
         match self.root {
             Some(ref mut node) => ValuesMut::new(node),
             None => ValuesMut::default(),
@@ -184,11 +211,41 @@ impl<K, V> Trie<K, V> {
     pub fn is_empty(&self) -> bool {
         self.root.is_none()
     }
+
+    // Borrow the root node of the trie, if it has one.
+    pub(crate) fn root(&self) -> Option<&Node<K, V, M>> {
+        self.root.as_ref()
+    }
+
+    // Construct a trie directly from a root node and the number of entries beneath it.
+    pub(crate) fn from_root(root: Option<Node<K, V, M>>, count: usize) -> Trie<K, V, M> {
+        Trie { root, count }
+    }
+
+    // Mutably borrow the root node of the trie, if it has one.
+    pub(crate) fn root_mut(&mut self) -> Option<&mut Node<K, V, M>> {
+        self.root.as_mut()
+    }
+
+    // Take apart the trie, leaving only its root node.
+    pub(crate) fn into_root(self) -> Option<Node<K, V, M>> {
+        self.root
+    }
+}
+
+impl<K, V> Trie<K, V> {
+    /// Create a new, empty trie.
+    pub fn new() -> Trie<K, V> {
+        Trie {
+            root: None,
+            count: 0,
+        }
+    }
 }
 
-impl<K: Borrow<[u8]>, V> Trie<K, V> {
+impl<K: Borrow<[u8]>, V, M: Measure<K, V>> Trie<K, V, M> {
     /// Iterate over all elements with a given prefix.
-    pub fn iter_prefix<'a, Q: ?Sized>(&'a self, prefix: &Q) -> Iter<'a, K, V>
+    pub fn iter_prefix<'a, Q: ?Sized>(&'a self, prefix: &Q) -> Iter<'a, K, V, M>
     where
         K: Borrow<Q>,
         Q: Borrow<[u8]>,
@@ -203,37 +260,6 @@ impl<K: Borrow<[u8]>, V> Trie<K, V> {
         }
     }
 
-    /// Iterate over all elements with a given prefix, but given a mutable reference to the
-    /// associated value.
-    pub fn iter_prefix_mut<'a, Q: ?Sized>(&'a mut self, prefix: &Q) -> IterMut<'a, K, V>
-    where
-        K: Borrow<Q>,
-        Q: Borrow<[u8]>,
-    {
-        match self
-            .root
-            .as_mut()
-            .and_then(|node| node.get_prefix_mut(prefix.borrow()))
-        {
-            Some(node) => IterMut::new(node),
-            None => IterMut::default(),
-        }
-    }
-
-    /// Get an immutable view into the trie, providing only values keyed with the given prefix.
-    pub fn subtrie<'a, Q: ?Sized>(&'a self, prefix: &Q) -> SubTrie<'a, K, V>
-    where
-        K: Borrow<Q>,
-        Q: Borrow<[u8]>,
-    {
-        SubTrie {
-            root: self
-                .root
-                .as_ref()
-                .and_then(|node| node.get_prefix(prefix.borrow())),
-        }
-    }
-
     /// Get the longest common prefix of all the nodes in the trie and the given key.
     pub fn longest_common_prefix<'a, Q: ?Sized>(&'a self, key: &Q) -> &'a K::Split
     where
@@ -282,16 +308,17 @@ impl<K: Borrow<[u8]>, V> Trie<K, V> {
             .map(|leaf| &leaf.val)
     }
 
-    /// Get a mutable reference to the value associated with a given key, if it is in the tree.
-    pub fn get_mut<'a, Q: ?Sized>(&'a mut self, key: &Q) -> Option<&'a mut V>
+    /// Get immutable references to the key and value associated with a given key, if it is in the
+    /// tree.
+    pub fn get_key_value<'a, Q>(&'a self, key: &Q) -> Option<(&'a K, &'a V)>
     where
         K: Borrow<Q>,
-        Q: Borrow<[u8]>,
+        Q: ?Sized + Borrow<[u8]>,
     {
         self.root
-            .as_mut()
-            .and_then(|node| node.get_mut(key.borrow()))
-            .map(|leaf| &mut leaf.val)
+            .as_ref()
+            .and_then(|node| node.get(key.borrow()))
+            .map(|leaf| (&leaf.key, &leaf.val))
     }
 
     /// Insert a key/value pair into the trie, returning the old value if an entry already existed.
@@ -326,9 +353,23 @@ impl<K: Borrow<[u8]>, V> Trie<K, V> {
         node
     }
 
+    /// Remove the key/value pair associated with a given key from the trie, returning both the
+    /// stored key and the value if a corresponding key/value pair was found.
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        let leaf = Node::remove(&mut self.root, key.borrow());
+        if leaf.is_some() {
+            self.count -= 1;
+        }
+        leaf.map(|leaf| (leaf.key, leaf.val))
+    }
+
     /// Remove all elements beginning with a given prefix from the trie, producing a subtrie
     /// containing the removed elements.
-    pub fn remove_prefix<Q: ?Sized>(&mut self, prefix: &Q) -> Trie<K, V>
+    pub fn remove_prefix<Q: ?Sized>(&mut self, prefix: &Q) -> Trie<K, V, M>
     where
         K: Borrow<Q>,
         Q: Borrow<[u8]>,
@@ -339,13 +380,163 @@ impl<K: Borrow<[u8]>, V> Trie<K, V> {
         Trie { root, count }
     }
 
+    /// Iterate over all elements with a given prefix, but given a mutable reference to the
+    /// associated value.
+    pub fn iter_prefix_mut<'a, Q: ?Sized>(&'a mut self, prefix: &Q) -> IterMut<'a, K, V, M>
+    where
+        K: Borrow<Q>,
+        Q: Borrow<[u8]>,
+    {
+        match self
+            .root
+            .as_mut()
+            .and_then(|node| node.get_prefix_mut(prefix.borrow()))
+        {
+            Some(node) => IterMut::new(node),
+            None => IterMut::default(),
+        }
+    }
+
+    /// Get an immutable view into the trie, providing only values keyed with the given prefix.
+    pub fn subtrie<'a, Q: ?Sized>(&'a self, prefix: &Q) -> SubTrie<'a, K, V, M>
+    where
+        K: Borrow<Q>,
+        Q: Borrow<[u8]>,
+    {
+        let prefix = prefix.borrow();
+        SubTrie::new(
+            self.root.as_ref().and_then(|node| node.get_prefix(prefix)),
+            prefix.to_vec(),
+        )
+    }
+
+    /// Get a mutable view into the trie, providing only values keyed with the given prefix. Every
+    /// key read or written through the view is constrained to begin with the prefix.
+    pub fn subtrie_mut<'a, Q>(&'a mut self, prefix: &Q) -> SubTrieMut<'a, K, V, M>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        SubTrieMut::new(self, prefix.borrow().to_vec())
+    }
+
+    /// Get a mutable reference to the value associated with a given key, if it is in the tree.
+    pub fn get_mut<'a, Q: ?Sized>(&'a mut self, key: &Q) -> Option<&'a mut V>
+    where
+        K: Borrow<Q>,
+        Q: Borrow<[u8]>,
+    {
+        self.root
+            .as_mut()
+            .and_then(|node| node.get_mut(key.borrow()))
+            .map(|leaf| &mut leaf.val)
+    }
+
+    /// Get mutable references to the values associated with several keys at once. Returns `None`
+    /// if any of the keys is not in the tree, or if any key is given more than once.
+    pub fn get_many_mut<'a, Q, const N: usize>(
+        &'a mut self,
+        keys: [&Q; N],
+    ) -> Option<[&'a mut V; N]>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        let mut leaves = [ptr::null_mut::<Leaf<K, V>>(); N];
+
+        // Every walk starts from this one pointer, so that no walk invalidates the leaves found by
+        // the others.
+        let root = self.root.as_mut().map(|root| root as *mut Node<K, V, M>);
+
+        for (i, key) in keys.iter().enumerate() {
+            let key = (*key).borrow();
+
+            if keys[..i].iter().any(|other| (*other).borrow() == key) {
+                return None;
+            }
+
+            // unsafe: `root` points into the trie, which is mutably borrowed.
+            leaves[i] = unsafe { Node::get_ptr(root?, key)? };
+        }
+
+        // unsafe: the keys are distinct, so every pointer is to a different leaf, and the trie is
+        // mutably borrowed for as long as the references live.
+        Some(leaves.map(|leaf| unsafe { &mut (*leaf).val }))
+    }
+
+    // Remove every entry beginning with `prefix` for which `f` returns false.
+    pub(crate) fn retain_prefix<F>(&mut self, prefix: &[u8], mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.count -= Node::retain_prefix(&mut self.root, prefix, &mut f);
+    }
+
+    /// Insert a key/value pair into the trie only if the key is not already present, returning a
+    /// mutable reference to the inserted value. If the key is present, nothing is overwritten, and
+    /// the error holds the occupied entry along with the rejected value.
+    pub fn try_insert(&mut self, key: K, val: V) -> Result<&mut V, OccupiedError<'_, K, V, M>> {
+        match self.entry(key) {
+            Entry::Occupied(entry) => Err(OccupiedError { entry, value: val }),
+            Entry::Vacant(entry) => Ok(entry.insert(val)),
+        }
+    }
+
     /// Get the corresponding entry for the given key.
-    pub fn entry(&mut self, key: K) -> Entry<K, V> {
+    pub fn entry(&mut self, key: K) -> Entry<K, V, M> {
         make_entry(key, &mut self.root, &mut self.count)
     }
+
+    /// Get the entry for the given prefix, which is occupied if any key begins with the prefix.
+    /// This finds the node holding those keys once, so that they can then be visited, removed,
+    /// or added to without searching for it again.
+    pub fn prefix_entry<'a, 'b, Q>(&'a mut self, prefix: &'b Q) -> PrefixEntry<'a, 'b, K, V, M>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        make_prefix_entry(prefix.borrow(), &mut self.root, &mut self.count)
+    }
+
+    /// Get the corresponding entry for the given borrowed key. Unlike `Trie::entry`, this only
+    /// converts the key into an owned key if a value is inserted into a vacant entry.
+    pub fn entry_ref<'a, 'b, Q>(&'a mut self, key: &'b Q) -> EntryRef<'a, 'b, K, Q, V, M>
+    where
+        Q: ?Sized + Borrow<[u8]> + ToOwned<Owned = K>,
+    {
+        make_entry_ref(key, &mut self.root, &mut self.count)
+    }
+
+    /// Write a compact, position-independent, read-only image of the trie, which can be read back
+    /// with `FrozenTrie` without deserialization. Values are stored as the bytes they borrow as.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the image would be larger than 2GiB.
+    pub fn freeze(&self) -> Vec<u8>
+    where
+        V: AsRef<[u8]>,
+    {
+        self.freeze_with(|val, buf| buf.extend_from_slice(val.as_ref()))
+    }
+
+    /// Write a frozen image of the trie as with `Trie::freeze`, using the given closure to append
+    /// the bytes stored for each value to the image. Each value begins on a multiple of
+    /// `FrozenTrie::VALUE_ALIGN` bytes into the image, so fixed-size encodings can be read back in
+    /// place.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the image would be larger than 2GiB.
+    pub fn freeze_with<F>(&self, value: F) -> Vec<u8>
+    where
+        F: FnMut(&V, &mut Vec<u8>),
+    {
+        frozen::freeze(self.root.as_ref(), self.count, value)
+    }
 }
 
-impl<'a, K: Borrow<[u8]>, V, Q: ?Sized> Index<&'a Q> for Trie<K, V>
+impl<'a, K: Borrow<[u8]>, V, M: Measure<K, V>, Q: ?Sized> Index<&'a Q> for Trie<K, V, M>
 where
     K: Borrow<Q>,
     Q: Borrow<[u8]>,
@@ -357,7 +548,7 @@ where
     }
 }
 
-impl<'a, K: Borrow<[u8]>, V, Q: ?Sized> IndexMut<&'a Q> for Trie<K, V>
+impl<'a, K: Borrow<[u8]>, V, M: Measure<K, V>, Q: ?Sized> IndexMut<&'a Q> for Trie<K, V, M>
 where
     K: Borrow<Q>,
     Q: Borrow<[u8]>,
@@ -402,76 +593,100 @@ impl<'b> Break for &'b [u8] {
     }
 }
 
-impl<V> Trie<BString, V> {
+impl<V, M: Measure<BString, V>> Trie<BString, V, M> {
     /// Convenience function for iterating over suffixes with a string.
-    pub fn iter_prefix_str<'a, Q: ?Sized>(&'a self, key: &Q) -> Iter<'a, BString, V>
+    pub fn iter_prefix_str<'a, Q: ?Sized>(&'a self, key: &Q) -> Iter<'a, BString, V, M>
     where
         Q: Borrow<str>,
     {
         self.iter_prefix(AsRef::<BStr>::as_ref(key.borrow()))
     }
 
-    /// Convenience function for iterating over suffixes with a string.
-    pub fn iter_prefix_mut_str<'a, Q: ?Sized>(&'a mut self, key: &Q) -> IterMut<'a, BString, V>
+    /// Returns true if there is an entry for the given string key.
+    pub fn contains_key_str<Q: ?Sized>(&self, key: &Q) -> bool
     where
         Q: Borrow<str>,
     {
-        self.iter_prefix_mut(AsRef::<BStr>::as_ref(key.borrow()))
+        self.contains_key(AsRef::<BStr>::as_ref(key.borrow()))
     }
 
-    /// Convenience function for viewing subtries wit a string prefix.
-    pub fn subtrie_str<'a, Q: ?Sized>(&'a self, prefix: &Q) -> SubTrie<'a, BString, V>
+    /// Convenience function for getting with a string.
+    pub fn get_str<'a, Q: ?Sized>(&'a self, key: &Q) -> Option<&'a V>
     where
         Q: Borrow<str>,
     {
-        self.subtrie(AsRef::<BStr>::as_ref(prefix.borrow()))
+        self.get(AsRef::<BStr>::as_ref(key.borrow()))
     }
 
-    /// Returns true if there is an entry for the given string key.
-    pub fn contains_key_str<Q: ?Sized>(&self, key: &Q) -> bool
+    /// Convenience function for inserting with a string.
+    pub fn insert_str<Q: ?Sized>(&mut self, key: &Q, val: V) -> Option<V>
     where
         Q: Borrow<str>,
     {
-        self.contains_key(AsRef::<BStr>::as_ref(key.borrow()))
+        self.insert(key.borrow().into(), val)
     }
 
-    /// Convenience function for getting with a string.
-    pub fn get_str<'a, Q: ?Sized>(&'a self, key: &Q) -> Option<&'a V>
+    /// Convenience function for removing with a string.
+    pub fn remove_str<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
     where
         Q: Borrow<str>,
     {
-        self.get(AsRef::<BStr>::as_ref(key.borrow()))
+        self.remove(AsRef::<BStr>::as_ref(key.borrow()))
     }
 
-    /// Convenience function for getting mutably with a string.
-    pub fn get_mut_str<'a, Q: ?Sized>(&'a mut self, key: &Q) -> Option<&'a mut V>
+    /// Convenience function for removing a prefix with a string.
+    pub fn remove_prefix_str<Q: ?Sized>(&mut self, prefix: &Q) -> Trie<BString, V, M>
     where
         Q: Borrow<str>,
     {
-        self.get_mut(AsRef::<BStr>::as_ref(key.borrow()))
+        self.remove_prefix(AsRef::<BStr>::as_ref(prefix.borrow()))
     }
 
-    /// Convenience function for inserting with a string.
-    pub fn insert_str<Q: ?Sized>(&mut self, key: &Q, val: V) -> Option<V>
+    /// Convenience function for iterating over suffixes with a string.
+    pub fn iter_prefix_mut_str<'a, Q: ?Sized>(&'a mut self, key: &Q) -> IterMut<'a, BString, V, M>
     where
         Q: Borrow<str>,
     {
-        self.insert(key.borrow().into(), val)
+        self.iter_prefix_mut(AsRef::<BStr>::as_ref(key.borrow()))
     }
 
-    /// Convenience function for removing with a string.
-    pub fn remove_str<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+    /// Convenience function for viewing subtries wit a string prefix.
+    pub fn subtrie_str<'a, Q: ?Sized>(&'a self, prefix: &Q) -> SubTrie<'a, BString, V, M>
     where
         Q: Borrow<str>,
     {
-        self.remove(AsRef::<BStr>::as_ref(key.borrow()))
+        self.subtrie(AsRef::<BStr>::as_ref(prefix.borrow()))
     }
 
-    /// Convenience function for removing a prefix with a string.
-    pub fn remove_prefix_str<Q: ?Sized>(&mut self, prefix: &Q) -> Trie<BString, V>
+    /// Convenience function for viewing subtries mutably with a string prefix.
+    pub fn subtrie_mut_str<'a, Q>(&'a mut self, prefix: &Q) -> SubTrieMut<'a, BString, V, M>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.subtrie_mut(AsRef::<BStr>::as_ref(prefix.borrow()))
+    }
+
+    /// Convenience function for getting mutably with a string.
+    pub fn get_mut_str<'a, Q: ?Sized>(&'a mut self, key: &Q) -> Option<&'a mut V>
     where
         Q: Borrow<str>,
     {
-        self.remove_prefix(AsRef::<BStr>::as_ref(prefix.borrow()))
+        self.get_mut(AsRef::<BStr>::as_ref(key.borrow()))
+    }
+
+    /// Convenience function for getting the entry for a borrowed string.
+    pub fn entry_ref_str<'a, 'b>(
+        &'a mut self,
+        key: &'b str,
+    ) -> EntryRef<'a, 'b, BString, BStr, V, M> {
+        self.entry_ref(AsRef::<BStr>::as_ref(key))
+    }
+
+    /// Convenience function for getting the prefix entry for a string prefix.
+    pub fn prefix_entry_str<'a, 'b>(
+        &'a mut self,
+        prefix: &'b str,
+    ) -> PrefixEntry<'a, 'b, BString, V, M> {
+        self.prefix_entry(AsRef::<BStr>::as_ref(prefix))
     }
 }
@@ -0,0 +1,522 @@
+//! Composite keys made of typed elements, after the tuple layer of FoundationDB.
+//!
+//! A `TupleKey` encodes a sequence of elements such as integers, strings and byte strings into
+//! bytes which sort in the same order as the elements, and in which the encoding of a tuple is a
+//! prefix of the encoding of every tuple extending it. Prefix queries on whole elements therefore
+//! work as they should: the rows of one table are exactly the keys beginning with
+//! `tuple!(tenant, table)`, whatever the table is called. `Trie::longest_common_prefix` only ever
+//! breaks keys between elements.
+//!
+//! ```rust
+//! # #[macro_use] extern crate qp_trie;
+//! # fn main() {
+//! use qp_trie::Trie;
+//! use qp_trie::tuple::TupleKey;
+//!
+//! let mut rows = Trie::new();
+//! rows.insert(tuple!(7u32, "users", "alice"), 1);
+//! rows.insert(tuple!(7u32, "users", "bob"), 2);
+//! rows.insert(tuple!(7u32, "users_archive", "carol"), 3);
+//! rows.insert(tuple!(8u32, "users", "dave"), 4);
+//!
+//! let users = rows
+//!     .iter_prefix(&tuple!(7u32, "users"))
+//!     .map(|(key, _)| key.unpack::<(u32, String, String)>().unwrap().2)
+//!     .collect::<Vec<_>>();
+//! assert_eq!(users, ["alice", "bob"]);
+//!
+//! let common = rows.longest_common_prefix(&tuple!(7u32, "users", "zoe"));
+//! assert_eq!(common, tuple!(7u32, "users").as_bytes());
+//! # }
+//! ```
+//!
+//! Integers of every width and signedness share one encoding, so `1u8` and `1i64` are the same
+//! element, and decode into any integer type which can hold them.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::convert::TryFrom;
+use core::fmt;
+
+use trie::Break;
+
+// Type codes. The order of the codes is the order of elements of different types.
+const BYTES: u8 = 0x01;
+const STRING: u8 = 0x02;
+const INT_ZERO: u8 = 0x14;
+const INT_MIN: u8 = INT_ZERO - 8;
+const INT_MAX: u8 = INT_ZERO + 8;
+const FALSE: u8 = 0x26;
+const TRUE: u8 = 0x27;
+
+// Byte and string elements are terminated by a zero byte followed by `END`, and zero bytes within
+// them are escaped by following them with `ESCAPE`. Unlike FoundationDB, which terminates them
+// with a lone zero byte, this keeps the encoding of an element from being a prefix of that of a
+// longer one with a zero byte in place of the terminator, so that prefix queries are exact.
+const END: u8 = 0x01;
+const ESCAPE: u8 = 0xFF;
+
+/// An error encountered while decoding a tuple.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TupleError {
+    /// The encoding ends partway through an element, or has fewer elements than were asked for.
+    Truncated,
+    /// An element has a different type from the one asked for, or an unknown type code, which is
+    /// given.
+    UnexpectedType(u8),
+    /// A zero byte in a byte or string element is followed by neither an escape nor a
+    /// terminator, but by the byte given.
+    InvalidEscape(u8),
+    /// A string element is not valid UTF-8.
+    InvalidUtf8,
+    /// An integer element is out of range of the type asked for.
+    OutOfRange,
+    /// The encoding has more elements than were asked for.
+    TrailingElements,
+}
+
+impl fmt::Display for TupleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TupleError::Truncated => f.write_str("truncated tuple"),
+            TupleError::UnexpectedType(code) => {
+                write!(f, "unexpected tuple element type code {:#04x}", code)
+            }
+            TupleError::InvalidEscape(b) => {
+                write!(f, "invalid tuple escape sequence 0x00 {:#04x}", b)
+            }
+            TupleError::InvalidUtf8 => f.write_str("tuple string element is not valid UTF-8"),
+            TupleError::OutOfRange => f.write_str("tuple integer element is out of range"),
+            TupleError::TrailingElements => f.write_str("tuple has trailing elements"),
+        }
+    }
+}
+
+/// A type which can be encoded as one or more tuple elements. Tuples of such types encode as the
+/// concatenation of their elements.
+pub trait Encode {
+    /// Append the encoding of `self` to `out`.
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+/// A type which can be decoded from one or more tuple elements.
+pub trait Decode: Sized {
+    /// Decode a value from the start of `bytes`, returning it along with the number of bytes
+    /// decoded.
+    fn decode(bytes: &[u8]) -> Result<(Self, usize), TupleError>;
+}
+
+impl<T: ?Sized + Encode> Encode for &T {
+    #[inline]
+    fn encode(&self, out: &mut Vec<u8>) {
+        (**self).encode(out)
+    }
+}
+
+fn encode_escaped(code: u8, bytes: &[u8], out: &mut Vec<u8>) {
+    out.push(code);
+    for &b in bytes {
+        out.push(b);
+        if b == 0 {
+            out.push(ESCAPE);
+        }
+    }
+    out.extend_from_slice(&[0, END]);
+}
+
+fn decode_escaped(code: u8, bytes: &[u8]) -> Result<(Vec<u8>, usize), TupleError> {
+    match bytes.first() {
+        Some(&c) if c == code => {}
+        Some(&c) => return Err(TupleError::UnexpectedType(c)),
+        None => return Err(TupleError::Truncated),
+    }
+
+    let mut unescaped = Vec::new();
+    let mut i = 1;
+    loop {
+        match (bytes.get(i), bytes.get(i + 1)) {
+            (Some(0), Some(&ESCAPE)) => {
+                unescaped.push(0);
+                i += 2;
+            }
+            (Some(0), Some(&END)) => return Ok((unescaped, i + 2)),
+            (Some(0), Some(&b)) => return Err(TupleError::InvalidEscape(b)),
+            (Some(0), None) => return Err(TupleError::Truncated),
+            (Some(&b), _) => {
+                unescaped.push(b);
+                i += 1;
+            }
+            (None, _) => return Err(TupleError::Truncated),
+        }
+    }
+}
+
+impl Encode for [u8] {
+    #[inline]
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_escaped(BYTES, self, out)
+    }
+}
+
+impl<const N: usize> Encode for [u8; N] {
+    #[inline]
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_escaped(BYTES, self, out)
+    }
+}
+
+impl Encode for Vec<u8> {
+    #[inline]
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_escaped(BYTES, self, out)
+    }
+}
+
+impl Decode for Vec<u8> {
+    #[inline]
+    fn decode(bytes: &[u8]) -> Result<(Vec<u8>, usize), TupleError> {
+        decode_escaped(BYTES, bytes)
+    }
+}
+
+impl Encode for str {
+    #[inline]
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_escaped(STRING, self.as_bytes(), out)
+    }
+}
+
+impl Encode for String {
+    #[inline]
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_escaped(STRING, self.as_bytes(), out)
+    }
+}
+
+impl Decode for String {
+    fn decode(bytes: &[u8]) -> Result<(String, usize), TupleError> {
+        let (unescaped, len) = decode_escaped(STRING, bytes)?;
+        let string = String::from_utf8(unescaped).map_err(|_| TupleError::InvalidUtf8)?;
+        Ok((string, len))
+    }
+}
+
+impl Encode for bool {
+    #[inline]
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(if *self { TRUE } else { FALSE });
+    }
+}
+
+impl Decode for bool {
+    #[inline]
+    fn decode(bytes: &[u8]) -> Result<(bool, usize), TupleError> {
+        match bytes.first() {
+            Some(&FALSE) => Ok((false, 1)),
+            Some(&TRUE) => Ok((true, 1)),
+            Some(&code) => Err(TupleError::UnexpectedType(code)),
+            None => Err(TupleError::Truncated),
+        }
+    }
+}
+
+// Integers are encoded as their magnitude in as few big-endian bytes as possible, preceded by a
+// type code giving their sign and the number of bytes. The bytes of negative integers are
+// complemented, so that larger magnitudes sort first.
+fn encode_int(n: i128, out: &mut Vec<u8>) {
+    let magnitude = n.unsigned_abs() as u64;
+    let len = 8 - magnitude.leading_zeros() as usize / 8;
+    let bytes = magnitude.to_be_bytes();
+
+    if n < 0 {
+        out.push(INT_ZERO - len as u8);
+        out.extend(bytes[8 - len..].iter().map(|b| !b));
+    } else {
+        out.push(INT_ZERO + len as u8);
+        out.extend_from_slice(&bytes[8 - len..]);
+    }
+}
+
+fn decode_int(bytes: &[u8]) -> Result<(i128, usize), TupleError> {
+    let code = match bytes.first() {
+        Some(&code) if (INT_MIN..=INT_MAX).contains(&code) => code,
+        Some(&code) => return Err(TupleError::UnexpectedType(code)),
+        None => return Err(TupleError::Truncated),
+    };
+
+    let len = (code as i32 - INT_ZERO as i32).unsigned_abs() as usize;
+    let digits = bytes.get(1..1 + len).ok_or(TupleError::Truncated)?;
+    let value = digits.iter().fold(0u64, |value, &b| value << 8 | b as u64);
+
+    if code < INT_ZERO {
+        let mask = u64::MAX >> (64 - 8 * len);
+        Ok((-((!value & mask) as i128), 1 + len))
+    } else {
+        Ok((value as i128, 1 + len))
+    }
+}
+
+macro_rules! impl_int {
+    ($($int:ty),*) => {
+        $(
+            impl Encode for $int {
+                #[inline]
+                fn encode(&self, out: &mut Vec<u8>) {
+                    encode_int(*self as i128, out)
+                }
+            }
+
+            impl Decode for $int {
+                #[inline]
+                fn decode(bytes: &[u8]) -> Result<($int, usize), TupleError> {
+                    let (n, len) = decode_int(bytes)?;
+                    let n = <$int>::try_from(n).map_err(|_| TupleError::OutOfRange)?;
+                    Ok((n, len))
+                }
+            }
+        )*
+    };
+}
+
+impl_int!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+macro_rules! impl_tuple {
+    ($($name:ident)*) => {
+        impl<$($name: Encode),*> Encode for ($($name,)*) {
+            #[allow(non_snake_case)]
+            #[inline]
+            fn encode(&self, out: &mut Vec<u8>) {
+                let ($(ref $name,)*) = *self;
+                $($name.encode(out);)*
+            }
+        }
+
+        impl<$($name: Decode),*> Decode for ($($name,)*) {
+            #[allow(non_snake_case)]
+            #[inline]
+            fn decode(bytes: &[u8]) -> Result<(Self, usize), TupleError> {
+                let mut len = 0;
+                $(
+                    let ($name, n) = $name::decode(&bytes[len..])?;
+                    len += n;
+                )*
+                Ok((($($name,)*), len))
+            }
+        }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A B);
+impl_tuple!(A B C);
+impl_tuple!(A B C D);
+impl_tuple!(A B C D E);
+impl_tuple!(A B C D E F);
+impl_tuple!(A B C D E F G);
+impl_tuple!(A B C D E F G H);
+
+// The length of the encoding of the element at the start of `bytes`, whatever its type.
+fn element_len(bytes: &[u8]) -> Result<usize, TupleError> {
+    match bytes.first() {
+        Some(&BYTES) | Some(&STRING) => {
+            let mut i = 1;
+            loop {
+                match (bytes.get(i), bytes.get(i + 1)) {
+                    (Some(0), Some(&ESCAPE)) => i += 2,
+                    (Some(0), Some(&END)) => return Ok(i + 2),
+                    (Some(0), Some(&b)) => return Err(TupleError::InvalidEscape(b)),
+                    (Some(0), None) => return Err(TupleError::Truncated),
+                    (Some(_), _) => i += 1,
+                    (None, _) => return Err(TupleError::Truncated),
+                }
+            }
+        }
+        Some(&FALSE) | Some(&TRUE) => Ok(1),
+        Some(_) => decode_int(bytes).map(|(_, len)| len),
+        None => Err(TupleError::Truncated),
+    }
+}
+
+/// Decode a whole encoded tuple, such as a prefix of a `TupleKey` returned by
+/// `Trie::longest_common_prefix`.
+pub fn unpack<T: Decode>(bytes: &[u8]) -> Result<T, TupleError> {
+    let (tuple, len) = T::decode(bytes)?;
+
+    if len == bytes.len() {
+        Ok(tuple)
+    } else {
+        Err(TupleError::TrailingElements)
+    }
+}
+
+/// A key made of a sequence of typed elements, encoded so that keys sort in the order of their
+/// elements and the key of a tuple is a prefix of the key of every tuple extending it. It borrows
+/// as its encoding.
+///
+/// Keys are built with `push`, `pack` or the `tuple!` macro, and decoded with `unpack`.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TupleKey {
+    bytes: Vec<u8>,
+}
+
+impl TupleKey {
+    /// Create a key with no elements.
+    #[inline]
+    pub fn new() -> TupleKey {
+        TupleKey { bytes: Vec::new() }
+    }
+
+    /// Create a key from a tuple of elements, or a single element.
+    #[inline]
+    pub fn pack<T: Encode>(elements: T) -> TupleKey {
+        let mut key = TupleKey::new();
+        key.push(elements);
+        key
+    }
+
+    /// Create a key from an encoding, checking that it is made of whole elements.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<TupleKey, TupleError> {
+        let mut i = 0;
+        while i < bytes.len() {
+            i += element_len(&bytes[i..])?;
+        }
+
+        Ok(TupleKey { bytes })
+    }
+
+    /// Append an element, or a tuple of elements, to the key.
+    #[inline]
+    pub fn push<E: Encode>(&mut self, element: E) -> &mut TupleKey {
+        element.encode(&mut self.bytes);
+        self
+    }
+
+    /// Decode the whole key as a tuple of elements.
+    #[inline]
+    pub fn unpack<T: Decode>(&self) -> Result<T, TupleError> {
+        unpack(&self.bytes)
+    }
+
+    /// Returns true if the key has no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The encoding of the key.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Unwrap the key into its encoding.
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl Borrow<[u8]> for TupleKey {
+    #[inline]
+    fn borrow(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl AsRef<[u8]> for TupleKey {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+// Add the element at the start of `bytes` to `tuple`, returning the length of its encoding.
+fn debug_element(tuple: &mut fmt::DebugTuple, bytes: &[u8]) -> Result<usize, TupleError> {
+    match bytes[0] {
+        BYTES => Vec::<u8>::decode(bytes).map(|(element, len)| {
+            tuple.field(&element);
+            len
+        }),
+        STRING => String::decode(bytes).map(|(element, len)| {
+            tuple.field(&element);
+            len
+        }),
+        FALSE | TRUE => bool::decode(bytes).map(|(element, len)| {
+            tuple.field(&element);
+            len
+        }),
+        _ => decode_int(bytes).map(|(element, len)| {
+            tuple.field(&element);
+            len
+        }),
+    }
+}
+
+// Keys are shown as tuples of their elements, with anything left undecodable shown as bytes.
+impl fmt::Debug for TupleKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut tuple = f.debug_tuple("TupleKey");
+        let mut rest = &self.bytes[..];
+
+        while !rest.is_empty() {
+            match debug_element(&mut tuple, rest) {
+                Ok(len) => rest = &rest[len..],
+                Err(_) => {
+                    tuple.field(&rest);
+                    break;
+                }
+            }
+        }
+
+        tuple.finish()
+    }
+}
+
+impl Break for TupleKey {
+    type Split = [u8];
+
+    #[inline]
+    fn empty<'a>() -> &'a [u8] {
+        <&'a [u8]>::default()
+    }
+
+    // Break after the last whole element within the first `loc` bytes.
+    fn find_break(&self, loc: usize) -> &[u8] {
+        let mut end = 0;
+
+        while let Ok(len) = element_len(&self.bytes[end..]) {
+            if end + len > loc {
+                break;
+            }
+            end += len;
+        }
+
+        &self.bytes[..end]
+    }
+}
+
+/// Build a `TupleKey` from a list of elements.
+///
+/// ```rust
+/// # #[macro_use] extern crate qp_trie;
+/// # fn main() {
+/// use qp_trie::tuple::TupleKey;
+///
+/// let key = tuple!(42u64, "orders", &b"\x00\x01"[..]);
+/// assert_eq!(key, TupleKey::pack((42u64, "orders", &b"\x00\x01"[..])));
+/// assert_eq!(
+///     key.unpack::<(u64, String, Vec<u8>)>(),
+///     Ok((42, "orders".to_owned(), vec![0, 1]))
+/// );
+/// # }
+/// ```
+#[macro_export]
+macro_rules! tuple {
+    ($($element:expr),* $(,)*) => {{
+        #[allow(unused_mut)]
+        let mut key = $crate::tuple::TupleKey::new();
+        $(key.push($element);)*
+        key
+    }};
+}
@@ -1,4 +1,5 @@
 use core::cmp;
+use core::convert::TryInto;
 
 // Get the "nybble index" corresponding to the `n`th nybble in the given slice.
 //
@@ -30,58 +31,166 @@ pub fn nybble_index(n: usize, slice: &[u8]) -> u8 {
     }
 }
 
+// Find the first byte at which the two provided slices differ, looking only at the bytes they
+// have in common. Keys tend to share long prefixes, so this compares sixteen bytes at a time with
+// SSE2 or NEON where available, then eight bytes at a time as words, and only then byte by byte.
+#[inline]
+fn byte_mismatch(left: &[u8], right: &[u8]) -> Option<usize> {
+    let len = cmp::min(left.len(), right.len());
+    let (left, right) = (&left[..len], &right[..len]);
+    let mut i = 0;
+
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+    {
+        use core::arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8};
+
+        while i + 16 <= len {
+            // unsafe: both slices have at least sixteen bytes past `i`, and the loads are
+            // unaligned.
+            let equal = unsafe {
+                let l = _mm_loadu_si128(left.as_ptr().add(i) as *const __m128i);
+                let r = _mm_loadu_si128(right.as_ptr().add(i) as *const __m128i);
+                _mm_movemask_epi8(_mm_cmpeq_epi8(l, r)) as u32
+            };
+
+            if equal != 0xFFFF {
+                return Some(i + (!equal).trailing_zeros() as usize);
+            }
+
+            i += 16;
+        }
+    }
+
+    #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+    {
+        use core::arch::aarch64::{
+            vceqq_u8, vget_lane_u64, vld1q_u8, vreinterpret_u64_u8, vreinterpretq_u16_u8,
+            vshrn_n_u16,
+        };
+
+        while i + 16 <= len {
+            // unsafe: both slices have at least sixteen bytes past `i`. Narrowing the comparison
+            // leaves four bits per byte, in order, as NEON has no byte movemask.
+            let equal = unsafe {
+                let l = vld1q_u8(left.as_ptr().add(i));
+                let r = vld1q_u8(right.as_ptr().add(i));
+                let eq = vreinterpretq_u16_u8(vceqq_u8(l, r));
+                vget_lane_u64::<0>(vreinterpret_u64_u8(vshrn_n_u16::<4>(eq)))
+            };
+
+            if equal != u64::MAX {
+                return Some(i + (!equal).trailing_zeros() as usize / 4);
+            }
+
+            i += 16;
+        }
+    }
+
+    while i + 8 <= len {
+        // Loading little-endian puts the first byte in the lowest bits, so the trailing zeros of
+        // the difference count the matching bytes.
+        let l = u64::from_le_bytes(left[i..i + 8].try_into().unwrap());
+        let r = u64::from_le_bytes(right[i..i + 8].try_into().unwrap());
+        let difference = l ^ r;
+
+        if difference != 0 {
+            return Some(i + difference.trailing_zeros() as usize / 8);
+        }
+
+        i += 8;
+    }
+
+    (i..len).find(|&i| left[i] != right[i])
+}
+
 // Find the nybble at which the two provided slices mismatch. If no such nybble exists and the
 // slices are the same length, `None` is returned; if no such nybble exists but the slices are
 // *not* the same length, then the point at which one slice has a byte and the other has ended is
 // considered the mismatch point.
 #[inline]
 pub fn nybble_mismatch(left: &[u8], right: &[u8]) -> Option<usize> {
-    let mut difference;
-
-    for (i, (l, r)) in left.iter().cloned().zip(right.iter().cloned()).enumerate() {
-        difference = l ^ r;
-
-        if difference != 0 {
-            if difference & 0x0F == 0 {
-                return Some(1 + i * 2);
+    match byte_mismatch(left, right) {
+        Some(i) => {
+            if (left[i] ^ right[i]) & 0x0F == 0 {
+                Some(1 + i * 2)
             } else {
-                return Some(i * 2);
+                Some(i * 2)
             }
         }
-    }
 
-    if left.len() == right.len() {
-        None
-    } else {
-        Some(cmp::min(left.len(), right.len()) * 2)
+        None if left.len() == right.len() => None,
+        None => Some(cmp::min(left.len(), right.len()) * 2),
     }
 }
 
+// As `nybble_mismatch`, but also return the nybble index of the mismatching nybble in `left`.
 #[inline]
 pub fn nybble_get_mismatch(left: &[u8], right: &[u8]) -> Option<(u8, usize)> {
-    let mut difference;
+    match byte_mismatch(left, right) {
+        Some(i) => {
+            let l = left[i];
 
-    for (i, (l, r)) in left.iter().cloned().zip(right.iter().cloned()).enumerate() {
-        difference = l ^ r;
-
-        if difference != 0 {
-            if difference & 0x0F == 0 {
-                return Some((1 + (l >> 4), 1 + i * 2));
+            if (l ^ right[i]) & 0x0F == 0 {
+                Some((1 + (l >> 4), 1 + i * 2))
             } else {
-                return Some((1 + (l & 0x0F), i * 2));
+                Some((1 + (l & 0x0F), i * 2))
             }
         }
+
+        None if left.len() == right.len() => None,
+        None => {
+            let idx = cmp::min(left.len(), right.len()) * 2;
+
+            Some((nybble_index(idx, left), idx))
+        }
     }
+}
 
-    if left.len() == right.len() {
-        None
-    } else {
-        let idx = cmp::min(left.len(), right.len()) * 2;
+// Hint to the processor that the memory behind `ptr` is about to be read. Only emitted when the
+// `prefetch` feature is enabled on a target with a stable prefetch intrinsic; otherwise a no-op.
+#[inline(always)]
+pub fn prefetch<T>(ptr: *const T) {
+    #[cfg(all(feature = "prefetch", target_arch = "x86_64"))]
+    unsafe {
+        use core::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+
+        _mm_prefetch::<_MM_HINT_T0>(ptr as *const i8);
+    }
 
-        Some((nybble_index(idx, left), idx))
+    #[cfg(not(all(feature = "prefetch", target_arch = "x86_64")))]
+    let _ = ptr;
+}
+
+// An item ranked by score for top-k selection. Higher scores rank higher, and among equal scores
+// whichever was seen first (had the lower `seq`) ranks higher.
+pub struct Ranked<S, T> {
+    pub score: S,
+    pub seq: usize,
+    pub item: T,
+}
+
+impl<S: Ord, T> Ord for Ranked<S, T> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.score
+            .cmp(&other.score)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl<S: Ord, T> PartialOrd for Ranked<S, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S: Ord, T> PartialEq for Ranked<S, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
     }
 }
 
+impl<S: Ord, T> Eq for Ranked<S, T> {}
+
 #[cfg(test)]
 mod test {
     use alloc::vec::Vec;
@@ -90,7 +199,72 @@ mod test {
 
     use super::*;
 
+    // The original byte-at-a-time implementations, kept as a reference for the word-at-a-time and
+    // vectorized ones.
+    fn nybble_mismatch_scalar(left: &[u8], right: &[u8]) -> Option<usize> {
+        for (i, (l, r)) in left.iter().cloned().zip(right.iter().cloned()).enumerate() {
+            let difference = l ^ r;
+
+            if difference != 0 {
+                if difference & 0x0F == 0 {
+                    return Some(1 + i * 2);
+                } else {
+                    return Some(i * 2);
+                }
+            }
+        }
+
+        if left.len() == right.len() {
+            None
+        } else {
+            Some(cmp::min(left.len(), right.len()) * 2)
+        }
+    }
+
+    fn nybble_get_mismatch_scalar(left: &[u8], right: &[u8]) -> Option<(u8, usize)> {
+        for (i, (l, r)) in left.iter().cloned().zip(right.iter().cloned()).enumerate() {
+            let difference = l ^ r;
+
+            if difference != 0 {
+                if difference & 0x0F == 0 {
+                    return Some((1 + (l >> 4), 1 + i * 2));
+                } else {
+                    return Some((1 + (l & 0x0F), i * 2));
+                }
+            }
+        }
+
+        if left.len() == right.len() {
+            None
+        } else {
+            let idx = cmp::min(left.len(), right.len()) * 2;
+
+            Some((nybble_index(idx, left), idx))
+        }
+    }
+
     quickcheck! {
+        // Random keys rarely share long prefixes, so build `right` from `left` with a single
+        // byte flipped and a possibly different length, which exercises every chunk width.
+        fn mismatch_matches_scalar(left: Vec<u8>, at: usize, flip: u8, len: usize) -> bool {
+            let mut right = left.clone();
+            if !right.is_empty() {
+                let at = at % right.len();
+                right[at] ^= flip;
+            }
+            right.resize(len % (left.len() + 8), 0x5A);
+
+            nybble_mismatch(&left, &right) == nybble_mismatch_scalar(&left, &right)
+                && nybble_mismatch(&right, &left) == nybble_mismatch_scalar(&right, &left)
+                && nybble_get_mismatch(&left, &right) == nybble_get_mismatch_scalar(&left, &right)
+                && nybble_get_mismatch(&right, &left) == nybble_get_mismatch_scalar(&right, &left)
+        }
+
+        fn mismatch_arbitrary_matches_scalar(left: Vec<u8>, right: Vec<u8>) -> bool {
+            nybble_mismatch(&left, &right) == nybble_mismatch_scalar(&left, &right)
+                && nybble_get_mismatch(&left, &right) == nybble_get_mismatch_scalar(&left, &right)
+        }
+
         fn nybble(nybs: Vec<u8>) -> TestResult {
             for &nyb in &nybs {
                 if nyb > 15 {
@@ -1,11 +1,14 @@
 use alloc::borrow::ToOwned;
 use alloc::string::String;
 use core::borrow::Borrow;
+use core::cmp::Ordering;
 use core::fmt;
 use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
 use core::ops::Deref;
 
-use trie::Break;
+use iter::{Iter, IterMut};
+use trie::{Break, Trie};
 
 /// A wrapper for `String` which implements `Borrow<[u8]>` and hashes in the same way as a byte
 /// slice.
@@ -156,3 +159,543 @@ impl AsRef<BStr> for str {
         <&BStr>::from(self)
     }
 }
+
+/// A normal form for strings used as keys, such as a case folding.
+pub trait Normalization {
+    /// Append the normal form of `s` to `out`.
+    fn normalize(s: &str, out: &mut String);
+
+    /// Given a string and its normal form, find the length of the longest prefix of the string
+    /// whose normal form is a prefix of the first `loc` bytes of the normal form. This is used to
+    /// map a common prefix found between normal forms back onto the original spelling.
+    ///
+    /// The default implementation normalizes every prefix of the string in turn; normalizations
+    /// which map characters independently of each other should override it.
+    fn original_len(original: &str, normalized: &str, loc: usize) -> usize {
+        let mut buf = String::new();
+        let boundaries = original.char_indices().map(|(i, _)| i).skip(1);
+
+        boundaries
+            .chain(Some(original.len()))
+            .filter(|&end| {
+                buf.clear();
+                Self::normalize(&original[..end], &mut buf);
+                buf.len() <= loc && normalized.as_bytes().starts_with(buf.as_bytes())
+            })
+            .last()
+            .unwrap_or(0)
+    }
+}
+
+// Map a prefix of the normal form back onto the original for normalizations which map characters
+// one at a time.
+fn original_len_by_char<F>(original: &str, loc: usize, mut normalized_len: F) -> usize
+where
+    F: FnMut(char) -> usize,
+{
+    let mut len = 0;
+
+    for (i, c) in original.char_indices() {
+        len += normalized_len(c);
+
+        if len > loc {
+            return i;
+        }
+    }
+
+    original.len()
+}
+
+/// Fold ASCII letters to lower case, leaving all other characters alone. This is the case
+/// insensitivity of HTTP header names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AsciiCaseFold {}
+
+impl Normalization for AsciiCaseFold {
+    fn normalize(s: &str, out: &mut String) {
+        out.extend(s.chars().map(|c| c.to_ascii_lowercase()));
+    }
+
+    fn original_len(original: &str, _: &str, loc: usize) -> usize {
+        original_len_by_char(original, loc, char::len_utf8)
+    }
+}
+
+/// Fold strings to lower case character by character, using the Unicode lower case mapping of
+/// each character. This agrees with full Unicode case folding for most scripts, although a few
+/// characters, such as `ß`, fold differently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CaseFold {}
+
+impl Normalization for CaseFold {
+    fn normalize(s: &str, out: &mut String) {
+        out.extend(s.chars().flat_map(char::to_lowercase));
+    }
+
+    fn original_len(original: &str, _: &str, loc: usize) -> usize {
+        original_len_by_char(original, loc, |c| {
+            c.to_lowercase().map(char::len_utf8).sum()
+        })
+    }
+}
+
+/// Normalize strings to Unicode Normalization Form C, so that canonically equivalent spellings,
+/// such as a precomposed `é` and an `e` followed by a combining acute accent, are the same key.
+#[cfg(feature = "unicode-normalization")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Nfc {}
+
+#[cfg(feature = "unicode-normalization")]
+impl Normalization for Nfc {
+    fn normalize(s: &str, out: &mut String) {
+        use unicode_normalization::UnicodeNormalization;
+
+        out.extend(s.nfc());
+    }
+
+    // NFC only reorders and composes characters within a run which begins with a starter, so the
+    // string is normalized a run at a time, counting how much of the normal form the runs before
+    // the current one account for. Only the run which straddles `loc` is searched character by
+    // character, as in the default implementation.
+    fn original_len(original: &str, normalized: &str, loc: usize) -> usize {
+        let (mut start, mut done) = (0, 0);
+        let (mut run, mut next, mut alone) = (String::new(), String::new(), String::new());
+
+        for (i, c) in original.char_indices() {
+            let end = i + c.len_utf8();
+            next.clear();
+            Self::normalize(&original[start..end], &mut next);
+
+            if i > start && begins_nfc_run(c) {
+                alone.clear();
+                Self::normalize(&original[i..end], &mut alone);
+
+                // The character starts a new run unless it composed with the run before it.
+                if next.len() == run.len() + alone.len() && next.starts_with(run.as_str()) {
+                    if done + run.len() > loc {
+                        return nfc_run_len(
+                            original,
+                            &normalized.as_bytes()[done..],
+                            loc - done,
+                            start,
+                            i,
+                        );
+                    }
+
+                    done += run.len();
+                    start = i;
+                    core::mem::swap(&mut next, &mut alone);
+                }
+            }
+
+            core::mem::swap(&mut run, &mut next);
+        }
+
+        if done + run.len() > loc {
+            nfc_run_len(
+                original,
+                &normalized.as_bytes()[done..],
+                loc - done,
+                start,
+                original.len(),
+            )
+        } else {
+            original.len()
+        }
+    }
+}
+
+// Whether a character can begin a run of NFC: its canonical decomposition begins with a starter,
+// so that no later character can be reordered or composed past it.
+#[cfg(feature = "unicode-normalization")]
+fn begins_nfc_run(c: char) -> bool {
+    use unicode_normalization::char::{canonical_combining_class, decompose_canonical};
+
+    let mut first = None;
+    decompose_canonical(c, |d| {
+        first.get_or_insert(d);
+    });
+    first.map_or(true, |d| canonical_combining_class(d) == 0)
+}
+
+// Within the run `original[start..end]`, whose normal form begins `normalized` but is longer than
+// `loc`, find the end of the longest prefix whose normal form is a prefix of `normalized[..loc]`.
+#[cfg(feature = "unicode-normalization")]
+fn nfc_run_len(original: &str, normalized: &[u8], loc: usize, start: usize, end: usize) -> usize {
+    let mut buf = String::new();
+
+    original[start..end]
+        .char_indices()
+        .map(|(i, _)| start + i)
+        .skip(1)
+        .filter(|&end| {
+            buf.clear();
+            Nfc::normalize(&original[start..end], &mut buf);
+            buf.len() <= loc && normalized.starts_with(buf.as_bytes())
+        })
+        .last()
+        .unwrap_or(start)
+}
+
+/// A string key which is stored and compared in a normal form given by `N`, but which keeps its
+/// original spelling for display. It implements `Borrow<[u8]>` as the bytes of its normal form,
+/// and `Break` by cutting the original string, so that `Trie::longest_common_prefix` returns an
+/// `OriginalStr` prefix of an original key.
+///
+/// The `*_str` methods of a trie with these keys, provided by `NormalizedStrTrie`, normalize their
+/// arguments, so queries need not be in normal form.
+///
+/// # Example
+///
+/// ```rust
+/// # use qp_trie::Trie;
+/// use qp_trie::wrapper::{CaseFoldStr, NormalizedStrTrie};
+///
+/// let mut headers = Trie::new();
+/// headers.insert(CaseFoldStr::from("Content-Type"), "text/html");
+/// headers.insert(CaseFoldStr::from("Content-Length"), "42");
+///
+/// assert_eq!(headers.get_str("content-type"), Some(&"text/html"));
+/// assert_eq!(headers.longest_common_prefix_str("CONTENT-ENCODING"), "Content-");
+///
+/// let names = headers.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+/// assert!(names.contains(&"Content-Length"));
+/// ```
+pub struct NormalizedString<N> {
+    original: String,
+    normalized: String,
+    normalization: PhantomData<N>,
+}
+
+/// A string key compared without regard to ASCII case.
+pub type AsciiCaseFoldStr = NormalizedString<AsciiCaseFold>;
+
+/// A string key compared without regard to case.
+pub type CaseFoldStr = NormalizedString<CaseFold>;
+
+/// A string key compared in Unicode Normalization Form C.
+#[cfg(feature = "unicode-normalization")]
+pub type NfcStr = NormalizedString<Nfc>;
+
+/// A prefix of the original spelling of a `NormalizedString`, as returned by
+/// `Trie::longest_common_prefix`.
+///
+/// It is deliberately not comparable or hashable as a key: the trie orders `NormalizedString`s by
+/// their normal form, so looking them up by their original spelling would not be meaningful.
+#[repr(transparent)]
+pub struct OriginalStr(str);
+
+impl OriginalStr {
+    #[inline]
+    fn new(s: &str) -> &OriginalStr {
+        unsafe { &*(s as *const str as *const OriginalStr) }
+    }
+
+    /// The original spelling as a string slice.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for OriginalStr {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for OriginalStr {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for OriginalStr {
+    #[inline]
+    fn eq(&self, other: &str) -> bool {
+        &self.0 == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for OriginalStr {
+    #[inline]
+    fn eq(&self, other: &&'a str) -> bool {
+        &self.0 == *other
+    }
+}
+
+impl fmt::Debug for OriginalStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl fmt::Display for OriginalStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<N: Normalization> NormalizedString<N> {
+    /// Normalize a string, keeping its original spelling.
+    pub fn new(original: String) -> NormalizedString<N> {
+        let normalized = normalize::<N>(&original);
+
+        NormalizedString {
+            original,
+            normalized,
+            normalization: PhantomData,
+        }
+    }
+}
+
+impl<N> NormalizedString<N> {
+    /// The original spelling of the string.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.original
+    }
+
+    /// The normal form of the string, as stored in the trie.
+    #[inline]
+    pub fn normalized(&self) -> &str {
+        &self.normalized
+    }
+
+    /// Unwrap the original spelling of the string.
+    #[inline]
+    pub fn into_string(self) -> String {
+        self.original
+    }
+}
+
+#[inline]
+fn normalize<N: Normalization>(s: &str) -> String {
+    let mut normalized = String::with_capacity(s.len());
+    N::normalize(s, &mut normalized);
+    normalized
+}
+
+impl<N: Normalization> From<String> for NormalizedString<N> {
+    #[inline]
+    fn from(s: String) -> NormalizedString<N> {
+        NormalizedString::new(s)
+    }
+}
+
+impl<'a, N: Normalization> From<&'a str> for NormalizedString<N> {
+    #[inline]
+    fn from(s: &'a str) -> NormalizedString<N> {
+        NormalizedString::new(s.into())
+    }
+}
+
+impl<N> Clone for NormalizedString<N> {
+    fn clone(&self) -> Self {
+        NormalizedString {
+            original: self.original.clone(),
+            normalized: self.normalized.clone(),
+            normalization: PhantomData,
+        }
+    }
+}
+
+impl<N> fmt::Debug for NormalizedString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.original.fmt(f)
+    }
+}
+
+impl<N> fmt::Display for NormalizedString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.original.fmt(f)
+    }
+}
+
+impl<N> Borrow<[u8]> for NormalizedString<N> {
+    #[inline]
+    fn borrow(&self) -> &[u8] {
+        self.normalized.as_bytes()
+    }
+}
+
+impl<N> Borrow<OriginalStr> for NormalizedString<N> {
+    #[inline]
+    fn borrow(&self) -> &OriginalStr {
+        OriginalStr::new(&self.original)
+    }
+}
+
+// Comparisons and hashing go by the normal form, agreeing with the `Borrow<[u8]>` impl.
+impl<N> PartialEq for NormalizedString<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.normalized == other.normalized
+    }
+}
+
+impl<N> Eq for NormalizedString<N> {}
+
+impl<N> PartialOrd for NormalizedString<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<N> Ord for NormalizedString<N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.normalized.cmp(&other.normalized)
+    }
+}
+
+impl<N> Hash for NormalizedString<N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.normalized.as_bytes().hash(state);
+    }
+}
+
+impl<N: Normalization> Break for NormalizedString<N> {
+    type Split = OriginalStr;
+
+    #[inline]
+    fn empty<'a>() -> &'a OriginalStr {
+        OriginalStr::new("")
+    }
+
+    #[inline]
+    fn find_break(&self, loc: usize) -> &OriginalStr {
+        OriginalStr::new(&self.original[..N::original_len(&self.original, &self.normalized, loc)])
+    }
+}
+
+/// The string convenience methods of a trie keyed by `NormalizedString`s, which normalize their
+/// arguments first so that queries need not be in normal form.
+///
+/// These are the counterparts of the `*_str` methods of `Trie<BString, V>`. They live in a trait
+/// so that those methods remain unambiguous when a trie's key type is left to inference.
+pub trait NormalizedStrTrie<N: Normalization, V> {
+    /// Convenience function for iterating over suffixes with a string.
+    fn iter_prefix_str<'a, Q>(&'a self, prefix: &Q) -> Iter<'a, NormalizedString<N>, V>
+    where
+        Q: ?Sized + Borrow<str>;
+
+    /// Convenience function for iterating mutably over suffixes with a string.
+    fn iter_prefix_mut_str<'a, Q>(&'a mut self, prefix: &Q) -> IterMut<'a, NormalizedString<N>, V>
+    where
+        Q: ?Sized + Borrow<str>;
+
+    /// Returns true if there is an entry for the given string key.
+    fn contains_key_str<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Borrow<str>;
+
+    /// Convenience function for getting with a string.
+    fn get_str<'a, Q>(&'a self, key: &Q) -> Option<&'a V>
+    where
+        Q: ?Sized + Borrow<str>;
+
+    /// Convenience function for getting mutably with a string.
+    fn get_mut_str<'a, Q>(&'a mut self, key: &Q) -> Option<&'a mut V>
+    where
+        Q: ?Sized + Borrow<str>;
+
+    /// Convenience function for inserting with a string. If an entry already exists under the
+    /// same normal form, its value is replaced.
+    fn insert_str<Q>(&mut self, key: &Q, val: V) -> Option<V>
+    where
+        Q: ?Sized + Borrow<str>;
+
+    /// Convenience function for removing with a string.
+    fn remove_str<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: ?Sized + Borrow<str>;
+
+    /// Convenience function for removing a prefix with a string.
+    fn remove_prefix_str<Q>(&mut self, prefix: &Q) -> Trie<NormalizedString<N>, V>
+    where
+        Q: ?Sized + Borrow<str>;
+
+    /// Get the longest common prefix of all the keys in the trie and the given string, in the
+    /// original spelling of one of the keys.
+    fn longest_common_prefix_str<'a, Q>(&'a self, key: &Q) -> &'a str
+    where
+        Q: ?Sized + Borrow<str>;
+}
+
+impl<N: Normalization, V> NormalizedStrTrie<N, V> for Trie<NormalizedString<N>, V> {
+    #[inline]
+    fn iter_prefix_str<'a, Q>(&'a self, prefix: &Q) -> Iter<'a, NormalizedString<N>, V>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.iter_prefix(normalize::<N>(prefix.borrow()).as_bytes())
+    }
+
+    #[inline]
+    fn iter_prefix_mut_str<'a, Q>(&'a mut self, prefix: &Q) -> IterMut<'a, NormalizedString<N>, V>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.iter_prefix_mut(normalize::<N>(prefix.borrow()).as_bytes())
+    }
+
+    #[inline]
+    fn contains_key_str<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.contains_key(normalize::<N>(key.borrow()).as_bytes())
+    }
+
+    #[inline]
+    fn get_str<'a, Q>(&'a self, key: &Q) -> Option<&'a V>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.get(normalize::<N>(key.borrow()).as_bytes())
+    }
+
+    #[inline]
+    fn get_mut_str<'a, Q>(&'a mut self, key: &Q) -> Option<&'a mut V>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.get_mut(normalize::<N>(key.borrow()).as_bytes())
+    }
+
+    #[inline]
+    fn insert_str<Q>(&mut self, key: &Q, val: V) -> Option<V>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.insert(key.borrow().into(), val)
+    }
+
+    #[inline]
+    fn remove_str<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.remove(normalize::<N>(key.borrow()).as_bytes())
+    }
+
+    #[inline]
+    fn remove_prefix_str<Q>(&mut self, prefix: &Q) -> Trie<NormalizedString<N>, V>
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.remove_prefix(normalize::<N>(prefix.borrow()).as_bytes())
+    }
+
+    #[inline]
+    fn longest_common_prefix_str<'a, Q>(&'a self, key: &Q) -> &'a str
+    where
+        Q: ?Sized + Borrow<str>,
+    {
+        self.longest_common_prefix(normalize::<N>(key.borrow()).as_bytes())
+            .as_str()
+    }
+}
@@ -0,0 +1,119 @@
+use alloc::collections::BinaryHeap;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::cmp::Reverse;
+
+use node::Node;
+use trie::Trie;
+use util::Ranked;
+
+// A lower bound on the XOR distance between `target` and any key beneath a node whose keys all
+// share their first `known` nybbles with `key`. Distances compare as big-endian integers, so
+// within a byte the high nybble matters most - but the trie decides the low nybble first, so a
+// byte whose high nybble is still undecided only bounds the rest of the distance from below by
+// zero.
+fn lower_bound(key: &[u8], known: usize, target: &[u8]) -> Vec<u8> {
+    let mut bound = Vec::with_capacity(target.len());
+
+    for (i, (&k, &t)) in key.iter().zip(target).enumerate() {
+        if 2 * i + 1 < known {
+            bound.push(k ^ t);
+        } else {
+            if 2 * i < known {
+                bound.push((k ^ t) & 0x0F);
+            }
+            break;
+        }
+    }
+
+    bound.resize(target.len(), 0);
+    bound
+}
+
+// A node awaiting a visit, ranked closest first by the lower bound on its distance.
+type Candidate<'a, K, V> = Ranked<Reverse<Vec<u8>>, &'a Node<K, V>>;
+
+/// An iterator over the entries of a trie in increasing XOR distance of their keys from a target,
+/// produced by `Trie::iter_by_xor_distance`. Only keys of the same length as the target are
+/// produced.
+pub struct XorIter<'a, K: 'a, V: 'a> {
+    target: Vec<u8>,
+    frontier: BinaryHeap<Candidate<'a, K, V>>,
+    seq: usize,
+}
+
+impl<'a, K: Borrow<[u8]>, V> XorIter<'a, K, V> {
+    pub(crate) fn new(root: Option<&'a Node<K, V>>, target: &[u8]) -> XorIter<'a, K, V> {
+        let mut iter = XorIter {
+            target: target.to_vec(),
+            frontier: BinaryHeap::new(),
+            seq: 0,
+        };
+
+        if let Some(root) = root {
+            iter.push(root);
+        }
+
+        iter
+    }
+
+    fn push(&mut self, node: &'a Node<K, V>) {
+        let (key, known) = match *node {
+            Node::Leaf(ref leaf) => (leaf.key_slice(), usize::MAX),
+            Node::Branch(ref branch) => (node.get_exemplar(&[]).key_slice(), branch.choice()),
+        };
+
+        self.seq += 1;
+        self.frontier.push(Ranked {
+            score: Reverse(lower_bound(key, known, &self.target)),
+            seq: self.seq,
+            item: node,
+        });
+    }
+}
+
+impl<'a, K: Borrow<[u8]>, V> Iterator for XorIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // A leaf popped off the frontier is at least as close as every lower bound left on it, so
+        // it is the next closest key.
+        while let Some(ranked) = self.frontier.pop() {
+            match *ranked.item {
+                Node::Leaf(ref leaf) if leaf.key_slice().len() == self.target.len() => {
+                    return Some((&leaf.key, &leaf.val))
+                }
+                Node::Leaf(..) => {}
+                Node::Branch(ref branch) => {
+                    for child in branch.iter() {
+                        self.push(child);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl<K: Borrow<[u8]>, V> Trie<K, V> {
+    /// Iterate over the entries of the trie in increasing XOR distance of their keys from the
+    /// target, as in Kademlia, with distances compared as big-endian integers. Only keys of the
+    /// same length as the target are considered. The search is best-first, so taking the first
+    /// few entries only visits the parts of the trie which could hold them.
+    pub fn iter_by_xor_distance<Q>(&self, target: &Q) -> XorIter<'_, K, V>
+    where
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        XorIter::new(self.root(), target.borrow())
+    }
+
+    /// Find the `k` keys of the same length as the target with the smallest XOR distance to it,
+    /// closest first.
+    pub fn nearest_by_xor<Q>(&self, target: &Q, k: usize) -> Vec<(&K, &V)>
+    where
+        Q: ?Sized + Borrow<[u8]>,
+    {
+        self.iter_by_xor_distance(target).take(k).collect()
+    }
+}
@@ -4,6 +4,15 @@ extern crate quickcheck;
 
 #[cfg(feature = "serde")]
 extern crate bincode;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "regex-automata")]
+extern crate regex_automata;
+#[cfg(feature = "digest")]
+extern crate sha2;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 #[cfg(feature = "serde")]
 extern crate serde_json;
 
@@ -15,6 +24,51 @@ use std::collections::HashMap;
 
 use qp_trie::*;
 
+#[cfg(feature = "serde")]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Pairs {
+    #[serde(with = "qp_trie::serialization::pairs")]
+    trie: Trie<Vec<u8>, usize>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Structural {
+    #[serde(with = "qp_trie::serialization::structural")]
+    trie: Trie<Vec<u8>, usize>,
+}
+
+// Measures of `u16` values, for testing the summaries cached by a trie.
+struct Total;
+
+impl<K> Measure<K, u16> for Total {
+    type Summary = Sum<u64>;
+
+    fn measure(_: &K, &val: &u16) -> Sum<u64> {
+        Sum(val as u64)
+    }
+}
+
+struct Lowest;
+
+impl<K> Measure<K, u16> for Lowest {
+    type Summary = Min<u16>;
+
+    fn measure(_: &K, &val: &u16) -> Min<u16> {
+        Min::from(val)
+    }
+}
+
+struct Highest;
+
+impl<K> Measure<K, u16> for Highest {
+    type Summary = Max<u16>;
+
+    fn measure(_: &K, &val: &u16) -> Max<u16> {
+        Max::from(val)
+    }
+}
+
 quickcheck! {
     fn insert_and_get(elts: Vec<(u8, u64)>) -> bool {
         let mut elts = elts;
@@ -113,6 +167,95 @@ quickcheck! {
         filtered == prefixed
     }
 
+    fn subtrie_read_api(prefix: Vec<u8>, elts: Vec<(Vec<u8>, u64)>) -> bool {
+        let mut trie = Trie::new();
+
+        for &(ref k, v) in elts.iter() {
+            trie.insert(k.clone(), v);
+        }
+
+        let expected: Vec<(&Vec<u8>, &u64)> = trie
+            .iter()
+            .filter(|&(k, _)| k.starts_with(&prefix[..]))
+            .collect();
+        let lookup: HashMap<&Vec<u8>, &u64> = expected.iter().cloned().collect();
+        let subtrie = trie.subtrie(&prefix[..]);
+
+        subtrie.prefix() == &prefix[..]
+            && subtrie.len() == expected.len()
+            && subtrie.is_empty() == expected.is_empty()
+            && subtrie.first() == expected.first().cloned()
+            && subtrie.last() == expected.last().cloned()
+            && subtrie.keys().eq(expected.iter().map(|e| e.0))
+            && subtrie.values().eq(expected.iter().map(|e| e.1))
+            && elts.iter().all(|(k, _)| {
+                subtrie.contains_key(&k[..]) == lookup.contains_key(k)
+                    && subtrie.get_key_value(&k[..]) == lookup.get_key_value(k).map(|(&k, &v)| (k, v))
+            })
+            && subtrie.to_owned_trie().iter().eq(expected.iter().cloned())
+    }
+
+    fn subtrie_mut_retain(prefix: Vec<u8>, elts: Vec<(Vec<u8>, u64)>, modulus: u64) -> bool {
+        let modulus = modulus % 4 + 1;
+        let mut hashmap = HashMap::new();
+        let mut trie = Trie::new();
+
+        for &(ref k, v) in elts.iter() {
+            hashmap.insert(&k[..], v);
+            trie.insert(&k[..], v);
+        }
+
+        hashmap.retain(|k, v| !k.starts_with(&prefix[..]) || *v % modulus == 0);
+        {
+            let mut subtrie = trie.subtrie_mut(&prefix[..]);
+            subtrie.retain(|k, v| {
+                assert!(k.starts_with(&prefix[..]));
+                *v % modulus == 0
+            });
+            if subtrie.iter().any(|(_, v)| v % modulus != 0) {
+                return false;
+            }
+        }
+
+        trie.count() == hashmap.len()
+            && hashmap.iter().all(|(k, v)| trie.get(k) == Some(v))
+            && trie.iter().all(|(k, v)| hashmap.get(k) == Some(v))
+    }
+
+    fn subtrie_mut_constrained(prefix: Vec<u8>, elts: Vec<(Vec<u8>, Option<u64>)>) -> bool {
+        let mut hashmap = HashMap::new();
+        let mut trie = Trie::new();
+
+        for &(ref k, v_opt) in &elts {
+            let mut subtrie = trie.subtrie_mut(&prefix[..]);
+            let inside = k.starts_with(&prefix[..]);
+
+            match v_opt {
+                Some(v) if inside => {
+                    *subtrie.entry(&k[..]).or_insert(0) += v;
+                    let total = hashmap.entry(&k[..]).or_insert(0);
+                    *total += v;
+                    if subtrie.get(&k[..]) != Some(total) {
+                        return false;
+                    }
+                }
+                Some(_) => {
+                    if subtrie.get_mut(&k[..]).is_some() {
+                        return false;
+                    }
+                }
+                None => {
+                    let expected = if inside { hashmap.remove(&k[..]) } else { None };
+                    if subtrie.remove(&k[..]) != expected {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        trie.count() == hashmap.len() && trie.subtrie_mut(&prefix[..]).iter_mut().count() == hashmap.len()
+    }
+
     fn entry_insert_and_remove(elts: Vec<(Vec<u8>, Option<u64>)>) -> bool {
         let mut hashmap = HashMap::new();
         let mut trie = Trie::new();
@@ -143,6 +286,605 @@ quickcheck! {
         hashmap == collected
     }
 
+    fn entry_and_modify_or_default(elts: Vec<(Vec<u8>, u64)>) -> bool {
+        let mut hashmap = HashMap::new();
+        let mut trie = Trie::new();
+
+        for &(ref k, v) in &elts {
+            hashmap.entry(k.as_ref()).and_modify(|e| *e += v).or_insert(v);
+            trie.entry(k.as_ref()).and_modify(|e| *e += v).or_insert(v);
+            *trie.entry(k.as_ref()).or_default() += 0;
+            trie.entry(k.as_ref()).or_insert_with_key(|_| unreachable!());
+        }
+
+        let collected: HashMap<&[u8], u64> = trie.into_iter().collect();
+
+        hashmap == collected
+    }
+
+    fn entry_ref_counting(elts: Vec<Vec<u8>>) -> bool {
+        let mut hashmap = HashMap::new();
+        let mut trie = Trie::<Vec<u8>, usize>::new();
+
+        for k in &elts {
+            *hashmap.entry(&k[..]).or_insert(0) += 1;
+            *trie.entry_ref(&k[..]).or_default() += 1;
+        }
+
+        let collected: HashMap<&[u8], usize> = trie.iter().map(|(k, &v)| (&k[..], v)).collect();
+
+        trie.count() == hashmap.len() && hashmap == collected
+    }
+
+    fn prefix_entry_model(prefix: Vec<u8>, elts: Vec<(Vec<u8>, u64)>, batch: Vec<(Vec<u8>, u64)>) -> bool {
+        let mut trie = Trie::new();
+        let mut hashmap = HashMap::new();
+
+        for (k, v) in elts {
+            trie.insert(k.clone(), v);
+            hashmap.insert(k, v);
+        }
+
+        let batch = batch
+            .into_iter()
+            .map(|(mut k, v)| {
+                let mut key = prefix.clone();
+                key.append(&mut k);
+                (key, v)
+            })
+            .collect::<Vec<_>>();
+
+        let under_prefix = match trie.prefix_entry(&prefix[..]) {
+            PrefixEntry::Occupied(mut occupied) => {
+                occupied.extend(batch.iter().cloned());
+                occupied.iter().map(|(k, &v)| (k.clone(), v)).collect::<HashMap<_, _>>()
+            }
+            PrefixEntry::Vacant(vacant) => match vacant.insert_all(batch.iter().cloned()) {
+                PrefixEntry::Occupied(occupied) => {
+                    occupied.iter().map(|(k, &v)| (k.clone(), v)).collect::<HashMap<_, _>>()
+                }
+                PrefixEntry::Vacant(..) => HashMap::new(),
+            },
+        };
+        hashmap.extend(batch);
+
+        let expected = hashmap
+            .iter()
+            .filter(|&(k, _)| k.starts_with(&prefix))
+            .map(|(k, &v)| (k.clone(), v))
+            .collect::<HashMap<_, _>>();
+
+        if trie.count() != hashmap.len() || under_prefix != expected {
+            return false;
+        }
+
+        let removed = match trie.prefix_entry(&prefix[..]) {
+            PrefixEntry::Occupied(occupied) => occupied.remove(),
+            PrefixEntry::Vacant(..) => Trie::new(),
+        };
+        hashmap.retain(|k, _| !k.starts_with(&prefix));
+
+        let remaining = trie.iter().map(|(k, &v)| (k.clone(), v)).collect::<HashMap<_, _>>();
+        let removed = removed.into_iter().collect::<HashMap<_, _>>();
+
+        removed == expected
+            && trie.count() == hashmap.len()
+            && remaining == hashmap
+            && trie.iter_prefix(&prefix[..]).next().is_none()
+    }
+
+    fn get_key_value_and_remove_entry(elts: Vec<(Vec<u8>, u64)>, removals: Vec<Vec<u8>>) -> bool {
+        let mut trie = Trie::new();
+        let mut hashmap = HashMap::new();
+
+        for (k, v) in elts {
+            trie.insert(k.clone(), v);
+            hashmap.insert(k, v);
+        }
+
+        if !hashmap.iter().all(|(k, v)| trie.get_key_value(k) == Some((k, v))) {
+            return false;
+        }
+
+        for k in removals {
+            if trie.remove_entry(&k) != hashmap.remove_entry(&k) {
+                return false;
+            }
+        }
+
+        trie.count() == hashmap.len()
+            && hashmap.iter().all(|(k, v)| trie.get_key_value(k) == Some((k, v)))
+    }
+
+    #[cfg(feature = "digest")]
+    fn merkle_diff_covers_differences(
+        shared: Vec<(Vec<u8>, u8)>,
+        ours_only: Vec<(Vec<u8>, u8)>,
+        theirs_only: Vec<(Vec<u8>, u8)>
+    ) -> bool {
+        use sha2::Sha256;
+
+        let mut ours = Trie::<_, _, Merkle<Sha256>>::with_measure();
+        ours.extend(shared);
+        let mut theirs = ours.clone();
+        ours.extend(ours_only);
+        theirs.extend(theirs_only);
+
+        let rebuilt = ours
+            .iter()
+            .map(|(k, &v)| (k.clone(), v))
+            .collect::<Vec<_>>();
+        let mut fresh = Trie::<_, _, Merkle<Sha256>>::with_measure();
+        fresh.extend(rebuilt);
+
+        let ranges = ours
+            .diff_by_hash(|range| theirs.merkle_digest(range))
+            .collect::<Vec<_>>();
+
+        let differs = |k: &Vec<u8>| ours.get(k) != theirs.get(k);
+        let covered = |k: &Vec<u8>| ranges.iter().filter(|r| r.contains(k)).count();
+
+        ours.merkle_root() == fresh.merkle_root()
+            && (ours.merkle_root() == theirs.merkle_root()) == (ours == theirs)
+            && ours.keys().chain(theirs.keys()).all(|k| covered(k) <= 1)
+            && ours.keys().chain(theirs.keys()).filter(|k| differs(k)).all(|k| covered(k) == 1)
+    }
+
+    fn diff_matches_model(ours: Vec<(Vec<u8>, u8)>, theirs: Vec<(Vec<u8>, u8)>) -> bool {
+        let ours_map = ours.iter().cloned().collect::<HashMap<_, _>>();
+        let theirs_map = theirs.iter().cloned().collect::<HashMap<_, _>>();
+        let ours = ours.into_iter().collect::<Trie<_, _>>();
+        let theirs = theirs.into_iter().collect::<Trie<_, _>>();
+
+        let mut expected = Vec::new();
+        for (k, v) in ours_map.iter() {
+            match theirs_map.get(k) {
+                None => expected.push(DiffItem::Removed(k, v)),
+                Some(w) if w != v => expected.push(DiffItem::Changed(k, v, w)),
+                Some(_) => {}
+            }
+        }
+        for (k, v) in theirs_map.iter() {
+            if !ours_map.contains_key(k) {
+                expected.push(DiffItem::Added(k, v));
+            }
+        }
+
+        let mut diff = ours.diff(&theirs).collect::<Vec<_>>();
+        let key = |item: &DiffItem<&Vec<u8>, &u8>| match *item {
+            DiffItem::Added(k, _) | DiffItem::Removed(k, _) | DiffItem::Changed(k, _, _) => k.clone(),
+        };
+        diff.sort_by_key(key);
+        expected.sort_by_key(key);
+
+        let mut patched = ours.clone();
+        patched.apply_diff(ours.diff(&theirs).map(DiffItem::cloned));
+
+        diff == expected && patched == theirs
+    }
+
+    fn annotated_aggregates_match_model(
+        inserts: Vec<(Vec<u8>, u16)>,
+        removes: Vec<Vec<u8>>,
+        prefixes: Vec<Vec<u8>>
+    ) -> bool {
+        let mut trie = Trie::<_, _, Total>::with_measure();
+        let mut map = HashMap::new();
+
+        for (k, v) in inserts {
+            if trie.insert(k.clone(), v) != map.insert(k, v) {
+                return false;
+            }
+        }
+        for k in removes {
+            if trie.remove(&k) != map.remove(&k) {
+                return false;
+            }
+        }
+
+        let sum = |map: &HashMap<Vec<u8>, u16>, p: &[u8]| {
+            Sum(map.iter().filter(|(k, _)| k.starts_with(p)).map(|(_, &v)| v as u64).sum::<u64>())
+        };
+
+        for p in prefixes.iter() {
+            if trie.aggregate_prefix(p) != sum(&map, p) {
+                return false;
+            }
+        }
+
+        if let Some(p) = prefixes.first() {
+            let removed = trie.remove_prefix(p);
+            let expected = sum(&map, p);
+            map.retain(|k, _| !k.starts_with(p));
+
+            if removed.aggregate() != expected
+                || trie.aggregate() != sum(&map, &[])
+                || prefixes.iter().any(|q| trie.aggregate_prefix(q) != sum(&map, q))
+            {
+                return false;
+            }
+        }
+
+        trie.count() == map.len() && trie.iter().all(|(k, v)| map.get(k) == Some(v))
+    }
+
+    fn annotated_aggregates_survive_mutable_borrows(
+        inserts: Vec<(Vec<u8>, u16)>,
+        edits: Vec<(Vec<u8>, u16)>,
+        prefixes: Vec<Vec<u8>>
+    ) -> bool {
+        let mut trie = Trie::<_, _, Total>::with_measure();
+        let mut map = HashMap::new();
+        for (k, v) in inserts {
+            trie.insert(k.clone(), v);
+            map.insert(k, v);
+        }
+
+        let sum = |map: &HashMap<Vec<u8>, u16>, p: &[u8]| {
+            Sum(map.iter().filter(|(k, _)| k.starts_with(p)).map(|(_, &v)| v as u64).sum::<u64>())
+        };
+        let check = |trie: &Trie<Vec<u8>, u16, Total>, map: &HashMap<Vec<u8>, u16>| {
+            trie.aggregate() == sum(map, &[])
+                && prefixes.iter().all(|p| trie.aggregate_prefix(p) == sum(map, p))
+        };
+
+        for (k, v) in edits.iter().cloned() {
+            if let Some(val) = trie.get_mut(&k) {
+                *val = v;
+                map.insert(k.clone(), v);
+            }
+            *trie.entry(k.clone()).or_insert(0) /= 2;
+            *map.entry(k).or_insert(0) /= 2;
+        }
+        if !check(&trie, &map) {
+            return false;
+        }
+
+        if let Some(p) = prefixes.first() {
+            for (_, v) in trie.iter_prefix_mut(p) {
+                *v = v.wrapping_add(1);
+            }
+            for (k, v) in map.iter_mut() {
+                if k.starts_with(p) {
+                    *v = v.wrapping_add(1);
+                }
+            }
+            if !check(&trie, &map) {
+                return false;
+            }
+
+            trie.subtrie_mut(p).retain(|_, v| *v % 3 != 0);
+            map.retain(|k, v| !k.starts_with(p) || *v % 3 != 0);
+            if !check(&trie, &map) {
+                return false;
+            }
+        }
+
+        for v in trie.values_mut() {
+            *v /= 3;
+        }
+        for v in map.values_mut() {
+            *v /= 3;
+        }
+
+        // Bring the caches on the way back up to date, and check that they are right.
+        for (k, v) in edits {
+            trie.insert(k.clone(), v);
+            map.insert(k, v);
+        }
+        check(&trie, &map)
+    }
+
+    fn top_k_prefix_matches_sort(elts: Vec<(Vec<u8>, u16)>, prefix: Vec<u8>, k: usize) -> bool {
+        let k = k % 8;
+        // Few distinct scores, so that ties are common.
+        let mut trie = Trie::<_, _, Highest>::with_measure();
+        trie.extend(elts.into_iter().map(|(key, v)| (key, v % 4)));
+
+        let mut expected = trie.iter_prefix(&prefix).map(|(_, &v)| v).collect::<Vec<_>>();
+        expected.sort_by(|a, b| b.cmp(a));
+        expected.truncate(k);
+
+        // Ties may be broken either way, so only the scores are compared.
+        let top = trie.top_k_prefix(&prefix, k, |best| best.0);
+        top.iter().all(|(key, _)| key.starts_with(&prefix))
+            && top.iter().map(|(_, &v)| v).collect::<Vec<_>>() == expected
+    }
+
+    fn reversed_suffix_queries(keys: Vec<Vec<u8>>, suffix: Vec<u8>) -> bool {
+        use qp_trie::reversed::{Bytes, ReversedKey};
+
+        let mut trie = keys.iter().map(|k| (ReversedKey::<Bytes>::new(k), ())).collect::<Trie<_, _>>();
+
+        let mut expected = keys.iter().filter(|k| k.ends_with(&suffix)).cloned().collect::<Vec<_>>();
+        expected.sort();
+        expected.dedup();
+
+        let mut found = trie.iter_suffix(&suffix).map(|(k, _)| k).collect::<Vec<_>>();
+        found.sort();
+
+        let common = keys
+            .iter()
+            .map(|k| k.iter().rev().zip(suffix.iter().rev()).take_while(|(a, b)| a == b).count())
+            .max()
+            .unwrap_or(0);
+        let lcs = trie.longest_common_suffix(&suffix);
+
+        let removed = trie.remove_suffix(&suffix);
+
+        found == expected
+            && lcs == suffix[suffix.len() - common..]
+            && removed.count() == expected.len()
+            && trie.iter().all(|(k, _)| !k.key().ends_with(&suffix))
+    }
+
+    fn xor_distance_order(keys: Vec<(u32, u8)>, short: Vec<u8>, target: u32) -> bool {
+        let distance = |k: &[u8]| {
+            let mut d = [0u8; 4];
+            d.iter_mut().zip(k.iter().zip(&target.to_be_bytes())).for_each(|(d, (a, b))| *d = a ^ b);
+            d
+        };
+
+        let mut trie = keys.iter().map(|&(k, v)| (k.to_be_bytes().to_vec(), v)).collect::<Trie<_, _>>();
+        // Keys of other lengths are never produced.
+        trie.insert(short, 0);
+
+        let mut expected = trie
+            .keys()
+            .filter(|k| k.len() == 4)
+            .map(|k| distance(k))
+            .collect::<Vec<_>>();
+        expected.sort();
+
+        let found = trie
+            .iter_by_xor_distance(&target.to_be_bytes()[..])
+            .map(|(k, _)| distance(k))
+            .collect::<Vec<_>>();
+        let nearest = trie.nearest_by_xor(&target.to_be_bytes()[..], 3);
+
+        found == expected
+            && nearest.len() == expected.len().min(3)
+            && nearest.iter().map(|(k, _)| distance(k)).eq(expected.iter().cloned().take(3))
+    }
+
+    fn hamming_and_mask_match_scan(keys: Vec<(u16, u8)>, extra: Vec<Vec<u8>>, target: u16, max_bits: u8, mask: u16) -> bool {
+        let mut trie = keys.iter().map(|&(k, v)| (k.to_be_bytes().to_vec(), v)).collect::<Trie<_, _>>();
+        trie.extend(extra.into_iter().map(|k| (k, 0)));
+
+        let max_bits = max_bits as u32 % 8;
+        let target = target.to_be_bytes();
+        let mask = mask.to_be_bytes();
+
+        let mut expected = trie
+            .keys()
+            .filter(|k| k.len() == 2 && k.iter().zip(&target).map(|(a, b)| (a ^ b).count_ones()).sum::<u32>() <= max_bits)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut found = trie.search_hamming(&target[..], max_bits).map(|(k, _)| k.clone()).collect::<Vec<_>>();
+        expected.sort();
+        found.sort();
+
+        let mut expected_masked = trie
+            .keys()
+            .filter(|k| k.len() >= 2 && (0..2).all(|i| k[i] & mask[i] == target[i] & mask[i]))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut masked = trie.search_mask(&target[..], &mask[..]).map(|(k, _)| k.clone()).collect::<Vec<_>>();
+        expected_masked.sort();
+        masked.sort();
+
+        found == expected && masked == expected_masked
+    }
+
+    fn levenshtein_matches_scan(keys: Vec<Vec<u8>>, query: Vec<u8>, max: u8) -> bool {
+        use qp_trie::automaton::Levenshtein;
+
+        fn distance(a: &[u8], b: &[u8]) -> usize {
+            let mut row = (0..=b.len()).collect::<Vec<_>>();
+            for (i, &x) in a.iter().enumerate() {
+                let mut next = vec![i + 1];
+                for (j, &y) in b.iter().enumerate() {
+                    next.push((row[j] + (x != y) as usize).min(row[j + 1].min(next[j]) + 1));
+                }
+                row = next;
+            }
+            row[b.len()]
+        }
+
+        // Keep the alphabet small, so that keys land near the query.
+        let squash = |k: &Vec<u8>| k.iter().map(|b| b % 4).collect::<Vec<u8>>();
+        let query = squash(&query);
+        let max = max as usize % 3;
+        let trie = keys.iter().map(|k| (squash(k), ())).collect::<Trie<_, _>>();
+
+        let mut expected = trie.keys().filter(|k| distance(k, &query) <= max).cloned().collect::<Vec<_>>();
+        let mut found = trie
+            .search_automaton(&Levenshtein::new(&query, max))
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        expected.sort();
+        found.sort();
+
+        found == expected
+    }
+
+    fn case_fold_keys(keys: Vec<(String, u8)>, query: String) -> bool {
+        use qp_trie::wrapper::{CaseFold, CaseFoldStr, Normalization, NormalizedStrTrie};
+
+        let fold = |s: &str| {
+            let mut out = String::new();
+            CaseFold::normalize(s, &mut out);
+            out
+        };
+
+        let mut trie = Trie::new();
+        let mut map = HashMap::new();
+        for (k, v) in keys.iter() {
+            trie.insert(CaseFoldStr::from(k.as_str()), *v);
+            map.insert(fold(k), *v);
+        }
+
+        let upper = query.to_uppercase();
+        let lcp = trie.longest_common_prefix_str(&upper);
+        let lcp_ok = trie.is_empty()
+            || trie.keys().any(|k| k.as_str().starts_with(lcp) && fold(&query).starts_with(&fold(lcp)));
+
+        keys.iter().all(|(k, _)| trie.get_str(&k.to_uppercase()) == map.get(&fold(&k.to_uppercase())))
+            && trie.count() == map.len()
+            && lcp_ok
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    fn nfc_original_len_matches_default(picks: Vec<u8>, loc: usize) -> bool {
+        use qp_trie::wrapper::{Nfc, Normalization};
+
+        // The default `original_len`, which normalizes every prefix of the original in turn.
+        enum SlowNfc {}
+        impl Normalization for SlowNfc {
+            fn normalize(s: &str, out: &mut String) {
+                Nfc::normalize(s, out)
+            }
+        }
+
+        // Characters which compose, reorder or decompose to non-starters under NFC.
+        let alphabet = [
+            'a', 'e', '\u{e9}', '\u{301}', '\u{328}', '\u{f71}', '\u{f73}', '\u{1100}',
+            '\u{1161}', '\u{11a8}', '\u{ac00}', '\u{dd9}', '\u{dcf}', '\u{344}',
+        ];
+        let original = picks.iter().map(|&i| alphabet[i as usize % alphabet.len()]).collect::<String>();
+        let mut normalized = String::new();
+        Nfc::normalize(&original, &mut normalized);
+        let loc = loc % (normalized.len() + 1);
+
+        Nfc::original_len(&original, &normalized, loc) == SlowNfc::original_len(&original, &normalized, loc)
+    }
+
+    #[cfg(feature = "rayon")]
+    fn par_extend_matches_extend(old: Vec<(Vec<u8>, u8)>, new: Vec<(Vec<u8>, u8)>) -> bool {
+        use rayon::prelude::*;
+
+        // Keys over a small alphabet, so that the two batches overlap.
+        let squash = |(k, v): (Vec<u8>, u8)| (k.into_iter().map(|b| b % 4).collect::<Vec<_>>(), v);
+        let new = new.into_iter().map(squash).collect::<Vec<_>>();
+
+        let mut sequential = old.into_iter().map(squash).collect::<Trie<_, _>>();
+        let mut parallel = sequential.clone();
+        sequential.extend(new.iter().cloned());
+        parallel.par_extend(new.into_par_iter());
+
+        // Tries with the same entries have the same shape, so this compares the branches too.
+        parallel == sequential && parallel.count() == sequential.count()
+    }
+
+    #[cfg(feature = "std")]
+    fn path_keys_match_scan(keys: Vec<Vec<u8>>, query: Vec<u8>) -> bool {
+        use qp_trie::path::PathKey;
+        use std::path::PathBuf;
+
+        let to_path = |components: &[u8]| {
+            let mut path = PathBuf::from("/");
+            for &c in components.iter().take(4) {
+                path.push(["a", "ab", "b", "a.b"][c as usize % 4]);
+            }
+            path
+        };
+
+        let keys = keys.iter().map(|key| to_path(key)).collect::<Vec<_>>();
+        let query = to_path(&query);
+        let trie = keys
+            .iter()
+            .map(|key| (PathKey::from(key.as_path()), ()))
+            .collect::<Trie<_, _>>();
+
+        let mut beneath = trie
+            .iter_prefix_path(&query)
+            .map(|(key, _)| key.as_path())
+            .collect::<Vec<_>>();
+        let mut expected = keys
+            .iter()
+            .filter(|key| key.starts_with(&query))
+            .map(|key| key.as_path())
+            .collect::<Vec<_>>();
+        beneath.sort();
+        expected.sort();
+        expected.dedup();
+
+        let deepest = keys
+            .iter()
+            .filter(|key| query.starts_with(key))
+            .max_by_key(|key| key.components().count());
+
+        let lcp = trie.longest_common_prefix_path(&query);
+        let depth = keys
+            .iter()
+            .map(|key| {
+                key.components()
+                    .zip(query.components())
+                    .take_while(|(k, q)| k == q)
+                    .count()
+            })
+            .max()
+            .unwrap_or(0);
+
+        let mut ancestors = keys
+            .iter()
+            .filter(|key| query.starts_with(key))
+            .map(|key| key.as_path())
+            .collect::<Vec<_>>();
+        ancestors.sort_by_key(|key| std::cmp::Reverse(key.components().count()));
+        ancestors.dedup();
+
+        beneath == expected
+            && trie.deepest_ancestor(&query).map(|(key, _)| key.as_path())
+                == deepest.map(|key| key.as_path())
+            && trie.iter_ancestors(&query).map(|(key, _)| key.as_path()).collect::<Vec<_>>()
+                == ancestors
+            && query.starts_with(lcp)
+            && lcp.components().count() == depth
+    }
+
+    fn tuple_keys_preserve_order(rows: Vec<(i64, String, Vec<u8>)>, tenant: i64) -> bool {
+        use qp_trie::tuple::TupleKey;
+
+        let trie = rows
+            .iter()
+            .map(|row| (TupleKey::pack(row), ()))
+            .collect::<Trie<_, _>>();
+
+        let mut sorted = rows.clone();
+        sorted.sort();
+        sorted.dedup();
+
+        let decoded = trie
+            .keys()
+            .map(|key| key.unpack::<(i64, String, Vec<u8>)>().unwrap())
+            .collect::<Vec<_>>();
+        let mut ordered = trie.keys().collect::<Vec<_>>();
+        ordered.sort();
+
+        let table = rows.first().map(|row| row.1.clone()).unwrap_or_default();
+        let mut beneath = trie
+            .iter_prefix(&tuple!(tenant, &table))
+            .map(|(key, _)| key.unpack::<(i64, String, Vec<u8>)>().unwrap())
+            .collect::<Vec<_>>();
+        let mut expected = sorted
+            .iter()
+            .filter(|row| row.0 == tenant && row.1 == table)
+            .cloned()
+            .collect::<Vec<_>>();
+        beneath.sort();
+        expected.sort();
+
+        decoded.iter().all(|row| sorted.binary_search(row).is_ok())
+            && decoded.len() == sorted.len()
+            && ordered
+                .iter()
+                .map(|key| key.unpack::<(i64, String, Vec<u8>)>().unwrap())
+                .collect::<Vec<_>>()
+                == sorted
+            && beneath == expected
+    }
+
     fn longest_common_prefix(boolfix: Vec<bool>, boolts: Vec<(Vec<bool>, u64)>) -> TestResult {
         let prefix = boolfix.into_iter().map(|b| if b { 1 } else { 0 }).collect::<Vec<u8>>();
         let elts = boolts.into_iter().map(|(key, val)| (key.into_iter().map(|b| if b { 1 } else { 0 }).collect::<Vec<u8>>(), val)).collect::<Vec<(Vec<u8>, u64)>>();
@@ -196,6 +938,31 @@ quickcheck! {
         given_values == yielded_values
     }
 
+    fn freeze_and_get(prefix: Vec<u8>, kvs: HashMap<Vec<u8>, Vec<u8>>) -> bool {
+        let trie: Trie<&[u8], &[u8]> = kvs.iter().map(|(k, v)| (&k[..], &v[..])).collect();
+        let image = trie.freeze();
+        let frozen = FrozenTrie::new(&image).unwrap();
+
+        let iterated = trie.iter().map(|(&k, &v)| (k, v)).eq(frozen.iter());
+        let prefixed = trie
+            .iter_prefix(&prefix[..])
+            .map(|(&k, &v)| (k, v))
+            .eq(frozen.iter_prefix(&prefix));
+        let got = trie.iter().all(|(&k, &v)| frozen.get(k) == Some(v));
+        let aligned = frozen
+            .iter()
+            .all(|(_, v)| (v.as_ptr() as usize - image.as_ptr() as usize) % FrozenTrie::VALUE_ALIGN == 0);
+
+        frozen.count() == trie.count()
+            && aligned
+            && iterated
+            && prefixed
+            && got
+            && frozen.get(&prefix) == trie.get(&prefix[..]).cloned()
+            && frozen.subtrie(&prefix).count() == trie.iter_prefix(&prefix[..]).count()
+            && frozen.longest_common_prefix(&prefix) == trie.longest_common_prefix(&prefix[..])
+    }
+
     #[cfg(feature = "serde")]
     fn serialize(kvs: Vec<(Vec<u8>, usize)>) -> bool {
         let original: Trie<Vec<u8>, usize> = kvs.into_iter().collect();
@@ -218,6 +985,24 @@ quickcheck! {
 
         deserialized == Trie::new()
     }
+
+    #[cfg(feature = "serde")]
+    fn serialize_pairs(kvs: Vec<(Vec<u8>, usize)>) -> bool {
+        let original = Pairs { trie: kvs.into_iter().collect() };
+        let serialized = serde_json::to_vec(&original).unwrap();
+        let deserialized: Pairs = serde_json::from_slice(&serialized).unwrap();
+
+        deserialized == original
+    }
+
+    #[cfg(feature = "serde")]
+    fn serialize_structural(kvs: Vec<(Vec<u8>, usize)>) -> bool {
+        let original = Structural { trie: kvs.into_iter().collect() };
+        let serialized = bincode::serialize(&original).unwrap();
+        let deserialized: Structural = bincode::deserialize(&serialized).unwrap();
+
+        deserialized.trie.count() == original.trie.count() && deserialized == original
+    }
 }
 
 fn entry_insert_and_remove_regression(elts: Vec<(Vec<u8>, Option<u64>)>) -> bool {
@@ -498,3 +1283,996 @@ fn issue_36_node_count_after_clear() {
     trie.clear();
     assert_eq!(0, trie.count());
 }
+
+#[test]
+fn frozen_rejects_bad_images() {
+    let trie: Trie<Vec<u8>, Vec<u8>> = Trie::new();
+    let image = trie.freeze();
+
+    assert!(FrozenTrie::new(&image).unwrap().is_empty());
+    assert_eq!(
+        FrozenTrie::new(&image[..8]).unwrap_err(),
+        FrozenError::Truncated
+    );
+    assert_eq!(
+        FrozenTrie::new(&[0; 32]).unwrap_err(),
+        FrozenError::BadMagic
+    );
+}
+
+#[test]
+fn frozen_rejects_corrupted_images() {
+    let trie: Trie<&[u8], &[u8]> = [(&b"aa"[..], &b"1"[..]), (b"ab", b"2"), (b"b", b"3")]
+        .iter()
+        .cloned()
+        .collect();
+    let image = trie.freeze();
+    assert!(FrozenTrie::new(&image).is_ok());
+
+    // The root branch directly follows the 24-byte header, and its first child, the branch over
+    // "aa" and "ab", is referenced 16 bytes into it.
+    let corrupt = |at: usize, word: u32| {
+        let mut image = image.clone();
+        image[at..at + 4].copy_from_slice(&word.to_le_bytes());
+        FrozenTrie::new(&image).map(|_| ())
+    };
+    assert_eq!(corrupt(40, 24), Err(FrozenError::Corrupt));
+    assert_eq!(corrupt(40, 0x7fff_fff0), Err(FrozenError::Corrupt));
+    assert_eq!(corrupt(12, 0), Err(FrozenError::Corrupt));
+    assert!(FrozenTrie::new(&image[..image.len() - 1]).is_err());
+
+    // No single corrupted byte may make opening or reading the image panic or loop.
+    for at in 0..image.len() {
+        for &flip in &[0x01, 0x10, 0x80, 0xff] {
+            let mut image = image.clone();
+            image[at] ^= flip;
+
+            if let Ok(frozen) = FrozenTrie::new(&image) {
+                for (key, _) in trie.iter() {
+                    frozen.get(key);
+                    frozen.iter_prefix(&key[..1]).count();
+                    frozen.longest_common_prefix(key);
+                }
+                frozen.iter().rev().count();
+            }
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn serialize_structural_rejects_inconsistent_branches() {
+    let trie = (0u8..4).map(|i| (vec![i], i as usize)).collect();
+    let json = serde_json::to_string(&Structural { trie }).unwrap();
+
+    let moved = json.replace("[[0],0]", "[[5],0]");
+    assert!(serde_json::from_str::<Structural>(&moved).is_err());
+
+    let shallow = json.replace(",0,30,", ",0,14,");
+    assert!(serde_json::from_str::<Structural>(&shallow).is_err());
+
+    assert_eq!(
+        serde_json::from_str::<Structural>(&json)
+            .unwrap()
+            .trie
+            .count(),
+        4
+    );
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn serialize_structural_limits_depth() {
+    use qp_trie::serialization::structural::{StructuralSeed, DEFAULT_MAX_DEPTH};
+
+    // Every key is a prefix of the next, so each one but the first adds a level of nesting.
+    let depth = DEFAULT_MAX_DEPTH + 1;
+    let deep = (1..=depth + 1)
+        .map(|len| (vec![b'a'; len], len))
+        .collect::<Trie<_, _>>();
+    let bytes = bincode::serialize(&Structural { trie: deep.clone() }).unwrap();
+
+    assert!(bincode::deserialize::<Structural>(&bytes).is_err());
+
+    let load = |seed: StructuralSeed<Vec<u8>, usize>| {
+        use bincode::Options;
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .deserialize_seed(seed, &bytes)
+    };
+
+    assert_eq!(load(StructuralSeed::new().max_depth(depth)).unwrap(), deep);
+    assert!(load(StructuralSeed::new().max_depth(depth - 1)).is_err());
+
+    let unlimited = || StructuralSeed::new().max_depth(usize::MAX);
+    assert!(load(unlimited().max_key_len(depth + 1)).is_ok());
+    assert!(load(unlimited().max_key_len(depth)).is_err());
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn serialize_subtrie() {
+    let mut trie = Trie::new();
+    trie.insert_str("a/b", 1);
+    trie.insert_str("a/c", 2);
+    trie.insert_str("b", 3);
+
+    let json = serde_json::to_string(&trie.subtrie_str("a/")).unwrap();
+    assert_eq!(json, r#"{"a/b":1,"a/c":2}"#);
+
+    let mut json = Vec::new();
+    trie.serialize_prefix(
+        AsRef::<wrapper::BStr>::as_ref("b"),
+        &mut serde_json::Serializer::new(&mut json),
+    )
+    .unwrap();
+    assert_eq!(json, br#"{"b":3}"#);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn serialize_nested() {
+    use qp_trie::serialization::nested;
+
+    let mut trie = Trie::new();
+    trie.insert_str("a/b/c", 1);
+    trie.insert_str("a/b/d", 2);
+    trie.insert_str("a/e", 3);
+    trie.insert_str("f", 4);
+
+    let mut json = Vec::new();
+    nested::serialize(&trie, &mut serde_json::Serializer::new(&mut json)).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({"a": {"b": {"c": 1, "d": 2}, "e": 3}, "f": 4})
+    );
+
+    let mut json = Vec::new();
+    nested::serialize_with_separator(&trie, b'b', &mut serde_json::Serializer::new(&mut json))
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({"a/": {"/c": 1, "/d": 2}, "a/e": 3, "f": 4})
+    );
+
+    trie.insert_str("a", 5);
+    let mut json = Vec::new();
+    assert!(nested::serialize(&trie, &mut serde_json::Serializer::new(&mut json)).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn deserialize_nested() {
+    use qp_trie::serialization::nested::TreeSeed;
+    use serde::de::DeserializeSeed;
+    use wrapper::BString;
+
+    let json = r#"{"a": {"b": {"c": [1, 2], "d": [4]}, "e": [3]}, "f": []}"#;
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    let trie: Trie<BString, Vec<u32>> = TreeSeed::new(b'.').deserialize(&mut deserializer).unwrap();
+
+    let mut expected = Trie::new();
+    expected.insert_str("a.b.c", vec![1, 2]);
+    expected.insert_str("a.b.d", vec![4]);
+    expected.insert_str("a.e", vec![3]);
+    expected.insert_str("f", vec![]);
+    assert_eq!(trie, expected);
+
+    let json = serde_json::to_string(&trie.as_tree(b'.')).unwrap();
+    let mut deserializer = serde_json::Deserializer::from_str(&json);
+    let roundtrip: Trie<BString, Vec<u32>> =
+        TreeSeed::new(b'.').deserialize(&mut deserializer).unwrap();
+    assert_eq!(roundtrip, expected);
+
+    let mut deserializer = serde_json::Deserializer::from_str(r#"{"a.b": 1}"#);
+    assert!(TreeSeed::<BString, u32>::new(b'.')
+        .deserialize(&mut deserializer)
+        .is_err());
+
+    #[derive(Deserialize)]
+    struct Nested {
+        #[serde(with = "qp_trie::serialization::nested")]
+        trie: Trie<Vec<u8>, u32>,
+    }
+
+    let nested: Nested = serde_json::from_str(r#"{"trie": {"x": {"y": 1}, "z": 2}}"#).unwrap();
+    assert_eq!(nested.trie.get(&b"x/y"[..]), Some(&1));
+    assert_eq!(nested.trie.get(&b"z"[..]), Some(&2));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn deserialize_seed_policies() {
+    use qp_trie::serialization::TrieSeed;
+    use serde::de::DeserializeSeed;
+    use wrapper::BString;
+
+    fn load<F: FnMut(&mut u32, u32)>(
+        seed: TrieSeed<BString, u32, F>,
+        json: &str,
+    ) -> Result<Trie<BString, u32>, serde_json::Error> {
+        seed.deserialize(&mut serde_json::Deserializer::from_str(json))
+    }
+
+    let json = r#"{"abc": 1, "ab": 2, "abc": 3}"#;
+
+    assert_eq!(
+        load(TrieSeed::new(), json).unwrap().get_str("abc"),
+        Some(&3)
+    );
+    assert_eq!(
+        load(TrieSeed::new().keep_first(), json)
+            .unwrap()
+            .get_str("abc"),
+        Some(&1)
+    );
+    assert_eq!(
+        load(TrieSeed::new().keep_first().keep_last(), json)
+            .unwrap()
+            .get_str("abc"),
+        Some(&3)
+    );
+    assert_eq!(
+        load(TrieSeed::new().merge_duplicates(|a, b| *a += b), json)
+            .unwrap()
+            .get_str("abc"),
+        Some(&4)
+    );
+    assert!(load(TrieSeed::new().reject_duplicates(), json).is_err());
+    assert!(load(
+        TrieSeed::new().reject_duplicates(),
+        r#"{"abc": 1, "ab": 2}"#
+    )
+    .is_ok());
+
+    assert!(load(TrieSeed::new().max_entries(3), json).is_ok());
+    assert!(load(TrieSeed::new().max_entries(2), json).is_err());
+    assert!(load(TrieSeed::new().max_key_len(3), json).is_ok());
+    assert!(load(TrieSeed::new().max_key_len(2), json).is_err());
+    assert!(load(TrieSeed::new().max_key_bytes(8), json).is_ok());
+    assert!(load(TrieSeed::new().max_key_bytes(7), json).is_err());
+}
+
+#[test]
+fn subtrie_mut_clear() {
+    let mut trie = Trie::new();
+    trie.insert_str("a/x", 1);
+    trie.insert_str("a/y", 2);
+    trie.insert_str("ab", 3);
+
+    trie.subtrie_mut_str("a/").clear();
+    assert_eq!(trie.count(), 1);
+    assert_eq!(trie.get_str("ab"), Some(&3));
+    assert!(trie.subtrie_mut_str("a/").is_empty());
+}
+
+#[test]
+#[should_panic]
+fn subtrie_mut_insert_outside_prefix() {
+    let mut trie = Trie::new();
+    trie.subtrie_mut_str("a/").insert_str("b/x", 1);
+}
+
+#[test]
+fn subtrie_nested_prefix() {
+    let mut trie = Trie::new();
+    trie.insert_str("abc", 1);
+    trie.insert_str("abd", 2);
+    trie.insert_str("b", 3);
+
+    let subtrie = trie.subtrie_str("ab");
+    assert_eq!(subtrie.subtrie_str("a").prefix(), b"ab");
+    assert_eq!(subtrie.subtrie_str("abc").prefix(), b"abc");
+    assert_eq!(subtrie.subtrie_str("abe").prefix(), b"abe");
+    assert!(subtrie.subtrie_str("abe").is_empty());
+    assert_eq!(subtrie.subtrie_str("a").len(), 2);
+    assert_eq!(subtrie.subtrie_str("abc").get_str("abc"), Some(&1));
+    assert!(subtrie.subtrie_str("b").is_empty());
+    assert_eq!(subtrie["abd".as_bytes()], 2);
+}
+
+#[test]
+fn entry_insert_entry_and_replace_key() {
+    // A key which carries a tag alongside its bytes.
+    #[derive(Debug, PartialEq)]
+    struct Tagged(&'static [u8], u32);
+
+    impl std::borrow::Borrow<[u8]> for Tagged {
+        fn borrow(&self) -> &[u8] {
+            self.0
+        }
+    }
+
+    let mut trie = Trie::new();
+    trie.insert(Tagged(b"ab", 0), 1);
+
+    let mut occupied = match trie.entry(Tagged(b"abc", 1)) {
+        Entry::Vacant(vacant) => vacant.insert_entry(2),
+        Entry::Occupied(..) => panic!("entry should be vacant"),
+    };
+    assert_eq!(occupied.key(), &Tagged(b"abc", 1));
+    assert_eq!(occupied.replace_key(Tagged(b"abc", 2)), Tagged(b"abc", 1));
+    assert_eq!(
+        occupied.replace_entry(Tagged(b"abc", 3), 4),
+        (Tagged(b"abc", 2), 2)
+    );
+    assert_eq!(occupied.remove_entry(), (Tagged(b"abc", 3), 4));
+    assert_eq!(trie.count(), 1);
+
+    let value = trie
+        .entry(Tagged(b"b", 0))
+        .or_insert_with_key(|key| key.0.len() as u32);
+    assert_eq!(*value, 1);
+    assert_eq!(trie.count(), 2);
+
+    if let Entry::Occupied(mut occupied) = trie.entry(Tagged(b"ab", 5)) {
+        assert_eq!(occupied.replace_key(Tagged(b"ab", 6)), Tagged(b"ab", 0));
+    }
+    assert_eq!(trie.iter().next(), Some((&Tagged(b"ab", 6), &1)));
+}
+
+#[test]
+#[should_panic]
+fn entry_replace_key_with_different_bytes() {
+    let mut trie = Trie::new();
+    trie.insert_str("a", 1);
+
+    if let Entry::Occupied(mut occupied) = trie.entry("a".into()) {
+        occupied.replace_key("b".into());
+    }
+}
+
+#[test]
+fn entry_ref_str() {
+    let mut trie = Trie::new();
+
+    for word in "the cat and the hat".split(' ') {
+        trie.entry_ref_str(word)
+            .and_modify(|n| *n += 1)
+            .or_insert(1);
+    }
+
+    assert_eq!(trie.count(), 4);
+    assert_eq!(trie.get_str("the"), Some(&2));
+    assert_eq!(trie.get_str("hat"), Some(&1));
+
+    match trie.entry_ref_str("dog") {
+        EntryRef::Vacant(vacant) => {
+            assert_eq!(vacant.key(), AsRef::<wrapper::BStr>::as_ref("dog"));
+            assert_eq!(vacant.insert_entry(5).key(), &"dog".into());
+        }
+        EntryRef::Occupied(..) => panic!("entry should be vacant"),
+    }
+    assert_eq!(trie.get_str("dog"), Some(&5));
+}
+
+#[test]
+fn prefix_entry_str() {
+    let mut trie = Trie::new();
+
+    for word in "a cat and a catalogue of cattle".split(' ') {
+        trie.insert_str(word, word.len());
+    }
+
+    match trie.prefix_entry_str("dog") {
+        PrefixEntry::Vacant(vacant) => {
+            let mut occupied = vacant.insert("dog".into(), 3);
+            assert_eq!(occupied.insert("doghouse".into(), 8), None);
+            assert_eq!(occupied.count(), 2);
+        }
+        PrefixEntry::Occupied(..) => panic!("entry should be vacant"),
+    }
+
+    match trie.prefix_entry_str("cat") {
+        PrefixEntry::Occupied(mut occupied) => {
+            assert_eq!(occupied.prefix(), b"cat");
+            assert_eq!(occupied.count(), 3);
+
+            for (_, v) in occupied.iter_mut() {
+                *v *= 10;
+            }
+
+            let removed = occupied.remove();
+            assert_eq!(removed.count(), 3);
+            assert_eq!(removed.get_str("cattle"), Some(&60));
+        }
+        PrefixEntry::Vacant(..) => panic!("entry should be occupied"),
+    }
+
+    assert_eq!(trie.count(), 5);
+    assert_eq!(trie.get_str("catalogue"), None);
+    assert_eq!(trie.get_str("and"), Some(&3));
+    assert_eq!(trie.get_str("doghouse"), Some(&8));
+}
+
+#[test]
+#[should_panic]
+fn prefix_entry_insert_outside_prefix() {
+    use wrapper::BString;
+
+    let mut trie = Trie::<BString, u32>::new();
+    trie.insert_str("cat", 1);
+
+    if let PrefixEntry::Occupied(mut occupied) = trie.prefix_entry_str("ca") {
+        occupied.insert("dog".into(), 2);
+    }
+}
+
+#[test]
+fn get_many_mut_swap() {
+    let mut trie = Trie::new();
+    trie.insert(b"a".to_vec(), 1);
+    trie.insert(b"ab".to_vec(), 2);
+    trie.insert(b"b".to_vec(), 3);
+
+    if let Some([a, b]) = trie.get_many_mut([&b"a"[..], &b"b"[..]]) {
+        std::mem::swap(a, b);
+    }
+
+    assert_eq!(trie.get(&b"a"[..]), Some(&3));
+    assert_eq!(trie.get(&b"b"[..]), Some(&1));
+
+    assert!(trie.get_many_mut([&b"a"[..], &b"c"[..]]).is_none());
+    assert!(trie
+        .get_many_mut([&b"ab"[..], &b"b"[..], &b"ab"[..]])
+        .is_none());
+    assert_eq!(trie.get_many_mut::<[u8], 0>([]), Some([]));
+}
+
+// Run under Miri as well: the leaves share one branch's entries, so the references must not
+// invalidate each other.
+#[test]
+fn get_many_mut_siblings() {
+    let mut trie = Trie::new();
+    for (i, key) in ["a", "b", "c", "d"].iter().enumerate() {
+        trie.insert(key.as_bytes().to_vec(), i);
+    }
+
+    let [a, b, c, d] = trie
+        .get_many_mut([&b"a"[..], &b"b"[..], &b"c"[..], &b"d"[..]])
+        .unwrap();
+    *a += 10;
+    *b += 20;
+    *c += 30;
+    *d += 40;
+    *a += *d;
+
+    assert_eq!(
+        trie.values().cloned().collect::<Vec<_>>(),
+        vec![53, 21, 32, 43]
+    );
+}
+
+#[test]
+fn try_insert() {
+    let mut trie = Trie::new();
+
+    *trie.try_insert("cat".into(), 1).unwrap() += 1;
+
+    let err = trie.try_insert("cat".into(), 5).unwrap_err();
+    assert_eq!(err.value, 5);
+    assert_eq!(err.entry.get(), &2);
+    assert_eq!(
+        err.to_string(),
+        "failed to insert 5, key \"cat\" already exists with value 2"
+    );
+
+    assert_eq!(trie.count(), 1);
+    assert_eq!(trie.get_str("cat"), Some(&2));
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_iter_matches_iter() {
+    use rayon::prelude::*;
+    use std::collections::BTreeMap;
+
+    let mut x = 0u32;
+    let mut map = BTreeMap::new();
+
+    for i in 0..20_000u32 {
+        x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        let len = (x >> 28) as usize;
+        let key = x
+            .to_le_bytes()
+            .iter()
+            .cycle()
+            .take(len)
+            .cloned()
+            .collect::<Vec<u8>>();
+        map.insert(key, i);
+    }
+
+    let mut trie = map.clone().into_iter().collect::<Trie<_, _>>();
+
+    let sequential = trie.iter().collect::<Vec<_>>();
+    assert_eq!(trie.par_iter().collect::<Vec<_>>(), sequential);
+
+    trie.par_values_mut().for_each(|v| *v += 1);
+    trie.par_iter_mut().for_each(|(_, v)| *v *= 2);
+    assert!(trie.iter().all(|(k, &v)| v == (map[k] + 1) * 2));
+
+    let prefix = &[0x39][..];
+    let subtrie = trie.subtrie(prefix);
+    assert_eq!(
+        subtrie.par_iter().collect::<Vec<_>>(),
+        subtrie.iter().collect::<Vec<_>>()
+    );
+    {
+        let mut view = trie.subtrie_mut(prefix);
+        view.par_values_mut().for_each(|v| *v = 0);
+    }
+    assert!(trie.iter().all(|(k, &v)| (v == 0) == k.starts_with(prefix)));
+
+    let built = Trie::par_from_sorted(map.clone().into_iter().collect());
+    assert_eq!(built, map.clone().into_iter().collect::<Trie<_, _>>());
+    assert_eq!(built.count(), map.len());
+
+    let collected = map.clone().into_par_iter().collect::<Trie<_, _>>();
+    assert_eq!(collected, built);
+
+    let mut merged = map
+        .iter()
+        .step_by(2)
+        .map(|(k, &v)| (k.clone(), v + 1))
+        .collect::<Trie<_, _>>();
+    merged.par_extend(map.clone().into_par_iter());
+    assert_eq!(merged, built);
+    assert_eq!(merged.count(), map.len());
+    assert_eq!(
+        built.into_par_iter().collect::<Vec<_>>(),
+        collected.into_iter().collect::<Vec<_>>()
+    );
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+#[should_panic]
+fn par_from_sorted_unsorted() {
+    Trie::par_from_sorted(vec![(vec![2u8], ()), (vec![1u8], ())]);
+}
+
+#[cfg(feature = "digest")]
+#[test]
+fn merkle_diff_narrows_to_changed_subtrie() {
+    use sha2::Sha256;
+
+    let mut ours = Trie::<_, _, Merkle<Sha256>>::with_measure();
+    for i in 0..1000u32 {
+        ours.insert(format!("config/{:04}/value", i).into_bytes(), i);
+    }
+
+    let mut theirs = ours.clone();
+    theirs.insert(b"config/0420/value".to_vec(), 0);
+    assert_ne!(ours.merkle_root(), theirs.merkle_root());
+
+    let mut asked = 0;
+    let ranges = ours
+        .diff_by_hash(|range| {
+            asked += 1;
+            theirs.merkle_digest(range)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(ranges, vec![KeyRange::key(b"config/0420/value")]);
+    assert!(asked < 100);
+
+    assert_eq!(
+        ours.merkle_digest(&KeyRange::prefix(b"config/05")),
+        theirs.merkle_digest(&KeyRange::prefix(b"config/05"))
+    );
+    assert_ne!(
+        ours.merkle_digest(&KeyRange::prefix(b"config/04")),
+        theirs.merkle_digest(&KeyRange::prefix(b"config/04"))
+    );
+
+    // Removing and restoring an entry restores the cached digests.
+    let root = ours.merkle_root();
+    ours.remove(&b"config/0123/value"[..]);
+    assert_ne!(ours.merkle_root(), root);
+    ours.insert(b"config/0123/value".to_vec(), 123);
+    assert_eq!(ours.merkle_root(), root);
+
+    let empty = Trie::<Vec<u8>, u32, Merkle<Sha256>>::with_measure();
+    assert_eq!(empty.merkle_root(), None);
+    assert_eq!(
+        ours.diff_by_hash(|range| ours.merkle_digest(range)).count(),
+        0
+    );
+    assert_eq!(
+        empty
+            .diff_by_hash(|range| ours.merkle_digest(range))
+            .collect::<Vec<_>>(),
+        vec![KeyRange::all()]
+    );
+}
+
+#[test]
+fn diff_config_releases() {
+    let mut old = Trie::new();
+    old.insert(b"db.host".to_vec(), "10.0.0.1");
+    old.insert(b"db.port".to_vec(), "5432");
+    old.insert(b"log.level".to_vec(), "info");
+
+    let mut new = old.clone();
+    new.insert(b"log.level".to_vec(), "debug");
+    new.insert(b"log.format".to_vec(), "json");
+    new.remove(&b"db.port"[..]);
+
+    let mut diff = old.diff(&new).collect::<Vec<_>>();
+    diff.sort_by_key(|item| match *item {
+        DiffItem::Added(k, _) | DiffItem::Removed(k, _) | DiffItem::Changed(k, _, _) => k.clone(),
+    });
+
+    assert_eq!(
+        diff,
+        vec![
+            DiffItem::Removed(&b"db.port".to_vec(), &"5432"),
+            DiffItem::Added(&b"log.format".to_vec(), &"json"),
+            DiffItem::Changed(&b"log.level".to_vec(), &"info", &"debug"),
+        ]
+    );
+    assert_eq!(old.diff_by(&new, |_, _| true).count(), 2);
+    assert_eq!(old.diff(&old).next(), None);
+}
+
+#[test]
+fn diff_skips_shared_subtries() {
+    let trie = (0..64u8)
+        .map(|i| (vec![i, i / 4], i))
+        .collect::<Trie<_, _>>();
+    let copy = trie.clone();
+
+    let mut compared = 0;
+    let count = |_: &u8, _: &u8| {
+        compared += 1;
+        true
+    };
+    assert_eq!(trie.diff_by(&trie, count).count(), 0);
+    assert_eq!(compared, 0);
+
+    let mut compared = 0;
+    let count = |_: &u8, _: &u8| {
+        compared += 1;
+        true
+    };
+    assert_eq!(trie.diff_by(&copy, count).count(), 0);
+    assert_eq!(compared, 64);
+}
+
+#[test]
+fn annotated_min_max() {
+    let mut lows = Trie::<_, _, Lowest>::with_measure();
+    let mut highs = Trie::<_, _, Highest>::with_measure();
+
+    for (k, v) in [("a/x", 5), ("a/y", 2), ("a/z", 9), ("b/x", 1)] {
+        lows.insert(k.as_bytes(), v);
+        highs.insert(k.as_bytes(), v);
+    }
+
+    assert_eq!(lows.aggregate_prefix(&b"a/"[..]), Min(Some(2)));
+    assert_eq!(highs.aggregate_prefix(&b"a/"[..]), Max(Some(9)));
+    assert_eq!(lows.aggregate(), Min(Some(1)));
+
+    highs.remove(&b"a/z"[..]);
+    assert_eq!(highs.aggregate_prefix(&b"a/"[..]), Max(Some(5)));
+    assert_eq!(highs.aggregate_prefix(&b"c/"[..]), Max(None));
+}
+
+#[test]
+fn top_k_prefix_str() {
+    use wrapper::BString;
+
+    let words = [
+        ("apple", 40),
+        ("apply", 25),
+        ("ape", 90),
+        ("banana", 70),
+        ("apt", 20),
+    ];
+
+    let mut trie = Trie::<_, _, Highest>::with_measure();
+    trie.extend(words.iter().map(|&(w, n)| (BString::from(w), n)));
+
+    let top = trie.top_k_prefix_str("ap", 3, |best| best.0);
+    assert_eq!(
+        top.iter().map(|(w, _)| w.as_str()).collect::<Vec<_>>(),
+        vec!["ape", "apple", "apply"]
+    );
+    assert_eq!(trie.top_k_prefix_str("ap", 0, |best| best.0), vec![]);
+    assert_eq!(trie.top_k_prefix_str("c", 3, |best| best.0), vec![]);
+    assert_eq!(trie.aggregate_prefix_str("b"), Max(Some(70)));
+    assert_eq!(trie.aggregate_prefix_str("ap"), Max(Some(90)));
+}
+
+#[test]
+fn top_k_prefix_by_total() {
+    let mut trie = Trie::<_, _, Total>::with_measure();
+    for (k, v) in [("a/x", 5), ("a/y", 2), ("a/z", 9), ("b/x", 30)] {
+        trie.insert(k.as_bytes(), v);
+    }
+
+    // A branch's total is at least that of any entry beneath it, so totals can guide the search.
+    let top = trie.top_k_prefix(&b"a/"[..], 2, |total| total.0);
+    assert_eq!(top, vec![(&&b"a/z"[..], &9), (&&b"a/x"[..], &5)]);
+    assert_eq!(
+        trie.top_k_prefix(&b""[..], 1, |total| total.0),
+        vec![(&&b"b/x"[..], &30)]
+    );
+}
+
+#[test]
+fn reversed_dns_labels() {
+    use qp_trie::reversed::{Labels, ReversedKey};
+
+    let mut trie = Trie::new();
+    for name in &[
+        "ads.example.com",
+        "example.com",
+        "cdn.examples.com",
+        "tracker.example.org",
+    ] {
+        trie.insert(ReversedKey::<Labels>::new(name), ());
+    }
+
+    let mut found = trie.iter_suffix(".com").map(|(k, _)| k).collect::<Vec<_>>();
+    found.sort();
+    assert_eq!(
+        found,
+        [
+            &b"ads.example.com"[..],
+            &b"cdn.examples.com"[..],
+            &b"example.com"[..]
+        ]
+    );
+    assert_eq!(
+        trie.iter().map(|(k, _)| k.reversed()).min(),
+        Some(&b"com.example"[..])
+    );
+
+    assert_eq!(
+        trie.longest_common_suffix("www.example.com"),
+        b".example.com"
+    );
+    assert_eq!(trie.longest_common_suffix("mple.com"), b".com");
+    assert_eq!(trie.longest_common_suffix("example.net"), b"");
+
+    let org = trie.remove_suffix(".org");
+    assert_eq!(org.count(), 1);
+    assert_eq!(trie.count(), 3);
+}
+
+#[test]
+fn nearest_by_xor_routing_table() {
+    let mut table = Trie::new();
+    for id in &[0x00u8, 0x0F, 0x10, 0x80, 0xF0, 0xFF] {
+        table.insert(vec![*id, 0xAA], *id);
+    }
+
+    let nearest = table
+        .nearest_by_xor(&[0x11u8, 0xAA][..], 3)
+        .into_iter()
+        .map(|(_, &id)| id)
+        .collect::<Vec<_>>();
+    assert_eq!(nearest, vec![0x10, 0x00, 0x0F]);
+
+    let farthest = table.iter_by_xor_distance(&[0x11u8, 0xAA][..]).last();
+    assert_eq!(farthest.map(|(_, &id)| id), Some(0xFF));
+}
+
+#[test]
+fn search_mask_feature_flags() {
+    let mut trie = Trie::new();
+    for flags in 0u8..=0xFF {
+        trie.insert(vec![flags], flags);
+    }
+
+    // Bit 0 set and bit 7 clear.
+    let found = trie.search_mask(&[0x01u8][..], &[0x81u8][..]).count();
+    assert_eq!(found, 64);
+
+    let near = trie
+        .search_hamming(&[0x00u8][..], 1)
+        .map(|(_, &v)| v)
+        .collect::<Vec<_>>();
+    assert_eq!(near.len(), 9);
+    assert!(near.iter().all(|v| v.count_ones() <= 1));
+}
+
+#[cfg(feature = "regex-automata")]
+#[test]
+fn search_automaton_regex() {
+    use regex_automata::dfa::dense;
+
+    let mut trie = Trie::new();
+    for key in &["app.log", "app.log.1", "db.log", "db.conf", "log"] {
+        trie.insert(key.as_bytes(), ());
+    }
+
+    let dfa = dense::DFA::new(r"[a-z]+\.log").unwrap();
+    let mut found = trie
+        .search_automaton(&dfa)
+        .map(|(&k, _)| k)
+        .collect::<Vec<_>>();
+    found.sort();
+
+    assert_eq!(found, [&b"app.log"[..], &b"db.log"[..]]);
+}
+
+#[test]
+fn normalized_header_names() {
+    use qp_trie::wrapper::{AsciiCaseFoldStr, NormalizedStrTrie};
+
+    let mut headers = Trie::new();
+    headers.insert(AsciiCaseFoldStr::from("Accept-Encoding"), 1);
+    headers.insert_str("X-Request-Id", 2);
+    assert_eq!(headers.insert_str("x-request-id", 3), Some(2));
+
+    assert_eq!(headers.get_str("ACCEPT-ENCODING"), Some(&1));
+    assert_eq!(headers.iter_prefix_str("x-").count(), 1);
+    assert_eq!(
+        headers.keys().map(|k| k.as_str()).collect::<Vec<_>>(),
+        vec!["Accept-Encoding", "X-Request-Id"]
+    );
+    assert_eq!(
+        headers.longest_common_prefix_str("accept-language"),
+        "Accept-"
+    );
+    assert_eq!(
+        headers.longest_common_prefix(&b"accept-language"[..]),
+        "Accept-"
+    );
+
+    assert_eq!(headers.remove_str("X-REQUEST-ID"), Some(3));
+    assert_eq!(headers.count(), 1);
+}
+
+#[cfg(feature = "unicode-normalization")]
+#[test]
+fn normalized_nfc_usernames() {
+    use qp_trie::wrapper::{NfcStr, NormalizedStrTrie};
+
+    let mut users = Trie::new();
+    users.insert(NfcStr::from("Jos\u{e9}"), 1);
+
+    assert_eq!(users.get_str("Jose\u{301}"), Some(&1));
+    assert_eq!(
+        users.longest_common_prefix_str("Jose\u{301}phine"),
+        "Jos\u{e9}"
+    );
+    assert_eq!(users.longest_common_prefix_str("Josh"), "Jos");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn path_keys_whole_components() {
+    use qp_trie::path::PathKey;
+    use std::path::Path;
+
+    let mut settings = Trie::new();
+    settings.insert(PathKey::from("/usr/lib"), 1);
+    settings.insert(PathKey::from("/usr/libexec/"), 2);
+    settings.insert_path("src/bin", 3);
+
+    assert_eq!(settings.get_path("/usr//lib/"), Some(&1));
+    assert_eq!(settings.get_path("/usr/./lib"), Some(&1));
+    assert!(settings.contains_path(Path::new("src/bin")));
+    assert!(!settings.contains_path("/usr/li"));
+
+    assert_eq!(
+        settings
+            .iter_prefix_path("/usr/lib")
+            .map(|(_, &v)| v)
+            .collect::<Vec<_>>(),
+        [1]
+    );
+    assert_eq!(settings.iter_prefix_path("/usr").count(), 2);
+    assert_eq!(settings.iter_prefix_path("/usr/li").count(), 0);
+
+    assert_eq!(
+        settings.longest_common_prefix_path("/usr/lib64"),
+        Path::new("/usr")
+    );
+    assert_eq!(settings.longest_common_prefix_path("/opt"), Path::new("/"));
+    assert_eq!(
+        settings.longest_common_prefix_path("src/lib"),
+        Path::new("src")
+    );
+    assert_eq!(settings.longest_common_prefix_path("lib"), Path::new(""));
+    assert_eq!(
+        settings.longest_common_prefix(&b"/usr/lib/gcc/"[..]),
+        Path::new("/usr/lib")
+    );
+
+    assert_eq!(
+        settings
+            .iter_ancestors(Path::new("/usr/lib/gcc/x86_64"))
+            .map(|(_, &v)| v)
+            .collect::<Vec<_>>(),
+        [1]
+    );
+    assert_eq!(
+        settings
+            .deepest_ancestor("src/bin/main.rs")
+            .map(|(_, &v)| v),
+        Some(3)
+    );
+    assert_eq!(settings.deepest_ancestor("src/lib.rs"), None);
+
+    assert_eq!(settings.remove_prefix_path("/usr").count(), 2);
+    assert_eq!(settings.remove_path("src/bin/"), Some(3));
+    assert!(settings.is_empty());
+}
+
+#[test]
+fn tuple_keys_whole_elements() {
+    use qp_trie::tuple::{unpack, TupleError, TupleKey};
+
+    let mut rows = Trie::new();
+    rows.insert(tuple!(1u32, "orders", -5i64), "a");
+    rows.insert(tuple!(1u32, "orders", 300u16), "b");
+    rows.insert(tuple!(1u32, "orders\0items", 0u8), "c");
+    rows.insert(tuple!(1u32, "order", true), "d");
+    rows.insert(tuple!(2u32, &b"\x00\xff"[..]), "e");
+
+    assert_eq!(
+        rows.iter_prefix(&tuple!(1u32, "orders"))
+            .map(|(_, &v)| v)
+            .collect::<Vec<_>>(),
+        ["a", "b"]
+    );
+    assert_eq!(rows.iter_prefix(&tuple!(1u32)).count(), 4);
+    assert_eq!(rows.get(&tuple!(1u64, "orders", 300i32)), Some(&"b"));
+
+    let common = rows.longest_common_prefix(&tuple!(1u32, "orders", 7u8));
+    assert_eq!(
+        unpack::<(u32, String)>(common),
+        Ok((1, "orders".to_owned()))
+    );
+    let common = rows.longest_common_prefix(&tuple!(1u32, "ord"));
+    assert_eq!(common, tuple!(1u32).as_bytes());
+    assert!(rows.longest_common_prefix(&tuple!(3u32)).is_empty());
+
+    let key = tuple!(2u32, &b"\x00\xff"[..]);
+    assert_eq!(key.unpack::<(u8, Vec<u8>)>(), Ok((2, vec![0, 0xff])));
+    assert_eq!(
+        TupleKey::from_bytes(key.clone().into_bytes()),
+        Ok(key.clone())
+    );
+    assert_eq!(format!("{:?}", key), "TupleKey(2, [0, 255])");
+
+    assert_eq!(key.unpack::<(u8,)>(), Err(TupleError::TrailingElements));
+    assert_eq!(
+        key.unpack::<(u8, Vec<u8>, u8)>(),
+        Err(TupleError::Truncated)
+    );
+    assert_eq!(
+        key.unpack::<(u8, String)>(),
+        Err(TupleError::UnexpectedType(0x01))
+    );
+    assert_eq!(tuple!(-1i8).unpack::<(u8,)>(), Err(TupleError::OutOfRange));
+    assert_eq!(
+        tuple!(u64::MAX).unpack::<(i64,)>(),
+        Err(TupleError::OutOfRange)
+    );
+    assert_eq!(
+        TupleKey::from_bytes(vec![0x02, b'a', 0x00]),
+        Err(TupleError::Truncated)
+    );
+    assert_eq!(
+        TupleKey::from_bytes(vec![0x02, b'a', 0x00, b'b']),
+        Err(TupleError::InvalidEscape(b'b'))
+    );
+
+    let mut ints = [i64::MIN, -256, -255, -1, 0, 1, 255, 256, i64::MAX]
+        .iter()
+        .map(|&n| (TupleKey::pack(n), n))
+        .collect::<Vec<_>>();
+    ints.reverse();
+    ints.sort();
+    assert!(ints.windows(2).all(|w| w[0].1 < w[1].1));
+    assert!(ints
+        .iter()
+        .all(|(key, n)| key.unpack::<(i64,)>() == Ok((*n,))));
+}